@@ -6,13 +6,15 @@ use libc::timespec;
 use std::ffi::CString;
 use std::fs::File;
 use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::RawFd;
+use std::path::Path;
 use std::time::SystemTime;
 
 pub fn fdutimensat(
     file: Option<&File>,
     dir: RawFd,
-    file_name: Option<&str>,
+    file_name: Option<&Path>,
     ts: &[timespec; 2],
     atflag: i32,
 ) -> std::io::Result<()> {
@@ -23,10 +25,10 @@ pub fn fdutimensat(
         -1
     };
 
-    if let Some(file_name_str) = file_name {
+    if let Some(file_name) = file_name {
         if file.is_none() || (result == -1 && unsafe { *libc::__errno_location() } == libc::ENOSYS)
         {
-            let file_cstr = CString::new(file_name_str).map_err(|_| {
+            let file_cstr = CString::new(file_name.as_os_str().as_bytes()).map_err(|_| {
                 std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file name")
             })?;
 
@@ -83,7 +85,7 @@ mod tests {
         let now = SystemTime::now();
         let ts = [timespec_from_systemtime(now), timespec_from_systemtime(now)];
 
-        let result = fdutimensat(None, libc::AT_FDCWD, Some(temp_file), &ts, 0);
+        let result = fdutimensat(None, libc::AT_FDCWD, Some(Path::new(temp_file)), &ts, 0);
         assert!(result.is_ok());
 
         std::fs::remove_file(temp_file).unwrap();
@@ -99,7 +101,13 @@ mod tests {
         // Force ENOSYS error
         unsafe { *libc::__errno_location() = libc::ENOSYS };
 
-        let result = fdutimensat(Some(&file), libc::AT_FDCWD, Some(temp_file), &ts, 0);
+        let result = fdutimensat(
+            Some(&file),
+            libc::AT_FDCWD,
+            Some(Path::new(temp_file)),
+            &ts,
+            0,
+        );
         assert!(result.is_ok());
 
         std::fs::remove_file(temp_file).unwrap();
@@ -110,7 +118,7 @@ mod tests {
         let now = SystemTime::now();
         let ts = [timespec_from_systemtime(now), timespec_from_systemtime(now)];
 
-        let result = fdutimensat(None, libc::AT_FDCWD, Some("\0"), &ts, 0);
+        let result = fdutimensat(None, libc::AT_FDCWD, Some(Path::new("\0")), &ts, 0);
         assert!(result.is_err());
     }
 
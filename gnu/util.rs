@@ -3,16 +3,20 @@
 // # SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::io;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 
-pub fn validate_and_sanitize_path(path_str: &str) -> io::Result<PathBuf> {
+pub fn validate_and_sanitize_path<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    let path_bytes = path.as_os_str().as_bytes();
+
     // 检查空路径
-    if path_str.is_empty() {
+    if path_bytes.is_empty() {
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "Empty path"));
     }
 
     // 检查是否包含换行符（可能用于命令注入）
-    if path_str.contains('\n') {
+    if path_bytes.contains(&b'\n') {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             "Path contains newline",
@@ -20,16 +24,13 @@ pub fn validate_and_sanitize_path(path_str: &str) -> io::Result<PathBuf> {
     }
 
     // 检查是否包含空字节（null byte）
-    if path_str.contains('\0') {
+    if path_bytes.contains(&0) {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             "Path contains null byte",
         ));
     }
 
-    // 创建路径对象
-    let path = Path::new(path_str);
-
     // 检查路径是否包含危险的遍历序列
     let path_components: Vec<_> = path.components().collect();
 
@@ -56,7 +57,7 @@ pub fn validate_and_sanitize_path(path_str: &str) -> io::Result<PathBuf> {
     let canonical_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
 
     // 额外的安全检查：确保路径不会太长
-    if canonical_path.to_string_lossy().len() > 4096 {
+    if canonical_path.as_os_str().len() > 4096 {
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "Path too long"));
     }
 
@@ -6,19 +6,21 @@
 
 use lazy_static::lazy_static;
 use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Component, Path};
 use std::sync::Mutex;
 
 lazy_static! {
-    static ref PREFIX_TABLES: [Mutex<HashSet<String>>; 2] =
+    static ref PREFIX_TABLES: [Mutex<HashSet<Vec<u8>>>; 2] =
         [Mutex::new(HashSet::new()), Mutex::new(HashSet::new()),];
 }
 
-fn is_slash(c: char) -> bool {
-    c == '/'
+fn is_slash(c: u8) -> bool {
+    c == b'/'
 }
 
-fn file_system_prefix_len(file_name: &str) -> usize {
+fn file_system_prefix_len(file_name: &OsStr) -> usize {
     let path = Path::new(file_name);
     let mut len = 0;
 
@@ -36,27 +38,28 @@ fn file_system_prefix_len(file_name: &str) -> usize {
     len
 }
 
-fn compute_safe_prefix_len(file_name: &str, initial: usize) -> usize {
+// 文件名按字节处理，不要求是合法的 UTF-8
+fn compute_safe_prefix_len(file_name: &[u8], initial: usize) -> usize {
     let mut max_len = initial;
-    let chars: Vec<char> = file_name[initial..].chars().collect();
+    let bytes = &file_name[initial..];
     let mut i = 0;
 
-    while i < chars.len() {
-        if i + 1 < chars.len() && chars[i] == '.' && chars[i + 1] == '.' {
+    while i < bytes.len() {
+        if i + 1 < bytes.len() && bytes[i] == b'.' && bytes[i + 1] == b'.' {
             let next = i + 2;
             max_len = initial + next;
 
-            if next < chars.len() && is_slash(chars[next]) {
+            if next < bytes.len() && is_slash(bytes[next]) {
                 max_len += 1;
                 i = next + 1;
             } else {
                 i = next;
             }
         } else {
-            while i < chars.len() && !is_slash(chars[i]) {
+            while i < bytes.len() && !is_slash(bytes[i]) {
                 i += 1;
             }
-            while i < chars.len() && is_slash(chars[i]) {
+            while i < bytes.len() && is_slash(bytes[i]) {
                 i += 1;
             }
         }
@@ -64,7 +67,7 @@ fn compute_safe_prefix_len(file_name: &str, initial: usize) -> usize {
 
     let mut safe_len = max_len;
     while safe_len < file_name.len() {
-        let c = file_name.as_bytes()[safe_len];
+        let c = file_name[safe_len];
         if c != b'/' && c != b'\\' {
             break;
         }
@@ -78,17 +81,18 @@ pub fn removed_prefixes_p() -> bool {
     !PREFIX_TABLES[0].lock().unwrap().is_empty() || !PREFIX_TABLES[1].lock().unwrap().is_empty()
 }
 
-pub fn safer_name_suffix(file_name: &str, link_target: bool, absolute_names: bool) -> String {
+pub fn safer_name_suffix(file_name: &OsStr, link_target: bool, absolute_names: bool) -> OsString {
+    let bytes = file_name.as_bytes();
     let prefix_len = if absolute_names {
         0
     } else {
         let fs_prefix = file_system_prefix_len(file_name);
-        let computed = compute_safe_prefix_len(file_name, fs_prefix);
+        let computed = compute_safe_prefix_len(bytes, fs_prefix);
         let mut final_len = computed;
 
         // Skip leading slashes after prefix
-        while final_len < file_name.len() {
-            let c = file_name.as_bytes()[final_len];
+        while final_len < bytes.len() {
+            let c = bytes[final_len];
             if c != b'/' && c != b'\\' {
                 break;
             }
@@ -99,12 +103,12 @@ pub fn safer_name_suffix(file_name: &str, link_target: bool, absolute_names: boo
 
     // Handle prefix insertion and warnings
     if prefix_len > 0 {
-        let prefix = &file_name[..prefix_len];
+        let prefix = &bytes[..prefix_len];
         let table_idx = link_target as usize;
         let inserted = PREFIX_TABLES[table_idx]
             .lock()
             .unwrap()
-            .insert(prefix.to_string());
+            .insert(prefix.to_vec());
 
         if inserted {
             let msg = if link_target {
@@ -112,15 +116,15 @@ pub fn safer_name_suffix(file_name: &str, link_target: bool, absolute_names: boo
             } else {
                 "Removing leading `{}' from member names"
             };
-            eprintln!("{} {}", msg, prefix);
+            eprintln!("{} {}", msg, String::from_utf8_lossy(prefix));
         }
     }
 
     // Get the suffix part
-    let suffix = &file_name[prefix_len..];
+    let suffix = &bytes[prefix_len..];
     if suffix.is_empty() {
-        ".".to_string()
+        OsString::from(".")
     } else {
-        suffix.replace(|c| is_slash(c), "/")
+        OsString::from_vec(suffix.to_vec())
     }
 }
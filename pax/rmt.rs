@@ -61,15 +61,15 @@ pub fn rmtopen(dev_name: &str, oflag: i32, mode: u32, command: &str) -> io::Resu
         let mut options = OpenOptions::new();
 
         // 根据 oflag 设置打开模式
-        if oflag != 0 {
-            options.read(true);
-        }
-        if oflag & libc::O_WRONLY != 0 {
-            options.write(true);
-        }
+        let accmode = oflag & libc::O_ACCMODE;
+        options.read(accmode == libc::O_RDONLY || accmode == libc::O_RDWR);
+        options.write(accmode == libc::O_WRONLY || accmode == libc::O_RDWR);
         if oflag & libc::O_CREAT != 0 {
             options.create(true);
         }
+        if oflag & libc::O_TRUNC != 0 {
+            options.truncate(true);
+        }
         options.mode(mode);
 
         // 打开文件并返回 File 对象
@@ -169,16 +169,9 @@ pub fn rmtread(file: &File, buffer: &mut [u8], length: usize) -> usize {
     let fd: i32 = file.as_raw_fd();
     if isrmt(file) {
         rmt_read__((fd - REM_BIAS) as usize, buffer, length)
-    } else if fd == libc::STDIN_FILENO {
-        let mut stdin = io::stdin();
-        match stdin.read(&mut buffer[..length]) {
-            Ok(read_bytes) => read_bytes,
-            Err(e) => {
-                eprintln!("Error reading from stdin: {:?}", e);
-                0
-            }
-        }
     } else {
+        // 标准输入也直接读文件描述符，不经过 io::stdin() 的缓冲，
+        // 否则预读的数据会使 lseek 后的位置不对
         let mut file_ref = file;
         match (&mut file_ref).read(&mut buffer[..length]) {
             Ok(read_bytes) => read_bytes,
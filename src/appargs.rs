@@ -4,6 +4,10 @@
 
 use crate::externs::*;
 use crate::initramfs::*;
+use crate::modechange::ModeChange;
+use crate::pattern::Pattern;
+use crate::predicate::Predicate;
+use crate::transform::Transform;
 
 // static DOC: &str = "GNU `cpio' copies files to and from archives\n\
 // \n\
@@ -15,6 +19,7 @@ use crate::initramfs::*;
 //  # Copy files named in name-list to destination-directory\n\
 //  cpio -p destination-directory < name-list\n";
 
+use std::ffi::OsString;
 use std::fs::File;
 use std::io;
 use std::sync::MutexGuard;
@@ -70,10 +75,10 @@ pub struct AppArgs {
     create_dir_flag: bool,
     change_directory_option: Option<String>,
     metadata_type: MetadataTypes,
-    pattern_file_name: Option<String>,
     archive_name: Option<String>,
     copy_matching_files: bool,
     copy_function: Option<CopyFunctionFn>,
+    copy_mode: Option<CopyMode>,
     input_archive_name: Option<String>,
     link_flag: bool,
     //xstat: Option<Xstat>,
@@ -111,14 +116,37 @@ pub struct AppArgs {
     dot_flag: bool,
     warn_option: i32,
     sparse_flag: bool,
+    sync_flag: bool,
+    transactional_flag: bool,
+    seek_remote_flag: bool,
+    resume_journal: Option<String>,
+    build_index: Option<String>,
+    index_file: Option<String>,
+    from_charset: Option<String>,
+    to_charset: Option<String>,
+    reflink_mode: ReflinkMode,
+    link_dest: Option<String>,
+    jobs: usize,
+    compress_program: Option<String>,
     force_local_option: bool,
     to_stdout_option: bool,
     // debug_flag: bool,
     numeric_uid: bool,
 
-    directory_name: Option<String>,
+    directory_name: Option<OsString>,
     num_patterns: i32,
-    save_patterns: Vec<String>,
+    save_patterns: Vec<Pattern>,
+    selection: Option<Predicate>,
+    transforms: Vec<Transform>,
+    strip_components: usize,
+    name_prefix: Option<OsString>,
+    rootless_flag: bool,
+    mode_changes: Vec<ModeChange>,
+    mtime_override: Option<i64>,
+    clamp_mtime_flag: bool,
+    owner_name: Option<String>,
+    group_name: Option<String>,
+    list_directives_flag: bool,
 }
 impl AppArgs {
     pub fn new() -> Self {
@@ -133,10 +161,10 @@ impl AppArgs {
             create_dir_flag: false,
             change_directory_option: None,
             metadata_type: MetadataTypes::TypeNone,
-            pattern_file_name: None,
             archive_name: None,
             copy_matching_files: true,
             copy_function: None,
+            copy_mode: None,
             input_archive_name: None,
             link_flag: false,
             //            xstat: None,
@@ -167,6 +195,18 @@ impl AppArgs {
             dot_flag: false,
             warn_option: 0,
             sparse_flag: false,
+            sync_flag: false,
+            transactional_flag: false,
+            seek_remote_flag: false,
+            resume_journal: None,
+            build_index: None,
+            index_file: None,
+            from_charset: None,
+            to_charset: None,
+            reflink_mode: ReflinkMode::Never,
+            link_dest: None,
+            jobs: 1,
+            compress_program: None,
             force_local_option: false,
             to_stdout_option: false,
             //            debug_flag: false,
@@ -175,19 +215,107 @@ impl AppArgs {
             //            archive_des: 0,
             num_patterns: 0,
             save_patterns: vec![],
+            selection: None,
+            transforms: vec![],
+            strip_components: 0,
+            name_prefix: None,
+            rootless_flag: false,
+            mode_changes: vec![],
+            mtime_override: None,
+            clamp_mtime_flag: false,
+            owner_name: None,
+            group_name: None,
+            list_directives_flag: false,
         }
     }
 }
 
 pub static APPARGS: OnceLock<Mutex<AppArgs>> = OnceLock::new();
 
-pub fn get_save_patterns() -> Vec<String> {
+pub fn get_save_patterns() -> Vec<Pattern> {
     APPARGS.get().unwrap().lock().unwrap().save_patterns.clone()
 }
-pub fn set_save_patterns(value: Vec<String>) {
+pub fn set_save_patterns(value: Vec<Pattern>) {
     APPARGS.get().unwrap().lock().unwrap().save_patterns = value;
 }
 
+pub fn get_selection() -> Option<Predicate> {
+    APPARGS.get().unwrap().lock().unwrap().selection.clone()
+}
+pub fn set_selection(value: Option<Predicate>) {
+    APPARGS.get().unwrap().lock().unwrap().selection = value;
+}
+
+pub fn get_transforms() -> Vec<Transform> {
+    APPARGS.get().unwrap().lock().unwrap().transforms.clone()
+}
+pub fn set_transforms(value: Vec<Transform>) {
+    APPARGS.get().unwrap().lock().unwrap().transforms = value;
+}
+
+pub fn get_strip_components() -> usize {
+    APPARGS.get().unwrap().lock().unwrap().strip_components
+}
+pub fn set_strip_components(value: usize) {
+    APPARGS.get().unwrap().lock().unwrap().strip_components = value;
+}
+
+pub fn get_name_prefix() -> Option<OsString> {
+    APPARGS.get().unwrap().lock().unwrap().name_prefix.clone()
+}
+pub fn set_name_prefix(value: Option<OsString>) {
+    APPARGS.get().unwrap().lock().unwrap().name_prefix = value;
+}
+
+pub fn get_rootless_flag() -> bool {
+    APPARGS.get().unwrap().lock().unwrap().rootless_flag
+}
+pub fn set_rootless_flag(value: bool) {
+    APPARGS.get().unwrap().lock().unwrap().rootless_flag = value;
+}
+
+pub fn get_mode_changes() -> Vec<ModeChange> {
+    APPARGS.get().unwrap().lock().unwrap().mode_changes.clone()
+}
+pub fn set_mode_changes(value: Vec<ModeChange>) {
+    APPARGS.get().unwrap().lock().unwrap().mode_changes = value;
+}
+
+pub fn get_mtime_override() -> Option<i64> {
+    APPARGS.get().unwrap().lock().unwrap().mtime_override
+}
+pub fn set_mtime_override(value: Option<i64>) {
+    APPARGS.get().unwrap().lock().unwrap().mtime_override = value;
+}
+
+pub fn get_clamp_mtime_flag() -> bool {
+    APPARGS.get().unwrap().lock().unwrap().clamp_mtime_flag
+}
+pub fn set_clamp_mtime_flag(value: bool) {
+    APPARGS.get().unwrap().lock().unwrap().clamp_mtime_flag = value;
+}
+
+pub fn get_owner_name() -> Option<String> {
+    APPARGS.get().unwrap().lock().unwrap().owner_name.clone()
+}
+pub fn set_owner_name(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().owner_name = value;
+}
+
+pub fn get_group_name() -> Option<String> {
+    APPARGS.get().unwrap().lock().unwrap().group_name.clone()
+}
+pub fn set_group_name(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().group_name = value;
+}
+
+pub fn get_list_directives_flag() -> bool {
+    APPARGS.get().unwrap().lock().unwrap().list_directives_flag
+}
+pub fn set_list_directives_flag(value: bool) {
+    APPARGS.get().unwrap().lock().unwrap().list_directives_flag = value;
+}
+
 pub fn get_num_patterns() -> i32 {
     APPARGS.get().unwrap().lock().unwrap().num_patterns
 }
@@ -205,10 +333,10 @@ pub fn set_num_patterns(value: i32) {
     }
 }
 
-pub fn set_directory_name(value: Option<String>) {
+pub fn set_directory_name(value: Option<OsString>) {
     APPARGS.get().unwrap().lock().unwrap().directory_name = value;
 }
-pub fn get_directory_name() -> Option<String> {
+pub fn get_directory_name() -> Option<OsString> {
     APPARGS
         .get()
         .unwrap()
@@ -322,20 +450,6 @@ pub fn set_metadata_type(value: MetadataTypes) {
     APPARGS.get().unwrap().lock().unwrap().metadata_type = value;
 }
 
-pub fn get_pattern_file_name() -> Option<String> {
-    APPARGS
-        .get()
-        .unwrap()
-        .lock()
-        .unwrap()
-        .pattern_file_name
-        .clone()
-}
-
-pub fn set_pattern_file_name(value: Option<String>) {
-    APPARGS.get().unwrap().lock().unwrap().pattern_file_name = value;
-}
-
 pub fn get_archive_name() -> Option<String> {
     APPARGS.get().unwrap().lock().unwrap().archive_name.clone()
 }
@@ -360,6 +474,14 @@ pub fn set_copy_function(value: CopyFunctionFn) {
     APPARGS.get().unwrap().lock().unwrap().copy_function = Some(value);
 }
 
+pub fn get_copy_mode() -> Option<CopyMode> {
+    APPARGS.get().unwrap().lock().unwrap().copy_mode
+}
+
+pub fn set_copy_mode(value: CopyMode) {
+    APPARGS.get().unwrap().lock().unwrap().copy_mode = Some(value);
+}
+
 pub fn get_input_archive_name() -> Option<String> {
     APPARGS
         .get()
@@ -607,6 +729,114 @@ pub fn get_sparse_flag() -> bool {
 pub fn set_sparse_flag(value: bool) {
     APPARGS.get().unwrap().lock().unwrap().sparse_flag = value;
 }
+
+pub fn get_sync_flag() -> bool {
+    APPARGS.get().unwrap().lock().unwrap().sync_flag
+}
+
+pub fn set_sync_flag(value: bool) {
+    APPARGS.get().unwrap().lock().unwrap().sync_flag = value;
+}
+
+pub fn get_transactional_flag() -> bool {
+    APPARGS.get().unwrap().lock().unwrap().transactional_flag
+}
+
+pub fn set_transactional_flag(value: bool) {
+    APPARGS.get().unwrap().lock().unwrap().transactional_flag = value;
+}
+
+pub fn get_seek_remote_flag() -> bool {
+    APPARGS.get().unwrap().lock().unwrap().seek_remote_flag
+}
+
+pub fn set_seek_remote_flag(value: bool) {
+    APPARGS.get().unwrap().lock().unwrap().seek_remote_flag = value;
+}
+
+pub fn get_resume_journal() -> Option<String> {
+    APPARGS
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .resume_journal
+        .clone()
+}
+
+pub fn set_resume_journal(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().resume_journal = value;
+}
+
+pub fn get_build_index() -> Option<String> {
+    APPARGS.get().unwrap().lock().unwrap().build_index.clone()
+}
+
+pub fn set_build_index(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().build_index = value;
+}
+
+pub fn get_index_file() -> Option<String> {
+    APPARGS.get().unwrap().lock().unwrap().index_file.clone()
+}
+
+pub fn set_index_file(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().index_file = value;
+}
+
+pub fn get_from_charset() -> Option<String> {
+    APPARGS.get().unwrap().lock().unwrap().from_charset.clone()
+}
+
+pub fn set_from_charset(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().from_charset = value;
+}
+
+pub fn get_to_charset() -> Option<String> {
+    APPARGS.get().unwrap().lock().unwrap().to_charset.clone()
+}
+
+pub fn set_to_charset(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().to_charset = value;
+}
+
+pub fn get_reflink_mode() -> ReflinkMode {
+    APPARGS.get().unwrap().lock().unwrap().reflink_mode
+}
+
+pub fn set_reflink_mode(value: ReflinkMode) {
+    APPARGS.get().unwrap().lock().unwrap().reflink_mode = value;
+}
+
+pub fn get_link_dest() -> Option<String> {
+    APPARGS.get().unwrap().lock().unwrap().link_dest.clone()
+}
+
+pub fn set_link_dest(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().link_dest = value;
+}
+
+pub fn get_jobs() -> usize {
+    APPARGS.get().unwrap().lock().unwrap().jobs
+}
+
+pub fn set_jobs(value: usize) {
+    APPARGS.get().unwrap().lock().unwrap().jobs = value;
+}
+
+pub fn get_compress_program() -> Option<String> {
+    APPARGS
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .compress_program
+        .clone()
+}
+
+pub fn set_compress_program(value: Option<String>) {
+    APPARGS.get().unwrap().lock().unwrap().compress_program = value;
+}
 #[allow(dead_code)]
 pub fn get_force_local_option() -> bool {
     APPARGS.get().unwrap().lock().unwrap().force_local_option
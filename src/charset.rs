@@ -0,0 +1,187 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 文件名的字符集转换（--from-charset / --to-charset）。
+// 成员名和符号链接目标在写入归档或从归档中取出时用 iconv 从 FROM 转换为 TO，
+// 不能在 TO 中表示的名字给出错误并跳过该成员。
+
+use std::ffi::{CString, OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::ptr;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use pax::paxexit_status::*;
+use pax::paxlib::*;
+
+use crate::util::{quote_name, warn_msg};
+
+pub const DEFAULT_CHARSET: &str = "UTF-8";
+
+pub struct Transcoder {
+    cd: libc::iconv_t,
+    from: String,
+    to: String,
+}
+
+// iconv_t 只在持有 CHARSET 锁时使用
+unsafe impl Send for Transcoder {}
+
+impl Transcoder {
+    pub fn new(from: &str, to: &str) -> Option<Transcoder> {
+        let from_c = CString::new(from).ok()?;
+        let to_c = CString::new(to).ok()?;
+        let cd = unsafe { libc::iconv_open(to_c.as_ptr(), from_c.as_ptr()) };
+        if cd as isize == -1 {
+            return None;
+        }
+        Some(Transcoder {
+            cd,
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+
+    // 转换一个名字。名字中有不能转换的字节，或者转换结果中含有 NUL 时返回 None
+    pub fn convert(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let mut output = vec![0u8; input.len() * 4 + 16];
+        let mut in_ptr = input.as_ptr() as *mut libc::c_char;
+        let mut in_left = input.len();
+        let mut done = 0;
+        let mut flushing = false;
+
+        unsafe {
+            libc::iconv(
+                self.cd,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+        }
+        loop {
+            let mut out_ptr = output[done..].as_mut_ptr() as *mut libc::c_char;
+            let mut out_left = output.len() - done;
+            let ret = unsafe {
+                if !flushing {
+                    libc::iconv(
+                        self.cd,
+                        &mut in_ptr,
+                        &mut in_left,
+                        &mut out_ptr,
+                        &mut out_left,
+                    )
+                } else {
+                    // 输出恢复初始移位状态所需的字节
+                    libc::iconv(
+                        self.cd,
+                        ptr::null_mut(),
+                        ptr::null_mut(),
+                        &mut out_ptr,
+                        &mut out_left,
+                    )
+                }
+            };
+            done = output.len() - out_left;
+            if ret != usize::MAX {
+                if flushing {
+                    break;
+                }
+                flushing = in_left == 0;
+                continue;
+            }
+            if std::io::Error::last_os_error().raw_os_error() != Some(libc::E2BIG) {
+                return None;
+            }
+            output.resize(output.len() * 2, 0);
+        }
+        output.truncate(done);
+        if output.contains(&0) {
+            return None;
+        }
+        Some(output)
+    }
+}
+
+impl Drop for Transcoder {
+    fn drop(&mut self) {
+        unsafe {
+            libc::iconv_close(self.cd);
+        }
+    }
+}
+
+lazy_static! {
+    static ref CHARSET: Mutex<Option<Transcoder>> = Mutex::new(None);
+}
+
+pub fn charset_init(from: &str, to: &str) -> bool {
+    match Transcoder::new(from, to) {
+        Some(transcoder) => {
+            *CHARSET.lock().unwrap() = Some(transcoder);
+            true
+        }
+        None => false,
+    }
+}
+
+// 不给出错误信息的转换，没有指定字符集时按原样返回
+pub fn charset_transcode(name: &OsStr) -> Option<OsString> {
+    match CHARSET.lock().unwrap().as_ref() {
+        Some(transcoder) => transcoder.convert(name.as_bytes()).map(OsString::from_vec),
+        None => Some(name.to_os_string()),
+    }
+}
+
+// 不能转换时给出警告并返回 None，进程最后以失败状态退出
+pub fn charset_convert_name(name: &OsStr) -> Option<OsString> {
+    let guard = CHARSET.lock().unwrap();
+    let Some(transcoder) = guard.as_ref() else {
+        return Some(name.to_os_string());
+    };
+    match transcoder.convert(name.as_bytes()) {
+        Some(converted) => Some(OsString::from_vec(converted)),
+        None => {
+            warn_msg(format_args!(
+                "{}: cannot convert file name from {} to {}",
+                quote_name(name),
+                transcoder.from,
+                transcoder.to
+            ));
+            set_exit_status(PAXEXIT_FAILURE);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcode_gb18030() {
+        let to_utf8 = Transcoder::new("GB18030", "UTF-8").unwrap();
+        let from_utf8 = Transcoder::new("UTF-8", "GB18030").unwrap();
+
+        let name = b"dir/\xc4\xe3\xba\xc3.txt";
+        let utf8 = to_utf8.convert(name).unwrap();
+        assert_eq!(utf8, "dir/你好.txt".as_bytes());
+        assert_eq!(from_utf8.convert(&utf8).unwrap(), name);
+        assert_eq!(to_utf8.convert(b"").unwrap(), b"");
+    }
+
+    #[test]
+    fn test_transcode_unrepresentable() {
+        let to_latin1 = Transcoder::new("UTF-8", "ISO-8859-1").unwrap();
+        assert_eq!(to_latin1.convert("vmé".as_bytes()).unwrap(), b"vm\xe9");
+        assert!(to_latin1.convert("你好".as_bytes()).is_none());
+        // 不完整的多字节序列
+        assert!(to_latin1.convert(b"a\xc3").is_none());
+        // 结果中不能有 NUL
+        let to_utf16 = Transcoder::new("UTF-8", "UTF-16LE").unwrap();
+        assert!(to_utf16.convert(b"a").is_none());
+        assert!(Transcoder::new("UTF-8", "NO-SUCH-CHARSET").is_none());
+    }
+}
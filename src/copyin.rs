@@ -12,11 +12,12 @@
     unused_mut
 )]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CString, OsStr, OsString};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Write};
-use std::io::{BufRead, BufReader};
 use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
 use std::str;
 use std::sync::{Mutex, MutexGuard};
@@ -25,7 +26,7 @@ use std::time::{Duration, SystemTime};
 use chrono::{TimeZone, Utc};
 use lazy_static::lazy_static;
 
-use libc::{dev_t, fnmatch, lchown, symlink, timespec, umask, unlink};
+use libc::{dev_t, lchown, symlink, timespec, umask};
 use nix::sys::stat::fstat;
 use nix::unistd::{Gid, Uid};
 
@@ -34,6 +35,7 @@ use pax::paxlib::PAXEXIT_FAILURE;
 use pax::rmt::*;
 
 use crate::appargs::*;
+use crate::charset::*;
 use crate::cpiohdr::*;
 use crate::dstring::*;
 use crate::externs::*;
@@ -42,12 +44,19 @@ use crate::filetype::*;
 use crate::filetype::{CP_IFBLK, CP_IFCHR, CP_IFIFO, CP_IFMT, CP_IFSOCK};
 use crate::global::*;
 use crate::idcache::*;
+use crate::idmap::{map_gid, map_uid};
+use crate::index::*;
+use crate::pattern::*;
+use crate::predicate::predicate_selected;
+use crate::resume::*;
+use crate::rootless::warn_symlink_owner;
 use crate::tar::*;
+use crate::transaction::*;
+use crate::transform::*;
 use crate::util::*;
 
 use gnu::error::*;
 use gnu::gettime::*;
-use gnu::quotearg::*;
 
 static mut CURRENT_TIME: timespec = timespec {
     tv_sec: 0,
@@ -66,8 +75,8 @@ struct DelayedLinkValue {
     pub uid: u32,   // uid_t
     pub gid: u32,   // gid_t
     pub mtime: i64, // time_t, representing seconds since epoch
-    pub source: String,
-    pub target: String,
+    pub source: OsString,
+    pub target: OsString,
 }
 
 struct DelayedLink {
@@ -101,9 +110,9 @@ lazy_static! {
 
 fn query_rename(
     file_hdr: &mut CpioFileStat,
-    tty_in: &mut File,
-    tty_out: &mut File,
-    rename_in: &mut File,
+    tty_in: Option<&mut File>,
+    tty_out: Option<&mut File>,
+    rename_in: Option<&mut File>,
 ) -> i32 {
     let mut new_name_guard = NEW_NAME.lock().unwrap();
     let mut initialized_guard = INITIALIZED_NEW_NAME.lock().unwrap();
@@ -115,36 +124,41 @@ fn query_rename(
 
     let c_name = file_hdr.get_c_name();
 
-    let str_res = if get_rename_flag() {
-        write!(tty_out, "rename {} -> ", c_name).unwrap();
-        tty_out.flush().unwrap();
-        ds_fgets(tty_in, new_name_guard.as_mut().unwrap())
-    } else {
-        ds_fgetstr(rename_in, new_name_guard.as_mut().unwrap(), b'\n')
+    // 给出 --rename-batch-file 时只打开了批处理文件，没有打开终端
+    let str_res = match (rename_in, tty_in, tty_out) {
+        (Some(rename_in), _, _) => ds_fgetstr(rename_in, new_name_guard.as_mut().unwrap(), b'\n'),
+        (None, Some(tty_in), Some(tty_out)) => {
+            write!(tty_out, "rename {} -> ", quote_name(&c_name)).unwrap();
+            tty_out.flush().unwrap();
+            ds_fgets(tty_in, new_name_guard.as_mut().unwrap())
+        }
+        _ => None,
     };
 
     if str_res.is_none() || str_res.as_ref().map_or(true, |s| s.is_empty()) {
         -1
     } else {
         let name = new_name_guard
-            .as_mut()
-            .map(|s| String::from_utf8(s.ds_string.clone()).unwrap())
+            .as_ref()
+            .map(|s| OsString::from_vec(s.ds_string[..s.ds_idx].to_vec()))
             .unwrap();
-        cpio_set_c_name(file_hdr, name.as_str());
+        cpio_set_c_name(file_hdr, &name);
         0
     }
 }
 
 fn tape_skip_padding(input_tape: &mut MutexGuard<TapeInput>, in_file_des: &mut File, offset: u64) {
     let pad: u64 = match get_archive_format() {
-        ArchiveFormat::Crcascii | ArchiveFormat::Newascii => (4 - (offset % 4)) % 4,
+        ArchiveFormat::Crcascii | ArchiveFormat::Newascii | ArchiveFormat::Newxascii => {
+            (4 - (offset % 4)) % 4
+        }
         ArchiveFormat::Binary | ArchiveFormat::Hpbinary => (2 - (offset % 2)) % 2,
         ArchiveFormat::Tar | ArchiveFormat::Ustar => (512 - (offset % 512)) % 512,
         _ => 0,
     };
 
     if pad != 0 {
-        tape_toss_input(input_tape, in_file_des, pad as i32);
+        tape_toss_input(input_tape, in_file_des, pad as u64);
     }
 }
 
@@ -152,7 +166,7 @@ fn get_link_name(
     input_tape: &mut MutexGuard<TapeInput>,
     file_hdr: &mut CpioFileStat,
     in_file_des: &mut File,
-) -> Option<String> {
+) -> Option<OsString> {
     // 放宽文件名长度验证条件，使用更合理的上限
     const MAX_LINK_NAME_SIZE: i64 = 1024 * 1024; // 1MB 作为合理的上限
 
@@ -162,7 +176,7 @@ fn get_link_name(
             0,
             format_args!(
                 "{}: stored filename length is out of range",
-                file_hdr.get_c_name()
+                quote_name(&file_hdr.get_c_name())
             ),
         );
         None
@@ -174,14 +188,15 @@ fn get_link_name(
         // 跳过padding
         tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
 
-        // 直接使用读取的字节转换为字符串，移除末尾的null字符
+        // 链接目标按原样保留，移除末尾的null字符
         let clean_name = link_name
             .iter()
             .take_while(|&&x| x != 0)
             .cloned()
             .collect::<Vec<u8>>();
 
-        String::from_utf8(clean_name).ok()
+        charset_convert_name(OsStr::from_bytes(&clean_name))
+            .map(|name| transform_name(&name, NameKind::Symlink))
     }
 }
 
@@ -206,10 +221,13 @@ fn list_file(
             long_format(file_hdr, None);
         }
     } else {
-        println!("{}", file_hdr.get_c_name());
+        // 列表按原样输出名字中的字节，使 -t 的输出可以再作为文件列表使用
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(file_hdr.get_c_name().as_bytes());
+        let _ = stdout.write_all(b"\n");
     }
     let crc = 0;
-    tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
+    tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
     tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
     if get_only_verify_crc_flag() {
         if (file_hdr.c_mode & CP_IFMT) == CP_IFLNK {
@@ -221,7 +239,7 @@ fn list_file(
                 0,
                 format_args!(
                     "{}: checksum error (0x{:x}, should be 0x{:x})",
-                    file_hdr.get_c_name(),
+                    quote_name(&file_hdr.get_c_name()),
                     crc,
                     file_hdr.c_chksum
                 ),
@@ -247,11 +265,20 @@ fn try_existing_file(
             error(
                 0,
                 0,
-                format_args!("{} not created: newer or same age version exists", c_name),
+                format_args!(
+                    "{} not created: newer or same age version exists",
+                    quote_name(&c_name)
+                ),
             );
-            tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
+            tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
             tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
             return -1;
+        } else if get_transactional_flag()
+            || (!metadata.is_dir() && (file_hdr.c_mode & CP_IFMT) != CP_IFDIR)
+        {
+            // 普通文件、符号链接和设备文件先写到临时文件，再改名覆盖，这里不删除；
+            // 事务模式下提交前不改动任何已有文件
+            return 0;
         } else {
             let res: io::Result<()>;
             if metadata.is_dir() {
@@ -265,8 +292,8 @@ fn try_existing_file(
                 res = fs::remove_file(&c_name);
             }
             if res.is_err() {
-                error(0, 0, format_args!("cannot remove {}", c_name));
-                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
+                error(0, 0, format_args!("cannot remove {}", quote_name(&c_name)));
+                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
                 tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
             }
         }
@@ -275,6 +302,7 @@ fn try_existing_file(
 }
 
 fn defer_copyin(file_hdr: &CpioFileStat) {
+    journal_record(&JournalRecord::Defer(file_hdr.clone()));
     let deferment = Deferment::new(file_hdr);
     let mut copyin_deferments = COPYIN_DEFERMENTS.lock().unwrap();
 
@@ -282,6 +310,11 @@ fn defer_copyin(file_hdr: &CpioFileStat) {
 }
 
 fn create_defered_links(file_hdr: &mut CpioFileStat) {
+    journal_record(&JournalRecord::Linked {
+        ino: file_hdr.c_ino,
+        maj: file_hdr.c_dev_maj as u64,
+        min: file_hdr.c_dev_min as u64,
+    });
     let mut deferments_guard = COPYIN_DEFERMENTS.lock().unwrap();
     let mut i = 0;
     let mut prev_i: Option<usize> = None;
@@ -300,10 +333,11 @@ fn create_defered_links(file_hdr: &mut CpioFileStat) {
                     0,
                     format_args!(
                         "cannot link {} to {}",
-                        deferment.header.get_c_name(),
-                        file_hdr.get_c_name()
+                        quote_name(&deferment.header.get_c_name()),
+                        quote_name(&file_hdr.get_c_name())
                     ),
                 );
+                transaction_fail();
             }
 
             if let Some(prev) = prev_i {
@@ -357,7 +391,8 @@ fn create_defered_links_to_skipped(
                 continue;
             }
 
-            cpio_set_c_name(file_hdr, deferment.header.get_c_name().as_str());
+            cpio_set_c_name(file_hdr, deferment.header.get_c_name());
+            journal_record(&JournalRecord::Undefer(file_hdr.get_c_name()));
             deferments_guard.remove(i);
             // Convert RawFd to File
             copyin_regular_file(output_tape, input_tape, file_hdr, in_file_des);
@@ -386,38 +421,65 @@ fn create_final_defers() {
             continue;
         }
 
-        let out_file_des = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .custom_flags(libc::O_CREAT | libc::O_WRONLY)
-            .mode(0o600)
-            .open(&c_name);
-
-        let out_file_des = match out_file_des {
-            Ok(file) => file,
-            Err(_e) => {
-                if get_create_dir_flag() {
-                    create_all_directories(&c_name);
-                    match OpenOptions::new()
-                        .create(true)
-                        .write(true)
-                        .custom_flags(libc::O_CREAT | libc::O_WRONLY)
-                        .mode(0o600)
-                        .open(&c_name)
-                    {
-                        Ok(file) => file,
-                        Err(_e) => {
-                            open_error(&c_name);
-                            continue;
-                        }
-                    }
-                } else {
-                    open_error(&c_name);
-                    continue;
-                }
+        let tmp_name = cpio_temp_name(&c_name);
+        let out_file_des = match create_temp_file(&c_name, &tmp_name) {
+            Some(file) => file,
+            None => {
+                open_error(&quote_name(&c_name));
+                transaction_fail();
+                continue;
             }
         };
         set_perms(Some(&out_file_des), &mut d.header);
+        if get_sync_flag() && out_file_des.sync_all().is_err() {
+            call_arg_error("fsync", &quote_name(&c_name));
+        }
+        drop(out_file_des);
+        install_temp_file(&tmp_name, &c_name);
+    }
+}
+
+// 在目标目录中创建临时文件，失败时按需创建上级目录后重试
+fn create_temp_file(c_name: &OsStr, tmp_name: &OsStr) -> Option<File> {
+    let open = || {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(tmp_name)
+    };
+    let file = match open() {
+        Ok(file) => Some(file),
+        Err(_) if get_create_dir_flag() => {
+            create_all_directories(c_name);
+            open().ok()
+        }
+        Err(_) => None,
+    };
+    if file.is_some() {
+        register_temp_file(tmp_name);
+    }
+    file
+}
+
+// 将已写好的临时文件改名为最终文件名，失败时删除临时文件
+fn install_temp_file(tmp_name: &OsStr, c_name: &OsStr) -> i32 {
+    unregister_temp_file(tmp_name);
+    if get_transactional_flag() {
+        transaction_stage(tmp_name, c_name);
+        return 0;
+    }
+    match cpio_rename_into_place(tmp_name, c_name) {
+        Ok(_) => 0,
+        Err(e) => {
+            let _ = fs::remove_file(tmp_name);
+            error(
+                0,
+                e.raw_os_error().unwrap_or(0),
+                format_args!("cannot create {}", quote_name(c_name)),
+            );
+            -1
+        }
     }
 }
 
@@ -429,22 +491,23 @@ fn copyin_regular_file(
 ) {
     let to_stdout_option = get_to_stdout_option();
     let archive_format = get_archive_format();
-    let create_dir_flag = get_create_dir_flag();
     // let swap_halfwords_flag = get_swap_halfwords_flag();
     // let swap_bytes_flag = get_swap_bytes_flag();
 
     let link_res: i32;
+    let mut tmp_name = OsString::new();
 
     let mut out_file_des = if to_stdout_option {
         unsafe { File::from_raw_fd(libc::STDOUT_FILENO) }
     } else {
         if file_hdr.c_nlink > 1
             && (archive_format == ArchiveFormat::Newascii
+                || archive_format == ArchiveFormat::Newxascii
                 || archive_format == ArchiveFormat::Crcascii)
         {
             if file_hdr.c_filesize == 0 {
                 defer_copyin(file_hdr);
-                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
+                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
                 tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
                 return;
             }
@@ -456,7 +519,7 @@ fn copyin_regular_file(
             );
 
             if link_res == 0 {
-                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
+                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
                 tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
                 return;
             }
@@ -471,7 +534,7 @@ fn copyin_regular_file(
                 file_hdr.c_ino,
             );
             if link_res == 0 {
-                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
+                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
                 tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
                 return;
             }
@@ -480,7 +543,7 @@ fn copyin_regular_file(
             && !file_hdr.c_tar_linkname.as_ref().unwrap().is_empty()
         {
             link_res = link_to_name(
-                file_hdr.get_c_name().as_str(),
+                &file_hdr.get_c_name(),
                 file_hdr.c_tar_linkname.as_ref().unwrap(),
             );
             if link_res < 0 {
@@ -488,49 +551,29 @@ fn copyin_regular_file(
                 error(
                     0,
                     0,
-                    format_args!("cannot link {} to {}", tar_linkname, file_hdr.get_c_name()),
+                    format_args!(
+                        "cannot link {} to {}",
+                        quote_name(&tar_linkname),
+                        quote_name(&file_hdr.get_c_name())
+                    ),
                 );
+                transaction_fail();
             }
             return;
         }
 
-        let out_file_res = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .custom_flags(libc::O_CREAT | libc::O_WRONLY)
-            .mode(0o600)
-            .open(file_hdr.get_c_name());
-
-        let out_file = match out_file_res {
-            Ok(file) => file,
-            Err(_e) => {
-                if create_dir_flag {
-                    create_all_directories(&file_hdr.get_c_name());
-                    match OpenOptions::new()
-                        .create(true)
-                        .write(true)
-                        .custom_flags(libc::O_CREAT | libc::O_WRONLY)
-                        .mode(0o600)
-                        .open(file_hdr.get_c_name())
-                    {
-                        Ok(file) => file,
-                        Err(_) => {
-                            open_error(&file_hdr.get_c_name());
-                            tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
-                            tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
-                            return;
-                        }
-                    }
-                } else {
-                    open_error(&file_hdr.get_c_name());
-                    tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
-                    tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
-                    return;
-                }
+        let c_name = file_hdr.get_c_name();
+        tmp_name = cpio_temp_name(&c_name);
+        match create_temp_file(&c_name, &tmp_name) {
+            Some(file) => file,
+            None => {
+                open_error(&quote_name(&c_name));
+                transaction_fail();
+                tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
+                tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
+                return;
             }
-        };
-
-        out_file
+        }
     };
 
     set_crc(0);
@@ -544,7 +587,7 @@ fn copyin_regular_file(
                 0,
                 format_args!(
                     "cannot swap halfwords of {}: odd number of halfwords",
-                    file_hdr.get_c_name()
+                    quote_name(&file_hdr.get_c_name())
                 ),
             );
         }
@@ -558,18 +601,23 @@ fn copyin_regular_file(
                 0,
                 format_args!(
                     "cannot swap bytes of {}: odd number of bytes",
-                    file_hdr.get_c_name()
+                    quote_name(&file_hdr.get_c_name())
                 ),
             );
         }
     }
-    copy_files_tape_to_disk(
-        input_tape,
-        output_tape,
-        in_file_des,
-        &mut out_file_des,
-        file_hdr.c_filesize as i32,
-    );
+    match file_hdr.c_sparse {
+        Some(ref map) => {
+            copy_sparse_tape_to_disk(input_tape, output_tape, in_file_des, &mut out_file_des, map)
+        }
+        None => copy_files_tape_to_disk(
+            input_tape,
+            output_tape,
+            in_file_des,
+            &mut out_file_des,
+            file_hdr.c_filesize as u64,
+        ),
+    }
     {
         disk_empty_output_buffer(output_tape, &mut out_file_des, true);
     }
@@ -578,7 +626,7 @@ fn copyin_regular_file(
         if archive_format == ArchiveFormat::Crcascii && get_crc() != file_hdr.c_chksum as usize {
             eprintln!(
                 "{}: checksum error (0x{:x}, should be 0x{:x})",
-                file_hdr.get_c_name(),
+                quote_name(&file_hdr.get_c_name()),
                 get_crc(),
                 file_hdr.c_chksum
             );
@@ -589,9 +637,11 @@ fn copyin_regular_file(
 
     set_perms(Some(&out_file_des), file_hdr);
 
-    // if unsafe { libc::close(out_file_des) } < 0 {
-    //     close_error(&file_hdr.c_name);
-    // }
+    if get_sync_flag() && out_file_des.sync_all().is_err() {
+        call_arg_error("fsync", &quote_name(&file_hdr.get_c_name()));
+    }
+    drop(out_file_des);
+    install_temp_file(&tmp_name, &file_hdr.get_c_name());
 
     if archive_format == ArchiveFormat::Crcascii && get_crc() != file_hdr.c_chksum as usize {
         error(
@@ -599,16 +649,19 @@ fn copyin_regular_file(
             0,
             format_args!(
                 "{}: checksum error (0x{:x}, should be 0x{:x})",
-                file_hdr.get_c_name(),
+                quote_name(&file_hdr.get_c_name()),
                 get_crc(),
                 file_hdr.c_chksum
             ),
         );
+        transaction_fail();
     }
 
     tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
     if file_hdr.c_nlink > 1
-        && (archive_format == ArchiveFormat::Newascii || archive_format == ArchiveFormat::Crcascii)
+        && (archive_format == ArchiveFormat::Newascii
+            || archive_format == ArchiveFormat::Newxascii
+            || archive_format == ArchiveFormat::Crcascii)
     {
         create_defered_links(file_hdr);
     }
@@ -656,58 +709,69 @@ fn copyin_device(file_hdr: &mut CpioFileStat) {
             error(
                 0,
                 0,
-                format_args!("cannot link {} to {}", tar_linkname, file_hdr.get_c_name()),
+                format_args!(
+                    "cannot link {} to {}",
+                    quote_name(&tar_linkname),
+                    quote_name(&file_hdr.get_c_name())
+                ),
             );
+            transaction_fail();
         }
         return;
     }
 
-    let dev = makedev(file_hdr.c_rdev_maj as u8, file_hdr.c_rdev_min as u8);
-    let res: i32 = unsafe {
-        let c_name_ptr = file_hdr.get_c_name().as_ptr();
-        let mode = file_hdr.c_mode;
+    let c_name = file_hdr.get_c_name();
+    let tmp_name = cpio_temp_name(&c_name);
+
+    if get_rootless_flag() {
+        // 设备、FIFO 和套接字创建为空的普通文件，类型和设备号由 set_perms 记录
+        let Some(out_file) = create_temp_file(&c_name, &tmp_name) else {
+            open_error(&quote_name(&c_name));
+            transaction_fail();
+            return;
+        };
+        set_perms(Some(&out_file), file_hdr);
+        drop(out_file);
+        install_temp_file(&tmp_name, &c_name);
+        return;
+    }
 
-        libc::mknod(c_name_ptr as *const libc::c_char, mode, dev as dev_t)
+    let tmp_cstr = match CString::new(tmp_name.as_bytes()) {
+        Ok(cstr) => cstr,
+        Err(_) => {
+            mknod_error(&quote_name(&c_name));
+            transaction_fail();
+            return;
+        }
     };
 
-    if res < 0 && create_dir_flag {
-        create_all_directories(&file_hdr.get_c_name());
-        {
-            let dev = makedev(file_hdr.c_rdev_maj as u8, file_hdr.c_rdev_min as u8);
-            let res = unsafe {
-                let c_name_ptr = file_hdr.get_c_name().as_ptr();
-                let mode = file_hdr.c_mode;
+    let dev = makedev(file_hdr.c_rdev_maj as u32, file_hdr.c_rdev_min);
+    let mut res: i32 = unsafe { libc::mknod(tmp_cstr.as_ptr(), file_hdr.c_mode, dev as dev_t) };
 
-                libc::mknod(c_name_ptr as *const libc::c_char, mode, dev as dev_t)
-            };
-            if res < 0 {
-                mknod_error(&file_hdr.get_c_name());
-                return;
-            }
-        }
+    if res < 0 && create_dir_flag {
+        create_all_directories(&c_name);
+        res = unsafe { libc::mknod(tmp_cstr.as_ptr(), file_hdr.c_mode, dev as dev_t) };
     }
     if res < 0 {
-        mknod_error(&file_hdr.get_c_name());
+        mknod_error(&quote_name(&c_name));
+        transaction_fail();
         return;
     }
+    register_temp_file(&tmp_name);
 
     if !no_chown_flag {
         let uid = if set_owner_flag {
             get_set_owner()
         } else {
-            file_hdr.c_uid
+            map_uid(file_hdr.c_uid)
         };
         let gid = if set_group_flag {
             get_set_group()
         } else {
-            file_hdr.c_gid
+            map_gid(file_hdr.c_gid)
         };
 
-        let chown_res = unsafe {
-            let c_name_ptr = file_hdr.get_c_name().as_ptr();
-
-            libc::chown(c_name_ptr as *const libc::c_char, uid, gid)
-        };
+        let chown_res = unsafe { libc::chown(tmp_cstr.as_ptr(), uid, gid) };
         if chown_res < 0 {
             // 对于符号链接，更宽容地处理权限设置错误
             let err = io::Error::last_os_error();
@@ -717,60 +781,39 @@ fn copyin_device(file_hdr: &mut CpioFileStat) {
                     // 这些错误对于符号链接来说是可以忽略的
                 }
                 _ => {
-                    chown_error_details(&file_hdr.get_c_name(), uid, gid);
+                    chown_error_details(&quote_name(&c_name), uid, gid);
                 }
             }
         }
     }
 
-    let chmod_res = unsafe {
-        let c_name_ptr = file_hdr.get_c_name().as_ptr();
-        libc::chmod(c_name_ptr as *const libc::c_char, file_hdr.c_mode)
-    };
+    let chmod_res = unsafe { libc::chmod(tmp_cstr.as_ptr(), file_hdr.c_mode) };
     if chmod_res < 0 {
-        chmod_error_details(&file_hdr.get_c_name(), file_hdr.c_mode);
+        chmod_error_details(&quote_name(&c_name), file_hdr.c_mode);
     }
 
     if retain_time_flag {
-        set_file_times(
-            None,
-            &file_hdr.get_c_name(),
-            file_hdr.c_mtime,
-            file_hdr.c_mtime,
-            0,
-        );
+        set_file_times(None, &tmp_name, file_hdr.c_mtime, file_hdr.c_mtime, 0);
     }
-}
 
-fn symlink_placeholder(oldpath: &str, newpath: &str, file_stat: &CpioFileStat) -> i32 {
-    let fd = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(newpath)
-        .map(|file| file.as_raw_fd());
-    let out_file_des = match fd {
-        Ok(file) => file.as_raw_fd(),
-        Err(_) if get_create_dir_flag() => {
-            create_all_directories(newpath);
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .custom_flags(libc::O_CREAT | libc::O_WRONLY)
-                .mode(0o600)
-                .open(newpath)
-                .map(|file| file.as_raw_fd())
-                .unwrap_or(-1)
-        }
-        Err(_) => -1,
-    };
+    install_temp_file(&tmp_name, &c_name);
+}
 
-    if out_file_des < 0 {
-        open_error(newpath);
+fn symlink_placeholder(oldpath: &OsStr, newpath: &OsStr, file_stat: &CpioFileStat) -> i32 {
+    let tmp_name = cpio_temp_name(newpath);
+    match create_temp_file(newpath, &tmp_name) {
+        Some(file) => drop(file),
+        None => {
+            open_error(&quote_name(newpath));
+            return -1;
+        }
+    }
+    if install_temp_file(&tmp_name, newpath) < 0 {
         return -1;
     }
 
     let metadata = fs::metadata(newpath).map_err(|_e| {
-        stat_error(newpath);
+        stat_error(&quote_name(newpath));
         -1
     });
 
@@ -787,10 +830,21 @@ fn symlink_placeholder(oldpath: &str, newpath: &str, file_stat: &CpioFileStat) -
         uid: file_stat.c_uid,
         gid: file_stat.c_gid,
         mtime: file_stat.c_mtime,
-        source: oldpath.to_string(),
-        target: newpath.to_string(),
+        source: oldpath.to_os_string(),
+        target: newpath.to_os_string(),
     };
 
+    journal_record(&JournalRecord::Symlink {
+        dev: key.dev,
+        ino: key.ino,
+        mode: value.mode,
+        uid: value.uid,
+        gid: value.gid,
+        mtime: value.mtime,
+        source: value.source.clone(),
+        target: value.target.clone(),
+    });
+
     let mut delayed_link: std::sync::MutexGuard<'_, DelayedLink> =
         GLOBAL_DELAYED_LINK.lock().unwrap();
     delayed_link.insert(key, value);
@@ -810,60 +864,60 @@ fn replace_symlink_placeholders() {
         let metadata = fs::symlink_metadata(&dl.target);
         if let Ok(st) = metadata {
             if st.dev() == key.dev && st.ino() == key.ino {
-                if unsafe { unlink(dl.target.as_ptr() as *const libc::c_char) != 0 } {
-                    unlink_error(&dl.target);
+                // 先在临时文件名上创建符号链接，再改名覆盖占位文件
+                let tmp_name = cpio_temp_name(&dl.target);
+                let source_cstr = CString::new(dl.source.as_bytes()).unwrap();
+                let target_cstr = CString::new(tmp_name.as_bytes()).unwrap();
+                let res = unsafe { symlink(source_cstr.as_ptr(), target_cstr.as_ptr()) };
+                if res < 0 {
+                    error(
+                        0,
+                        0,
+                        format_args!(
+                            "{:?}: Cannot create symlink to {:?}",
+                            quote_name(&dl.target),
+                            quote_name(&dl.source)
+                        ),
+                    );
                 } else {
-                    let source_cstr = std::ffi::CString::new(dl.source.as_str()).unwrap();
-                    let target_cstr = std::ffi::CString::new(dl.target.as_str()).unwrap();
-                    let mut res = unsafe { symlink(source_cstr.as_ptr(), target_cstr.as_ptr()) };
-                    if res < 0 && get_create_dir_flag() {
-                        create_all_directories(&dl.target);
-                        res = unsafe { symlink(source_cstr.as_ptr(), target_cstr.as_ptr()) };
-                    }
-                    if res < 0 {
-                        error(
-                            0,
-                            0,
-                            format_args!(
-                                "{:?}: Cannot create symlink to {:?}",
-                                quotearg_colon(&dl.target),
-                                quotearg(&dl.source)
-                            ),
-                        );
-                    } else {
-                        if !get_no_chown_flag() {
-                            let uid = if get_set_owner_flag() {
-                                get_set_owner()
-                            } else {
-                                dl.uid
-                            };
-                            let gid = if get_set_group_flag() {
-                                get_set_group()
-                            } else {
-                                dl.gid
-                            };
-                            if unsafe {
-                                libc::lchown(dl.target.as_ptr() as *const libc::c_char, uid, gid)
-                            } != 0
-                            {
-                                // 对于符号链接，更宽容地处理权限设置错误
-                                let err = io::Error::last_os_error();
-                                match err.raw_os_error() {
-                                    Some(libc::EPERM) | Some(libc::ENOENT) | Some(libc::EROFS)
-                                    | Some(libc::EINVAL) | Some(libc::EACCES)
-                                    | Some(libc::ENOTSUP) => {
-                                        // 这些错误对于符号链接来说是可以忽略的
-                                    }
-                                    _ => {
-                                        chown_error_details(&dl.target, uid, gid);
-                                    }
+                    register_temp_file(&tmp_name);
+                    if get_rootless_flag() || !get_no_chown_flag() {
+                        let uid = if get_set_owner_flag() {
+                            get_set_owner()
+                        } else {
+                            map_uid(dl.uid)
+                        };
+                        let gid = if get_set_group_flag() {
+                            get_set_group()
+                        } else {
+                            map_gid(dl.gid)
+                        };
+                        if get_rootless_flag() {
+                            warn_symlink_owner(&dl.target, uid, gid);
+                        } else if unsafe { libc::lchown(target_cstr.as_ptr(), uid, gid) } != 0 {
+                            // 对于符号链接，更宽容地处理权限设置错误
+                            let err = io::Error::last_os_error();
+                            match err.raw_os_error() {
+                                Some(libc::EPERM) | Some(libc::ENOENT) | Some(libc::EROFS)
+                                | Some(libc::EINVAL) | Some(libc::EACCES) | Some(libc::ENOTSUP) => {
+                                    // 这些错误对于符号链接来说是可以忽略的
+                                }
+                                _ => {
+                                    chown_error_details(&quote_name(&dl.target), uid, gid);
                                 }
                             }
                         }
-                        if get_retain_time_flag() {
-                            set_file_times(None, &dl.target, dl.mtime, dl.mtime, 0);
-                        }
                     }
+                    if get_retain_time_flag() {
+                        set_file_times(
+                            None,
+                            &tmp_name,
+                            dl.mtime,
+                            dl.mtime,
+                            libc::AT_SYMLINK_NOFOLLOW,
+                        );
+                    }
+                    install_temp_file(&tmp_name, &dl.target);
                 }
             }
         }
@@ -876,26 +930,36 @@ fn copyin_link(
     file_hdr: &mut CpioFileStat,
     in_file_des: &mut File,
 ) {
-    let link_name = get_link_name(input_tape, file_hdr, in_file_des);
+    // tar 格式的链接目标保存在头部中，成员没有数据
+    let link_name = if get_archive_format() != ArchiveFormat::Tar
+        && get_archive_format() != ArchiveFormat::Ustar
+    {
+        get_link_name(input_tape, file_hdr, in_file_des)
+    } else {
+        file_hdr.c_tar_linkname.clone()
+    };
     if link_name.is_none() {
         return;
     }
 
     let str_link_name = link_name.unwrap();
 
-    if get_no_abs_paths_flag() {
-        symlink_placeholder(&str_link_name, file_hdr.get_c_name().as_str(), file_hdr);
+    if get_no_abs_paths_flag() && !get_transactional_flag() {
+        symlink_placeholder(&str_link_name, &file_hdr.get_c_name(), file_hdr);
     } else {
-        let source_cstr = std::ffi::CString::new(str_link_name.clone()).unwrap();
-        let target_cstr = std::ffi::CString::new(file_hdr.get_c_name().clone()).unwrap();
+        let c_name = file_hdr.get_c_name();
+        let tmp_name = cpio_temp_name(&c_name);
+        let source_cstr = CString::new(str_link_name.as_bytes()).unwrap();
+        let target_cstr = CString::new(tmp_name.as_bytes()).unwrap();
         let mut res = unsafe { symlink(source_cstr.as_ptr(), target_cstr.as_ptr()) };
         if res < 0 && get_create_dir_flag() {
-            create_all_directories(&file_hdr.get_c_name());
+            create_all_directories(&c_name);
             res = unsafe { symlink(source_cstr.as_ptr(), target_cstr.as_ptr()) };
         }
         if res < 0 {
             // 改进错误处理：对符号链接创建失败更加宽容
             let err = io::Error::last_os_error();
+            transaction_fail();
             match err.raw_os_error() {
                 Some(libc::EPERM) | Some(libc::EACCES) | Some(libc::EROFS) => {
                     // 权限相关错误，可以忽略
@@ -916,26 +980,29 @@ fn copyin_link(
                         0,
                         format_args!(
                             "{:?}: Cannot create symlink to {:?}",
-                            quotearg_colon(&file_hdr.get_c_name()),
-                            quotearg(&str_link_name)
+                            quote_name(&file_hdr.get_c_name()),
+                            quote_name(&str_link_name)
                         ),
                     );
                 }
             }
-        } else if !get_no_chown_flag() {
+        } else if get_rootless_flag() || !get_no_chown_flag() {
             let uid = if get_set_owner_flag() {
                 get_set_owner()
             } else {
-                file_hdr.c_uid
+                map_uid(file_hdr.c_uid)
             };
             let gid = if get_set_group_flag() {
                 get_set_group()
             } else {
-                file_hdr.c_gid
+                map_gid(file_hdr.c_gid)
             };
-            if unsafe {
+            if get_rootless_flag() {
+                // 不改变属主，符号链接上也不能记录属主
+                warn_symlink_owner(&c_name, uid, gid);
+            } else if unsafe {
                 lchown(
-                    file_hdr.get_c_name().as_ptr() as *const libc::c_char,
+                    target_cstr.as_ptr(),
                     u32::from(Uid::from_raw(uid)),
                     u32::from(Gid::from_raw(gid)),
                 )
@@ -949,21 +1016,26 @@ fn copyin_link(
                         // 这些错误对于符号链接来说是可以忽略的
                     }
                     _ => {
-                        chown_error_details(&file_hdr.get_c_name(), uid, gid);
+                        chown_error_details(&quote_name(&c_name), uid, gid);
                     }
                 }
             }
         }
 
+        if res < 0 {
+            return;
+        }
+        register_temp_file(&tmp_name);
         if get_retain_time_flag() {
             set_file_times(
                 None,
-                &file_hdr.get_c_name(),
+                &tmp_name,
                 file_hdr.c_mtime,
                 file_hdr.c_mtime,
-                0,
+                libc::AT_SYMLINK_NOFOLLOW,
             );
         }
+        install_temp_file(&tmp_name, &c_name);
     }
 }
 
@@ -986,15 +1058,15 @@ fn copyin_file(
         CP_IFDIR => {
             cpio_create_dir(file_hdr, existing_dir);
         }
-        CP_IFCHR | CP_IFBLK => copyin_device(file_hdr),
+        CP_IFCHR | CP_IFBLK | CP_IFIFO | CP_IFSOCK => copyin_device(file_hdr),
         CP_IFLNK => copyin_link(input_tape, file_hdr, in_file_des),
         _ => {
             error(
                 0,
                 0,
-                format_args!("{}: unknown file type", file_hdr.get_c_name()),
+                format_args!("{}: unknown file type", quote_name(&file_hdr.get_c_name())),
             );
-            tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as i32);
+            tape_toss_input(input_tape, in_file_des, file_hdr.c_filesize as u64);
             tape_skip_padding(input_tape, in_file_des, file_hdr.c_filesize as u64);
         }
     }
@@ -1006,7 +1078,7 @@ fn format_time(when: i64) -> String {
     datetime.format("%a %b %d %H:%M:%S %Y").to_string()
 }
 
-fn long_format(file_hdr: &mut CpioFileStat, link_name: Option<String>) {
+fn long_format(file_hdr: &mut CpioFileStat, link_name: Option<OsString>) {
     let mut mbuf: [char; 11] = ['\0'; 11];
 
     //let mut when_timespec = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(when as u64);
@@ -1036,11 +1108,19 @@ fn long_format(file_hdr: &mut CpioFileStat, link_name: Option<String>) {
     if (file_hdr.c_mode & CP_IFMT) == CP_IFCHR || (file_hdr.c_mode & CP_IFMT) == CP_IFBLK {
         print!("{:3}, {:3} ", file_hdr.c_rdev_maj, file_hdr.c_rdev_min);
     } else {
-        print!("{:8} ", file_hdr.c_filesize);
+        let size = file_hdr
+            .c_sparse
+            .as_ref()
+            .map_or(file_hdr.c_filesize, |map| map.realsize as i64);
+        print!("{:8} ", size);
     }
 
     let when = file_hdr.c_mtime;
-    let when_timespec = SystemTime::UNIX_EPOCH + Duration::new(when.try_into().unwrap(), 0);
+    let when_timespec = if when < 0 {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(when.unsigned_abs())
+    } else {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(when as u64)
+    };
 
     let binding = format_time(when).clone();
     let mut tbuf = binding.into_bytes();
@@ -1063,66 +1143,18 @@ fn long_format(file_hdr: &mut CpioFileStat, link_name: Option<String>) {
     // 打印时间
     print!("{}", tbuf_str);
 
-    // 打印文件名和链接名
-    print!("{}", quotearg(file_hdr.get_c_name().as_str()));
+    // 打印文件名和链接名，与不带 -v 的列表一样按原样输出其中的字节
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(file_hdr.get_c_name().as_bytes());
     if let Some(link) = link_name {
-        print!(" -> {}", quotearg(link.as_str()));
+        let _ = stdout.write_all(b" -> ");
+        let _ = stdout.write_all(link.as_bytes());
     }
-    println!(); // 换行
+    let _ = stdout.write_all(b"\n"); // 换行
 
     // todo
 }
 
-fn read_pattern_file() {
-    let mut new_save_patterns: Vec<String> = Vec::new();
-    let mut max_new_patterns: usize;
-    let mut new_num_patterns: usize;
-    //let pattern_name = DYNAMIC_STRING_INITIALIZER;
-    //let pattern_fp: File;
-
-    if get_num_patterns() < 0 {
-        set_num_patterns(0);
-    }
-    new_num_patterns = get_num_patterns() as usize;
-    max_new_patterns = get_num_patterns() as usize;
-    new_save_patterns.reserve(max_new_patterns);
-
-    let out_file_des = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .custom_flags(libc::O_CREAT | libc::O_WRONLY)
-        .mode(0o600)
-        .open(get_pattern_file_name().unwrap());
-    match out_file_des {
-        Ok(file) => {
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
-                let line = line.unwrap();
-                if new_num_patterns == max_new_patterns {
-                    max_new_patterns *= 2;
-                    new_save_patterns.reserve(max_new_patterns);
-                }
-                new_save_patterns.push(line);
-                new_num_patterns += 1;
-            }
-
-            // if let Err(e) = reader.into_inner().sync_all() {
-            //     close_error(get_pattern_file_name().as_deref().unwrap());
-            // }
-        }
-        Err(_) => {
-            open_error(get_pattern_file_name().as_deref().unwrap());
-        }
-    }
-
-    for i in 0..get_num_patterns() as usize {
-        new_save_patterns[i] = get_save_patterns()[i].clone();
-    }
-
-    set_save_patterns(new_save_patterns);
-    set_num_patterns(new_num_patterns as i32);
-}
-
 fn swab_short(i: u16) -> u16 {
     ((i << 8) & 0xff00) | ((i >> 8) & 0x00ff)
 }
@@ -1139,11 +1171,12 @@ enum TmpBuf {
     Us(u16),
 }
 
+// 读入下一个成员的头部，返回头部在归档中的偏移
 fn read_in_header(
     input_tape: &mut MutexGuard<TapeInput>,
     file_hdr: &mut CpioFileStat,
     in_des: &mut File,
-) {
+) -> u64 {
     let magic = Magic::Str([0; 6]);
     let mut bytes_skipped: i64 = 0;
 
@@ -1176,6 +1209,8 @@ fn read_in_header(
             } else if hdr == "070702" {
                 archive_format = ArchiveFormat::Crcascii;
                 set_crc_i_flag(true);
+            } else if hdr == "070703" {
+                archive_format = ArchiveFormat::Newxascii;
             } else if peeked_bytes >= 2 && {
                 let us = u16::from_le_bytes([tmpbuf_s[0], tmpbuf_s[1]]);
                 us == 0o070707 || us == swab_short(0o070707)
@@ -1199,19 +1234,19 @@ fn read_in_header(
     }
 
     if archive_format == ArchiveFormat::Tar || archive_format == ArchiveFormat::Ustar {
+        let header_start = (input_tape.input_bytes - input_tape.input_size) as u64;
         if get_append_flag() {
-            let last_header_start =
-                input_tape.input_bytes - get_io_block_size() as usize + input_tape.in_buff;
-            set_last_header_start(last_header_start as i32);
+            set_last_header_start(header_start);
         }
         if bytes_skipped > 0 {
             warn_junk_bytes(bytes_skipped as u64);
         }
         read_in_tar_header(input_tape, file_hdr, in_des);
-        return;
+        return header_start;
     }
 
     file_hdr.c_tar_linkname = None;
+    file_hdr.c_sparse = None;
 
     let mut magic_str = match magic {
         Magic::Str(arr) => arr,
@@ -1221,26 +1256,33 @@ fn read_in_header(
     tape_buffered_read(input_tape, &mut magic_str, in_des, 6);
 
     loop {
+        let header_start = (input_tape.input_bytes - input_tape.input_size - 6) as u64;
         if get_append_flag() {
-            let last_header_start =
-                input_tape.input_bytes - get_io_block_size() as usize + input_tape.in_buff - 6;
-            set_last_header_start(last_header_start as i32);
+            set_last_header_start(header_start);
         }
         if archive_format == ArchiveFormat::Newascii && &magic_str == b"070701" {
             if bytes_skipped > 0 {
                 warn_junk_bytes(bytes_skipped as u64);
             }
             file_hdr.c_magic = 0o70701;
-            read_in_new_ascii(input_tape, file_hdr, in_des);
-            break;
+            read_in_new_ascii(input_tape, file_hdr, in_des, 8);
+            break header_start;
         }
         if archive_format == ArchiveFormat::Crcascii && &magic_str == b"070702" {
             if bytes_skipped > 0 {
                 warn_junk_bytes(bytes_skipped as u64);
             }
             file_hdr.c_magic = 0o70702;
-            read_in_new_ascii(input_tape, file_hdr, in_des);
-            break;
+            read_in_new_ascii(input_tape, file_hdr, in_des, 8);
+            break header_start;
+        }
+        if archive_format == ArchiveFormat::Newxascii && &magic_str == b"070703" {
+            if bytes_skipped > 0 {
+                warn_junk_bytes(bytes_skipped as u64);
+            }
+            file_hdr.c_magic = 0o70703;
+            read_in_new_ascii(input_tape, file_hdr, in_des, 16);
+            break header_start;
         }
         if (archive_format == ArchiveFormat::Oldascii
             || archive_format == ArchiveFormat::Hpoldascii)
@@ -1251,7 +1293,7 @@ fn read_in_header(
             }
             file_hdr.c_magic = 0o70707;
             read_in_old_ascii(input_tape, file_hdr, in_des);
-            break;
+            break header_start;
         }
         if archive_format == ArchiveFormat::Binary || archive_format == ArchiveFormat::Hpbinary {
             let num = u16::from_le_bytes([magic_str[0], magic_str[1]]);
@@ -1265,7 +1307,7 @@ fn read_in_header(
                 let mut old_header: OldCpioHeader = OldCpioHeader::from_bytes(magic_str);
 
                 read_in_binary(input_tape, file_hdr, &mut old_header, in_des);
-                break;
+                break header_start;
             }
         }
         bytes_skipped += 1;
@@ -1295,14 +1337,14 @@ fn read_name_from_file(
             format_args!("malformed header: file name too long ({})", len),
         );
         // 跳过这个文件
-        tape_toss_input(input_tape, file, len as i32);
+        tape_toss_input(input_tape, file, len as u64);
     } else {
         //cpio_realloc_c_name(file_hdr, len);
         // 用 len 分配一个内存
         let mut in_buf = vec![0; len];
 
         tape_buffered_read(input_tape, &mut in_buf, file, len);
-        file_hdr.set_c_name(String::from_utf8_lossy(&in_buf).as_ref());
+        file_hdr.set_c_name(OsStr::from_bytes(&in_buf));
         // if file_hdr.c_name.as_bytes()[len as usize - 1] != 0 {
         //     error(
         //         0,
@@ -1346,8 +1388,8 @@ fn read_in_old_ascii(
     tape_buffered_read(input_tape, &mut c_filesize, in_des, 11);
 
     dev = from_octal(&c_dev.to_vec());
-    file_hdr.c_dev_maj = major(dev as u32) as i32;
-    file_hdr.c_dev_min = minor(dev as u32) as u32;
+    file_hdr.c_dev_maj = major(dev) as i32;
+    file_hdr.c_dev_min = minor(dev);
 
     file_hdr.c_ino = from_octal(&c_ino.to_vec());
     file_hdr.c_mode = from_octal(&c_mode.to_vec()) as u32;
@@ -1355,8 +1397,8 @@ fn read_in_old_ascii(
     file_hdr.c_gid = from_octal(&c_gid.to_vec()) as u32;
     file_hdr.c_nlink = from_octal(&c_nlink.to_vec()) as usize;
     dev = from_octal(&c_rdev.to_vec());
-    file_hdr.c_rdev_maj = major(dev as u32) as i32;
-    file_hdr.c_rdev_min = minor(dev as u32) as u32;
+    file_hdr.c_rdev_maj = major(dev) as i32;
+    file_hdr.c_rdev_min = minor(dev);
 
     file_hdr.c_mtime = from_octal(&c_mtime.to_vec()) as i64;
     file_hdr.c_filesize = from_octal(&c_filesize.to_vec()) as i64;
@@ -1370,8 +1412,8 @@ fn read_in_old_ascii(
     match file_hdr.c_mode & CP_IFMT {
         CP_IFCHR | CP_IFBLK | CP_IFSOCK | CP_IFIFO => {
             if file_hdr.c_filesize != 0 && file_hdr.c_rdev_maj == 0 && file_hdr.c_rdev_min == 1 {
-                file_hdr.c_rdev_maj = major(file_hdr.c_filesize as u32) as i32;
-                file_hdr.c_rdev_min = minor(file_hdr.c_filesize as u32) as u32;
+                file_hdr.c_rdev_maj = major(file_hdr.c_filesize as u64) as i32;
+                file_hdr.c_rdev_min = minor(file_hdr.c_filesize as u64);
                 file_hdr.c_filesize = 0;
             }
         }
@@ -1379,10 +1421,12 @@ fn read_in_old_ascii(
     }
 }
 
-fn read_in_new_ascii(
+// TIME_DIGITS 是修改时间和文件大小字段的长度，newcx 格式为 16，其他为 8
+pub fn read_in_new_ascii(
     input_tape: &mut MutexGuard<TapeInput>,
     file_hdr: &mut CpioFileStat,
     in_des: &mut File,
+    time_digits: usize,
 ) {
     // Read header fields individually
     let mut c_ino = [0u8; 8];
@@ -1390,8 +1434,8 @@ fn read_in_new_ascii(
     let mut c_uid = [0u8; 8];
     let mut c_gid = [0u8; 8];
     let mut c_nlink = [0u8; 8];
-    let mut c_mtime = [0u8; 8];
-    let mut c_filesize = [0u8; 8];
+    let mut c_mtime = vec![0u8; time_digits];
+    let mut c_filesize = vec![0u8; time_digits];
     let mut c_dev_maj = [0u8; 8];
     let mut c_dev_min = [0u8; 8];
     let mut c_rdev_maj = [0u8; 8];
@@ -1404,8 +1448,8 @@ fn read_in_new_ascii(
     tape_buffered_read(input_tape, &mut c_uid, in_des, 8);
     tape_buffered_read(input_tape, &mut c_gid, in_des, 8);
     tape_buffered_read(input_tape, &mut c_nlink, in_des, 8);
-    tape_buffered_read(input_tape, &mut c_mtime, in_des, 8);
-    tape_buffered_read(input_tape, &mut c_filesize, in_des, 8);
+    tape_buffered_read(input_tape, &mut c_mtime, in_des, time_digits);
+    tape_buffered_read(input_tape, &mut c_filesize, in_des, time_digits);
     tape_buffered_read(input_tape, &mut c_dev_maj, in_des, 8);
     tape_buffered_read(input_tape, &mut c_dev_min, in_des, 8);
     tape_buffered_read(input_tape, &mut c_rdev_maj, in_des, 8);
@@ -1418,12 +1462,12 @@ fn read_in_new_ascii(
     file_hdr.c_uid = from_hex(&c_uid.to_vec()) as u32;
     file_hdr.c_gid = from_hex(&c_gid.to_vec()) as u32;
     file_hdr.c_nlink = from_hex(&c_nlink.to_vec()) as usize;
-    file_hdr.c_mtime = from_hex(&c_mtime.to_vec()) as i64;
-    file_hdr.c_filesize = from_hex(&c_filesize.to_vec()) as i64;
-    file_hdr.c_dev_maj = major(from_hex(&c_dev_maj.to_vec()) as u32) as i32;
-    file_hdr.c_dev_min = minor(from_hex(&c_dev_min.to_vec()) as u32) as u32;
-    file_hdr.c_rdev_maj = major(from_hex(&c_rdev_maj.to_vec()) as u32) as i32;
-    file_hdr.c_rdev_min = minor(from_hex(&c_rdev_min.to_vec()) as u32) as u32;
+    file_hdr.c_mtime = from_hex(&c_mtime) as i64;
+    file_hdr.c_filesize = from_hex(&c_filesize) as i64;
+    file_hdr.c_dev_maj = from_hex(&c_dev_maj.to_vec()) as i32;
+    file_hdr.c_dev_min = from_hex(&c_dev_min.to_vec()) as u32;
+    file_hdr.c_rdev_maj = from_hex(&c_rdev_maj.to_vec()) as i32;
+    file_hdr.c_rdev_min = from_hex(&c_rdev_min.to_vec()) as u32;
     file_hdr.c_chksum = from_hex(&c_chksum.to_vec()) as u32;
 
     read_name_from_file(
@@ -1433,7 +1477,11 @@ fn read_in_new_ascii(
         from_hex(&c_namesize.to_vec()) as usize,
     );
 
-    tape_skip_padding(input_tape, in_des, (file_hdr.c_namesize + 110) as u64);
+    tape_skip_padding(
+        input_tape,
+        in_des,
+        (file_hdr.c_namesize + 110 + (time_digits - 8) * 2) as u64,
+    );
 }
 
 fn read_in_binary(
@@ -1469,15 +1517,15 @@ fn read_in_binary(
         swab_array(old_hdr.as_mut_slice(), 13);
     }
 
-    file_hdr.c_dev_maj = major(old_hdr.c_dev as u32) as i32;
-    file_hdr.c_dev_min = minor(old_hdr.c_dev as u32) as u32;
+    file_hdr.c_dev_maj = major(old_hdr.c_dev as u64) as i32;
+    file_hdr.c_dev_min = minor(old_hdr.c_dev as u64);
     file_hdr.c_ino = old_hdr.c_ino as u64;
     file_hdr.c_mode = old_hdr.c_mode as u32;
     file_hdr.c_uid = old_hdr.c_uid as u32;
     file_hdr.c_gid = old_hdr.c_gid as u32;
     file_hdr.c_nlink = old_hdr.c_nlink as usize;
-    file_hdr.c_rdev_maj = major(old_hdr.c_rdev as u32) as i32;
-    file_hdr.c_rdev_min = minor(old_hdr.c_rdev as u32) as u32;
+    file_hdr.c_rdev_maj = major(old_hdr.c_rdev as u64) as i32;
+    file_hdr.c_rdev_min = minor(old_hdr.c_rdev as u64);
     file_hdr.c_mtime = (old_hdr.c_mtimes[0] as i64) << 16 | old_hdr.c_mtimes[1] as i64;
     file_hdr.c_filesize = (old_hdr.c_filesizes[0] as i64) << 16 | old_hdr.c_filesizes[1] as i64;
     read_name_from_file(input_tape, file_hdr, in_des, old_hdr.c_namesize as usize);
@@ -1489,8 +1537,8 @@ fn read_in_binary(
     match file_hdr.c_mode & CP_IFMT {
         CP_IFCHR | CP_IFBLK | CP_IFSOCK | CP_IFIFO => {
             if file_hdr.c_filesize != 0 && file_hdr.c_rdev_maj == 0 && file_hdr.c_rdev_min == 1 {
-                file_hdr.c_rdev_maj = major(file_hdr.c_filesize as u32) as i32;
-                file_hdr.c_rdev_min = minor(file_hdr.c_filesize as u32) as u32;
+                file_hdr.c_rdev_maj = major(file_hdr.c_filesize as u64) as i32;
+                file_hdr.c_rdev_min = minor(file_hdr.c_filesize as u64);
                 file_hdr.c_filesize = 0;
             }
         }
@@ -1504,6 +1552,248 @@ fn swab_array(ptr: &mut [u8], count: usize) {
     }
 }
 
+// 重放续传日志，恢复延迟的硬链接、目录属性和符号链接占位文件，
+// 返回最后一个完成的成员之后的归档偏移
+fn replay_journal(records: Vec<JournalRecord>) -> u64 {
+    let mut resume_offset = 0;
+
+    for record in records {
+        match record {
+            JournalRecord::Done { offset, .. } => resume_offset = offset,
+            JournalRecord::Inode {
+                ino,
+                maj,
+                min,
+                name,
+            } => {
+                add_inode(ino, name, maj, min);
+            }
+            JournalRecord::Defer(header) => defer_copyin(&header),
+            JournalRecord::Linked { ino, maj, min } => {
+                COPYIN_DEFERMENTS.lock().unwrap().retain(|d| {
+                    d.header.c_ino != ino
+                        || d.header.c_dev_maj as u64 != maj
+                        || d.header.c_dev_min as u64 != min
+                });
+            }
+            JournalRecord::Undefer(name) => {
+                let mut deferments = COPYIN_DEFERMENTS.lock().unwrap();
+                if let Some(i) = deferments
+                    .iter_mut()
+                    .position(|d| d.header.get_c_name() == name)
+                {
+                    deferments.remove(i);
+                }
+            }
+            JournalRecord::Dir { invert, stat } => delay_cpio_set_stat(&stat, invert),
+            JournalRecord::Repair { mode, name } => {
+                let mut file_hdr = CpioFileStat::new();
+                file_hdr.c_mode = mode;
+                cpio_set_c_name(&mut file_hdr, &name);
+                repair_delayed_set_stat(&mut file_hdr);
+            }
+            JournalRecord::Symlink {
+                dev,
+                ino,
+                mode,
+                uid,
+                gid,
+                mtime,
+                source,
+                target,
+            } => {
+                GLOBAL_DELAYED_LINK.lock().unwrap().insert(
+                    DelayedLinkKey { dev, ino },
+                    DelayedLinkValue {
+                        mode,
+                        uid,
+                        gid,
+                        mtime,
+                        source,
+                        target,
+                    },
+                );
+            }
+        }
+    }
+    resume_offset
+}
+
+// 按 --from-charset/--to-charset 转换成员名和 tar 头部中的链接名，
+// 不能转换时返回 false，这个成员被跳过
+fn convert_member_names(file_hdr: &mut CpioFileStat) -> bool {
+    let Some(name) = charset_convert_name(&file_hdr.get_c_name()) else {
+        return false;
+    };
+    if let Some(ref link_name) = file_hdr.c_tar_linkname {
+        let Some(link_name) = charset_convert_name(link_name) else {
+            return false;
+        };
+        file_hdr.c_tar_linkname = Some(link_name);
+    }
+    cpio_set_c_name(file_hdr, name);
+    true
+}
+
+// 按 --strip-components 和 --transform 改写成员名和 tar 头部中的链接名，
+// 成员名或硬链接的目标改写后为空时返回 false
+fn rewrite_member_names(file_hdr: &mut CpioFileStat) -> bool {
+    let Some(name) = adjust_member_path(&file_hdr.get_c_name(), false) else {
+        return false;
+    };
+    let Some(name) = transform_member_name(&name) else {
+        return false;
+    };
+    if let Some(ref link_name) = file_hdr.c_tar_linkname {
+        if !link_name.is_empty() {
+            let link_name = if (file_hdr.c_mode & CP_IFMT) == CP_IFLNK {
+                transform_name(link_name, NameKind::Symlink)
+            } else {
+                let Some(link_name) = adjust_member_path(link_name, true) else {
+                    return false;
+                };
+                transform_name(&link_name, NameKind::Hardlink)
+            };
+            file_hdr.c_tar_linkname = Some(link_name);
+        }
+    }
+    cpio_set_c_name(file_hdr, name);
+    true
+}
+
+// 检查输入的当前位置是否是一个成员的头部
+fn at_member_header(input_tape: &mut MutexGuard<TapeInput>, in_des: &mut File) -> bool {
+    let mut buf = [0u8; 512];
+    let peeked_bytes = tape_buffered_peek(input_tape, &mut buf, in_des, 512) as usize;
+    let cpio_magic = if peeked_bytes >= 6 { &buf[..6] } else { &[] };
+    let binary = peeked_bytes >= 2 && {
+        let us = u16::from_le_bytes([buf[0], buf[1]]);
+        us == 0o070707 || us == swab_short(0o070707)
+    };
+    // is_tar_header 会对不是数字的校验和报错，放到最后检查
+    let tar = || peeked_bytes >= 512 && is_tar_header(&buf) != 0;
+
+    match get_archive_format() {
+        ArchiveFormat::Newascii => cpio_magic == b"070701",
+        ArchiveFormat::Crcascii => cpio_magic == b"070702",
+        ArchiveFormat::Newxascii => cpio_magic == b"070703",
+        ArchiveFormat::Oldascii | ArchiveFormat::Hpoldascii => cpio_magic == b"070707",
+        ArchiveFormat::Binary | ArchiveFormat::Hpbinary => binary,
+        ArchiveFormat::Tar | ArchiveFormat::Ustar => tar(),
+        ArchiveFormat::Unknown => {
+            matches!(cpio_magic, b"070701" | b"070702" | b"070703" | b"070707") || binary || tar()
+        }
+    }
+}
+
+// 读出 ENTRIES 中每个成员的头部，与索引中的记录比较
+fn verify_index(
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    archive_size: Option<u64>,
+    entries: &[IndexEntry],
+) -> Result<(), &'static str> {
+    const STALE: &str = "index does not match the archive";
+    let mut file_hdr = CpioFileStat::new();
+
+    if !input_tape.input_is_seekable {
+        return Err("archive is not seekable");
+    }
+    if let Some(size) = archive_size {
+        if !isrmt(in_des) && in_des.metadata().map(|st| st.len()).ok() != Some(size) {
+            return Err(STALE);
+        }
+    }
+    for entry in entries {
+        if archive_size.is_some_and(|size| entry.body_offset > size) {
+            return Err(STALE);
+        }
+        if !tape_lseek_input_to(input_tape, in_des, entry.header_offset) {
+            return Err("archive is not seekable");
+        }
+        if !at_member_header(input_tape, in_des) {
+            return Err(STALE);
+        }
+        set_swapping_bytes(false);
+        set_swapping_halfwords(false);
+        let header_start = read_in_header(input_tape, &mut file_hdr, in_des);
+        if header_start != entry.header_offset
+            || (input_tape.input_bytes - input_tape.input_size) as u64 != entry.body_offset
+            || file_hdr.get_c_name() != entry.name
+            || file_hdr.c_filesize as u64 != entry.size
+            || file_hdr.c_mode != entry.mode
+            || file_hdr.c_mtime != entry.mtime
+        {
+            return Err(STALE);
+        }
+        // 这两种格式中硬链接的数据只保存在最后一个链接中，不能单独取出
+        if file_hdr.c_nlink > 1
+            && (file_hdr.c_mode & CP_IFMT) == CP_IFREG
+            && (get_archive_format() == ArchiveFormat::Newascii
+                || get_archive_format() == ArchiveFormat::Newxascii
+                || get_archive_format() == ArchiveFormat::Crcascii)
+        {
+            return Err("index cannot be used for hard-linked members");
+        }
+    }
+    Ok(())
+}
+
+// 根据索引找出与模式匹配的成员在归档中的偏移。索引不能使用时给出警告并
+// 返回 None，这时按顺序读取整个归档
+fn plan_index_jumps(
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    index_file: &str,
+) -> Option<VecDeque<u64>> {
+    let start = (input_tape.input_bytes - input_tape.input_size) as u64;
+    let archive_format = get_archive_format();
+    let crc_i_flag = get_crc_i_flag();
+
+    let result = match index_load(index_file) {
+        Some(index) => {
+            let mut entries: Vec<IndexEntry> = index
+                .entries
+                .into_iter()
+                .filter(|entry| {
+                    if entry.header_offset < start {
+                        return false;
+                    }
+                    // 和顺序读取时一样先处理开头的 '/' 和 "../"，再转换字符集；
+                    // 不能转换的名字留给顺序读取时报告
+                    let mut name = entry.name.clone();
+                    cpio_safer_name_suffix(&mut name, false, !get_no_abs_paths_flag(), false);
+                    charset_transcode(&name).map_or(true, |name| name_selected(&name))
+                })
+                .collect();
+            entries.sort_by_key(|entry| entry.header_offset);
+            entries.dedup_by_key(|entry| entry.header_offset);
+            verify_index(input_tape, in_des, index.archive_size, &entries)
+                .map(|_| entries.iter().map(|entry| entry.header_offset).collect())
+        }
+        None => Err("index not usable"),
+    };
+
+    if input_tape.input_bytes - input_tape.input_size != start as usize
+        && !tape_lseek_input_to(input_tape, in_des, start)
+    {
+        error(PAXEXIT_FAILURE, 0, format_args!("cannot seek on input"));
+    }
+    match result {
+        Ok(plan) => Some(plan),
+        Err(reason) => {
+            set_archive_format(archive_format);
+            set_crc_i_flag(crc_i_flag);
+            error(
+                0,
+                0,
+                format_args!("{}: {}, reading the whole archive", index_file, reason),
+            );
+            None
+        }
+    }
+}
+
 pub fn process_copy_in() -> io::Result<()> {
     let mut tty_in: Option<File> = None;
     let mut tty_out: Option<File> = None;
@@ -1519,10 +1809,6 @@ pub fn process_copy_in() -> io::Result<()> {
     set_newdir_umask(unsafe { umask(0) });
 
     // Initialize the copy in
-    if get_pattern_file_name().is_some() {
-        read_pattern_file();
-    }
-
     if let Some(rename_batch_file) = get_rename_batch_file() {
         rename_in = Some(File::open(rename_batch_file)?);
         if rename_in.is_none() {
@@ -1564,7 +1850,9 @@ pub fn process_copy_in() -> io::Result<()> {
 
     // Check if input is a tape
     if isrmt(&mut in_file_des) {
-        input_tape.input_is_seekable = false;
+        // rmt 不能告诉我们远程归档是不是普通文件，远程磁带上的 lseek 可能不出错
+        // 却没有移动磁带，所以只有用户指定 --seek-remote 时才定位
+        input_tape.input_is_seekable = get_seek_remote_flag();
         input_tape.input_is_special = true;
     } else {
         let stat = fstat(in_file_des.as_raw_fd())?;
@@ -1577,11 +1865,50 @@ pub fn process_copy_in() -> io::Result<()> {
 
     change_dir();
 
+    let transactional = get_transactional_flag() && !get_table_flag() && !get_append_flag();
+    if transactional {
+        transaction_begin();
+    }
+
+    let resume_journal = get_resume_journal().filter(|_| !get_table_flag() && !get_append_flag());
+    let mut last_member: Option<OsString> = None;
+    if let Some(ref journal) = resume_journal {
+        let resume_offset = journal_resume(journal, None, replay_journal);
+        if resume_offset > 0 {
+            tape_seek_input(&mut input_tape, &mut in_file_des, resume_offset);
+        }
+    }
+
+    let build_index = get_build_index();
+    let mut index_plan = None;
+    if let Some(index_file) = get_index_file() {
+        // 只有给出了要取出的成员时索引才有用
+        if get_num_patterns() > 0 && get_copy_matching_files() {
+            index_plan = plan_index_jumps(&mut input_tape, &mut in_file_des, &index_file);
+        }
+    }
+
     // Process each file in the archive
     loop {
+        // 上一个成员已处理完，记录下一个成员在归档中的位置
+        if let Some(name) = last_member.take() {
+            journal_record(&JournalRecord::Done {
+                offset: (input_tape.input_bytes - input_tape.input_size) as u64,
+                name,
+            });
+        }
+
+        // 按索引直接跳到下一个要处理的成员
+        if let Some(ref mut plan) = index_plan {
+            let Some(offset) = plan.pop_front() else {
+                break;
+            };
+            tape_seek_input(&mut input_tape, &mut in_file_des, offset);
+        }
+
         set_swapping_bytes(false);
         set_swapping_halfwords(false);
-        read_in_header(&mut input_tape, &mut file_hdr, &mut in_file_des);
+        let header_start = read_in_header(&mut input_tape, &mut file_hdr, &mut in_file_des);
 
         if file_hdr.c_namesize == 0 {
             skip_file = true;
@@ -1590,30 +1917,34 @@ pub fn process_copy_in() -> io::Result<()> {
             if name == CPIO_TRAILER_NAME {
                 break;
             }
+            if resume_journal.is_some() {
+                last_member = Some(name.clone());
+            }
+            if build_index.is_some() {
+                index_record(IndexEntry {
+                    header_offset: header_start,
+                    body_offset: (input_tape.input_bytes - input_tape.input_size) as u64,
+                    size: file_hdr.c_filesize as u64,
+                    mode: file_hdr.c_mode,
+                    mtime: file_hdr.c_mtime,
+                    name: name.clone(),
+                });
+            }
 
-            cpio_safer_name_suffix(&mut name.clone(), false, !get_no_abs_paths_flag(), false);
-
-            let num_patterns = get_num_patterns();
+            let mut safe_name = name.clone();
+            cpio_safer_name_suffix(&mut safe_name, false, !get_no_abs_paths_flag(), false);
+            cpio_set_c_name(&mut file_hdr, &safe_name);
 
-            if get_num_patterns() <= 0 {
-                skip_file = false;
-            } else {
-                skip_file = get_copy_matching_files();
-                for i in 0..num_patterns {
-                    let pattern_cstr =
-                        std::ffi::CString::new(get_save_patterns()[i as usize].as_str()).unwrap();
-                    let name_cstr = std::ffi::CString::new(name.clone()).unwrap();
-                    if unsafe { fnmatch(pattern_cstr.as_ptr(), name_cstr.as_ptr(), 0) } == 0 {
-                        skip_file = !get_copy_matching_files();
-                        break;
-                    }
-                }
-            }
+            skip_file = !convert_member_names(&mut file_hdr)
+                || !name_selected(&file_hdr.get_c_name())
+                || !predicate_selected(&file_hdr)
+                || !rewrite_member_names(&mut file_hdr);
         }
 
         if skip_file {
             if file_hdr.c_nlink > 1
                 && (get_archive_format() == ArchiveFormat::Newascii
+                    || get_archive_format() == ArchiveFormat::Newxascii
                     || get_archive_format() == ArchiveFormat::Crcascii)
             {
                 if create_defered_links_to_skipped(
@@ -1626,7 +1957,7 @@ pub fn process_copy_in() -> io::Result<()> {
                     tape_toss_input(
                         &mut input_tape,
                         &mut in_file_des,
-                        file_hdr.c_filesize as i32,
+                        file_hdr.c_filesize as u64,
                     );
                     tape_skip_padding(
                         &mut input_tape,
@@ -1638,7 +1969,7 @@ pub fn process_copy_in() -> io::Result<()> {
                 tape_toss_input(
                     &mut input_tape,
                     &mut in_file_des,
-                    file_hdr.c_filesize as i32,
+                    file_hdr.c_filesize as u64,
                 );
                 tape_skip_padding(
                     &mut input_tape,
@@ -1652,7 +1983,7 @@ pub fn process_copy_in() -> io::Result<()> {
             tape_toss_input(
                 &mut input_tape,
                 &mut in_file_des,
-                file_hdr.c_filesize as i32,
+                file_hdr.c_filesize as u64,
             );
             tape_skip_padding(
                 &mut input_tape,
@@ -1667,7 +1998,7 @@ pub fn process_copy_in() -> io::Result<()> {
                 tape_toss_input(
                     &mut input_tape,
                     &mut in_file_des,
-                    file_hdr.c_filesize as i32,
+                    file_hdr.c_filesize as u64,
                 );
                 tape_skip_padding(
                     &mut input_tape,
@@ -1680,7 +2011,7 @@ pub fn process_copy_in() -> io::Result<()> {
             tape_toss_input(
                 &mut input_tape,
                 &mut in_file_des,
-                file_hdr.c_filesize as i32,
+                file_hdr.c_filesize as u64,
             );
             tape_skip_padding(
                 &mut input_tape,
@@ -1690,13 +2021,13 @@ pub fn process_copy_in() -> io::Result<()> {
             if crc != file_hdr.c_chksum {
                 eprintln!(
                     "{}: checksum error (0x{:x}, should be 0x{:x})",
-                    file_hdr.get_c_name(),
+                    quote_name(&file_hdr.get_c_name()),
                     crc,
                     file_hdr.c_chksum
                 );
             }
             if get_verbose_flag() {
-                eprintln!("{}", file_hdr.get_c_name());
+                eprintln!("{}", quote_name(&file_hdr.get_c_name()));
             }
             if get_dot_flag() {
                 eprint!(".");
@@ -1705,15 +2036,15 @@ pub fn process_copy_in() -> io::Result<()> {
             if (get_rename_flag() || get_rename_batch_file().is_some())
                 && query_rename(
                     &mut file_hdr,
-                    tty_in.as_mut().unwrap(),
-                    tty_out.as_mut().unwrap(),
-                    rename_in.as_mut().unwrap(),
+                    tty_in.as_mut(),
+                    tty_out.as_mut(),
+                    rename_in.as_mut(),
                 ) < 0
             {
                 tape_toss_input(
                     &mut input_tape,
                     &mut in_file_des,
-                    file_hdr.c_filesize as i32,
+                    file_hdr.c_filesize as u64,
                 );
                 tape_skip_padding(
                     &mut input_tape,
@@ -1731,7 +2062,7 @@ pub fn process_copy_in() -> io::Result<()> {
             );
 
             if get_verbose_flag() {
-                eprintln!("{}", file_hdr.get_c_name());
+                eprintln!("{}", quote_name(&file_hdr.get_c_name()));
             }
             if get_dot_flag() {
                 eprint!(".");
@@ -1743,15 +2074,35 @@ pub fn process_copy_in() -> io::Result<()> {
         eprintln!();
     }
 
-    replace_symlink_placeholders();
-    apply_delayed_set_stat();
-
-    if !get_append_flag() {
+    if transactional {
+        // 先补齐延迟的硬链接，提交成功后才设置目录属性
         if get_archive_format() == ArchiveFormat::Newascii
+            || get_archive_format() == ArchiveFormat::Newxascii
             || get_archive_format() == ArchiveFormat::Crcascii
         {
             create_final_defers();
         }
+        if !transaction_commit() {
+            error(
+                PAXEXIT_FAILURE,
+                0,
+                format_args!("extraction failed, no files were changed"),
+            );
+        }
+        apply_delayed_set_stat();
+    } else {
+        replace_symlink_placeholders();
+        apply_delayed_set_stat();
+    }
+
+    if !get_append_flag() {
+        if !transactional
+            && (get_archive_format() == ArchiveFormat::Newascii
+                || get_archive_format() == ArchiveFormat::Newxascii
+                || get_archive_format() == ArchiveFormat::Crcascii)
+        {
+            create_final_defers();
+        }
         if !get_quiet_flag() {
             let blocks = (input_tape.input_bytes + get_io_block_size() as usize - 1)
                 / get_io_block_size() as usize;
@@ -1759,6 +2110,20 @@ pub fn process_copy_in() -> io::Result<()> {
         }
     }
 
+    if let Some(ref journal) = resume_journal {
+        journal_finish(journal);
+    }
+
+    // 追加时由 copy-out 在写完新成员后写出索引
+    if let (Some(index_file), false) = (build_index, get_append_flag()) {
+        let archive_size = if input_tape.input_is_seekable && !isrmt(&mut in_file_des) {
+            Some(in_file_des.metadata()?.len())
+        } else {
+            None
+        };
+        index_finish(&index_file, archive_size);
+    }
+
     input_tape.free();
 
     Ok(())
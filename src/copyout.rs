@@ -9,12 +9,15 @@
     clippy::useless_format
 )]
 
-use std::ffi::{CStr, CString};
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::os::fd::{FromRawFd, RawFd};
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
 use std::path::Path;
+use std::process::{Child, Command, Stdio};
 use std::sync::MutexGuard;
 use std::{ptr, slice};
 
@@ -22,9 +25,11 @@ use libc::mkstemp;
 use nix::libc::{self, dev_t};
 
 use pax::paxerror::*;
+use pax::paxlib::PAXEXIT_FAILURE;
 use pax::rmt::isrmt;
 
 use crate::appargs::*;
+use crate::charset::*;
 use crate::copyin::process_copy_in;
 use crate::cpiohdr::*;
 use crate::dstring::*;
@@ -32,14 +37,23 @@ use crate::externs::*;
 use crate::filetype::*;
 use crate::filetype::{CP_IFBLK, CP_IFCHR, CP_IFIFO, CP_IFMT, CP_IFSOCK};
 use crate::global::*;
+use crate::idmap::map_header_ids;
+use crate::index::*;
 use crate::initramfs::*;
+use crate::namelist::*;
+use crate::pattern::name_selected;
+use crate::predicate::predicate_selected;
+use crate::readahead::ReadAhead;
+use crate::resume::*;
+use crate::rootless::apply_override_stat;
 use crate::tar::*;
+use crate::transform::*;
 use crate::util::*;
 
 use gnu::error::*;
 use gnu::umaxtostr::*;
 
-fn read_for_checksum(in_file_des: &mut File, file_size: u64, file_name: &str) -> u32 {
+fn read_for_checksum(in_file_des: &mut File, file_size: u64, file_name: &OsStr) -> u32 {
     let mut crc = 0;
     let mut buf = [0u8; 1024];
     let mut remaining = file_size;
@@ -54,7 +68,7 @@ fn read_for_checksum(in_file_des: &mut File, file_size: u64, file_name: &str) ->
                 remaining -= bytes_read as u64;
             }
             Err(e) => {
-                eprintln!("Error reading file {}: {}", file_name, e);
+                eprintln!("Error reading file {}: {}", quote_name(file_name), e);
                 return 0;
             }
         }
@@ -62,7 +76,7 @@ fn read_for_checksum(in_file_des: &mut File, file_size: u64, file_name: &str) ->
     match in_file_des.seek(io::SeekFrom::Start(0)) {
         Ok(_) => {}
         Err(e) => {
-            eprintln!("Error seeking in file {}: {}", file_name, e);
+            eprintln!("Error seeking in file {}: {}", quote_name(file_name), e);
             return 0;
         }
     }
@@ -83,7 +97,9 @@ fn tape_clear_rest_of_block(output_tape: &mut MutexGuard<TapeOutput>, out_file_d
 
 fn tape_pad_output(output_tape: &mut MutexGuard<TapeOutput>, out_file_des: &mut File, offset: u64) {
     let pad = match get_archive_format() {
-        ArchiveFormat::Newascii | ArchiveFormat::Crcascii => (4 - (offset % 4)) % 4,
+        ArchiveFormat::Newascii | ArchiveFormat::Newxascii | ArchiveFormat::Crcascii => {
+            (4 - (offset % 4)) % 4
+        }
         ArchiveFormat::Tar | ArchiveFormat::Ustar => (512 - (offset % 512)) % 512,
         _ => (2 - (offset % 2)) % 2,
     };
@@ -119,6 +135,7 @@ fn last_link(file_hdr: &CpioFileStat) -> bool {
     file_hdr.c_nlink == count_defered_links_to_dev_ino(file_hdr) + 1
 }
 fn add_link_defer(file_hdr: &CpioFileStat) {
+    journal_record(&JournalRecord::Defer(file_hdr.clone()));
     let mut global_deferments = GLOBAL_DEFERMENTS.lock().unwrap();
     let deferment = Deferment::new(file_hdr);
 
@@ -134,6 +151,12 @@ fn writeout_other_defers(
     let maj = file_hdr.c_dev_maj;
     let min = file_hdr.c_dev_min;
 
+    journal_record(&JournalRecord::Linked {
+        ino,
+        maj: maj as u64,
+        min: min as u64,
+    });
+
     let mut deferments = GLOBAL_DEFERMENTS.lock().unwrap();
     let mut prev_index: Option<usize> = None;
     let mut i = 0;
@@ -182,6 +205,10 @@ fn writeout_defered_file(
         .custom_flags(libc::O_RDONLY)
         .open(path)
         .unwrap();
+    // 头部中的修改时间可能已被 --mtime 改写，与文件本身的比较
+    let mtime = in_file_des
+        .metadata()
+        .map_or(file_hdr.c_mtime, |st| st.mtime());
 
     if get_archive_format() == ArchiveFormat::Crcascii {
         file_hdr.c_chksum =
@@ -197,16 +224,12 @@ fn writeout_defered_file(
         input_tape,
         &mut in_file_des,
         out_file_des,
-        file_hdr.c_filesize as i32,
-        c_name.as_str(),
-    );
-
-    warn_if_file_changed(
-        c_name.as_str(),
         file_hdr.c_filesize as u64,
-        file_hdr.c_mtime as u64,
+        &c_name,
     );
 
+    warn_if_file_changed(&c_name, file_hdr.c_filesize as u64, mtime);
+
     if get_archive_format() == ArchiveFormat::Tar || get_archive_format() == ArchiveFormat::Ustar {
         add_inode(
             file_hdr.c_ino,
@@ -221,7 +244,7 @@ fn writeout_defered_file(
     if get_reset_time_flag() {
         set_file_times(
             Some(&in_file_des),
-            c_name.as_str(),
+            &c_name,
             file_hdr.c_mtime,
             file_hdr.c_mtime,
             0,
@@ -234,7 +257,7 @@ fn writeout_final_defers(
     input_tape: &mut MutexGuard<TapeInput>,
     out_des: &mut File,
 ) {
-    let global_deferments = GLOBAL_DEFERMENTS.lock().unwrap();
+    let mut global_deferments = GLOBAL_DEFERMENTS.lock().unwrap();
 
     for deferment in global_deferments.iter() {
         let other_count =
@@ -250,18 +273,68 @@ fn writeout_final_defers(
             write_out_header(output_tape, &mut file_hdr, out_des);
         }
     }
+    global_deferments.clear();
 }
 
-fn field_width_error(filename: &str, fieldname: &str, value: u64, width: usize, nul: bool) {
+// --list-directives：读到下一个文件名为止，执行途中遇到的指令
+fn next_listed_name(
+    output_tape: &mut MutexGuard<TapeOutput>,
+    input_tape: &mut MutexGuard<TapeInput>,
+    out_des: &mut File,
+    list_file: &mut File,
+    name: &mut DynamicString,
+    list: &mut ListDirectives,
+) -> Option<()> {
+    loop {
+        if ds_fgetstr(list_file, name, get_name_end() as u8).is_none() {
+            // 剩下的硬链接组按最后的目录打开，然后回到开始时的目录
+            if list.dir_changed() {
+                writeout_final_defers(output_tape, input_tape, out_des);
+                list.restore_dir();
+            }
+            return None;
+        }
+        match parse_list_line(&name.ds_string[..name.ds_idx]) {
+            Ok(ListLine::Name) => return Some(()),
+            Ok(ListLine::Skip) => {}
+            Ok(ListLine::ChangeDir(dir)) => {
+                // 未写出的硬链接组要按当前目录中的名字打开，换目录之前写出
+                writeout_final_defers(output_tape, input_tape, out_des);
+                list.change_dir(dir);
+            }
+            Ok(ListLine::Owner(spec)) => list.set_owner(spec),
+            Err(e) => error(PAXEXIT_FAILURE, 0, format_args!("{}", e)),
+        }
+    }
+}
+
+fn field_width_error(
+    filename: &str,
+    fieldname: &str,
+    value: u64,
+    width: usize,
+    logbase: u32,
+    nul: bool,
+) {
     eprintln!(
         "{}: value {} {} out of allowed range 0..{}",
         filename,
         fieldname,
         value,
-        (1 << ((width - nul as usize) * 3)) - 1
+        u64::MAX >> (64 - (width - nul as usize) as u32 * logbase)
     );
 }
 
+// 成员在当前格式中放不下时提示改用 newcx 格式
+fn suggest_newcx(filename: &str) {
+    if get_archive_format() != ArchiveFormat::Newxascii {
+        eprintln!(
+            "{}: use '-H newcx' to archive files of 4 GiB or more and times before 1970 or after 2106",
+            filename
+        );
+    }
+}
+
 fn field_width_warning(filename: &str, fieldname: &str) {
     if get_warn_option() as usize & CPIO_WARN_TRUNCATE != 0 {
         error(0, 0, format_args!("{}: truncating {}", filename, fieldname));
@@ -290,7 +363,7 @@ fn to_ascii_or_error(
     fieldname: &str,
 ) -> bool {
     if to_ascii(where_, n, digits, logbase, false) {
-        field_width_error(filename, fieldname, n, digits, false);
+        field_width_error(filename, fieldname, n, digits, logbase, false);
         true
     } else {
         false
@@ -303,9 +376,12 @@ fn write_out_new_ascii_header(
     file_hdr: &mut CpioFileStat,
     out_des: &mut File,
 ) -> i32 {
-    let mut ascii_header = [0u8; 110];
+    // newcx 格式的修改时间和文件大小各占 16 位，之后的字段依次后移
+    let time_digits = if magic_string == "070703" { 16 } else { 8 };
+    let x = (time_digits - 8) * 2;
+    let mut ascii_header = vec![0u8; 110 + x];
 
-    let c_name = file_hdr.get_c_name();
+    let c_name = quote_name(&file_hdr.get_c_name());
 
     // Write magic string directly to the array
     ascii_header[0..6].copy_from_slice(magic_string.as_bytes());
@@ -349,26 +425,30 @@ fn write_out_new_ascii_header(
         &c_name,
         "number of links",
     );
-    to_ascii_or_warn(
-        &mut ascii_header[46..54],
+    if to_ascii(
+        &mut ascii_header[46..46 + time_digits],
         file_hdr.c_mtime as u64,
-        8,
+        time_digits,
         4,
-        &c_name,
-        "modification time",
-    );
+        false,
+    ) && get_warn_option() as usize & CPIO_WARN_TRUNCATE != 0
+    {
+        field_width_warning(&c_name, "modification time");
+        suggest_newcx(&c_name);
+    }
     if to_ascii_or_error(
-        &mut ascii_header[54..62],
+        &mut ascii_header[46 + time_digits..46 + time_digits * 2],
         file_hdr.c_filesize as u64,
-        8,
+        time_digits,
         4,
         &c_name,
         "file size",
     ) {
+        suggest_newcx(&c_name);
         return 1;
     }
     if to_ascii_or_error(
-        &mut ascii_header[62..70],
+        &mut ascii_header[62 + x..70 + x],
         file_hdr.c_dev_maj as u64,
         8,
         4,
@@ -378,7 +458,7 @@ fn write_out_new_ascii_header(
         return 1;
     }
     if to_ascii_or_error(
-        &mut ascii_header[70..78],
+        &mut ascii_header[70 + x..78 + x],
         file_hdr.c_dev_min as u64,
         8,
         4,
@@ -388,7 +468,7 @@ fn write_out_new_ascii_header(
         return 1;
     }
     if to_ascii_or_error(
-        &mut ascii_header[78..86],
+        &mut ascii_header[78 + x..86 + x],
         file_hdr.c_rdev_maj as u64,
         8,
         4,
@@ -398,7 +478,7 @@ fn write_out_new_ascii_header(
         return 1;
     }
     if to_ascii_or_error(
-        &mut ascii_header[86..94],
+        &mut ascii_header[86 + x..94 + x],
         file_hdr.c_rdev_min as u64,
         8,
         4,
@@ -408,7 +488,7 @@ fn write_out_new_ascii_header(
         return 1;
     }
     if to_ascii_or_error(
-        &mut ascii_header[94..102],
+        &mut ascii_header[94 + x..102 + x],
         file_hdr.c_namesize as u64,
         8,
         4,
@@ -418,19 +498,15 @@ fn write_out_new_ascii_header(
         return 1;
     }
     to_ascii(
-        &mut ascii_header[102..110],
+        &mut ascii_header[102 + x..110 + x],
         file_hdr.c_chksum as u64,
         8,
         4,
         false,
     );
 
-    tape_buffered_write(
-        output_tape,
-        &mut ascii_header.to_vec(),
-        out_des,
-        ascii_header.len(),
-    );
+    let header_size = ascii_header.len();
+    tape_buffered_write(output_tape, &mut ascii_header, out_des, header_size);
     tape_buffered_write(
         output_tape,
         &mut file_hdr.c_name.clone(),
@@ -455,22 +531,16 @@ fn write_out_old_ascii_header(
 ) -> i32 {
     let mut ascii_header = [0u8; 76];
     let p = &mut ascii_header[..];
+    let c_name = quote_name(&file_hdr.get_c_name());
 
     to_ascii(&mut p[0..6], file_hdr.c_magic as u64, 6, 3, false);
-    to_ascii_or_warn(
-        &mut p[6..12],
-        dev,
-        6,
-        3,
-        &file_hdr.get_c_name(),
-        "device number",
-    );
+    to_ascii_or_warn(&mut p[6..12], dev, 6, 3, &c_name, "device number");
     to_ascii_or_warn(
         &mut p[12..18],
         file_hdr.c_ino,
         6,
         3,
-        &file_hdr.get_c_name(),
+        &c_name,
         "inode number",
     );
     to_ascii_or_warn(
@@ -478,40 +548,29 @@ fn write_out_old_ascii_header(
         file_hdr.c_mode as u64,
         6,
         3,
-        &file_hdr.get_c_name(),
+        &c_name,
         "file mode",
     );
-    to_ascii_or_warn(
-        &mut p[24..30],
-        file_hdr.c_uid as u64,
-        6,
-        3,
-        &file_hdr.get_c_name(),
-        "uid",
-    );
-    to_ascii_or_warn(
-        &mut p[30..36],
-        file_hdr.c_gid as u64,
-        6,
-        3,
-        &file_hdr.get_c_name(),
-        "gid",
-    );
+    to_ascii_or_warn(&mut p[24..30], file_hdr.c_uid as u64, 6, 3, &c_name, "uid");
+    to_ascii_or_warn(&mut p[30..36], file_hdr.c_gid as u64, 6, 3, &c_name, "gid");
     to_ascii_or_warn(
         &mut p[36..42],
         file_hdr.c_nlink as u64,
         6,
         3,
-        &file_hdr.get_c_name(),
+        &c_name,
         "number of links",
     );
-    to_ascii_or_warn(&mut p[42..48], rdev, 6, 3, &file_hdr.get_c_name(), "rdev");
+    // 设备号截断后会在解包时创建错误的设备文件，因此报错而不是警告
+    if to_ascii_or_error(&mut p[42..48], rdev, 6, 3, &c_name, "rdev") {
+        return 1;
+    }
     to_ascii_or_warn(
         &mut p[48..59],
         file_hdr.c_mtime as u64,
         11,
         3,
-        &file_hdr.get_c_name(),
+        &c_name,
         "modification time",
     );
     if to_ascii_or_error(
@@ -519,7 +578,7 @@ fn write_out_old_ascii_header(
         file_hdr.c_namesize as u64,
         6,
         3,
-        &file_hdr.get_c_name(),
+        &c_name,
         "name size",
     ) {
         return 1;
@@ -529,9 +588,10 @@ fn write_out_old_ascii_header(
         file_hdr.c_filesize as u64,
         11,
         3,
-        &file_hdr.get_c_name(),
+        &c_name,
         "file size",
     ) {
+        suggest_newcx(&c_name);
         return 1;
     }
 
@@ -554,14 +614,13 @@ fn write_out_old_ascii_header(
 fn hp_compute_dev(file_hdr: &mut CpioFileStat, pdev: &mut dev_t, prdev: &mut dev_t) {
     match file_hdr.c_mode & CP_IFMT {
         CP_IFCHR | CP_IFBLK | CP_IFSOCK | CP_IFIFO => {
-            file_hdr.c_filesize =
-                makedev(file_hdr.c_rdev_maj as u8, file_hdr.c_rdev_min as u8) as i64;
+            file_hdr.c_filesize = makedev(file_hdr.c_rdev_maj as u32, file_hdr.c_rdev_min) as i64;
             *pdev = makedev(0, 1) as dev_t;
             *prdev = makedev(0, 1) as dev_t;
         }
         _ => {
-            *pdev = makedev(file_hdr.c_rdev_maj as u8, file_hdr.c_rdev_min as u8) as dev_t;
-            *prdev = makedev(file_hdr.c_rdev_maj as u8, file_hdr.c_rdev_min as u8) as dev_t;
+            *pdev = makedev(file_hdr.c_dev_maj as u32, file_hdr.c_dev_min) as dev_t;
+            *prdev = makedev(file_hdr.c_rdev_maj as u32, file_hdr.c_rdev_min) as dev_t;
         }
     }
 }
@@ -575,9 +634,13 @@ fn write_out_binary_header(
     let mut short_hdr = OldCpioHeader::new();
 
     short_hdr.c_magic = 0o070707;
-    short_hdr.c_dev = makedev(file_hdr.c_dev_maj as u8, file_hdr.c_dev_min as u8) as u16;
+    let c_name = quote_name(&file_hdr.get_c_name());
 
-    let c_name = file_hdr.get_c_name().clone();
+    let dev = makedev(file_hdr.c_dev_maj as u32, file_hdr.c_dev_min);
+    short_hdr.c_dev = dev as u16;
+    if (short_hdr.c_dev as u64) != dev {
+        field_width_warning(&c_name, "device number");
+    }
 
     if (get_warn_option() as usize & CPIO_WARN_TRUNCATE) != 0 && (file_hdr.c_ino >> 16) != 0 {
         error(0, 0, format_args!("{}: truncating inode number", c_name));
@@ -609,6 +672,10 @@ fn write_out_binary_header(
     }
 
     short_hdr.c_rdev = rdev as u16;
+    if (short_hdr.c_rdev as dev_t) != rdev {
+        field_width_error(&c_name, "rdev", rdev, 4, 4, false);
+        return 1;
+    }
     short_hdr.c_mtimes[0] = (file_hdr.c_mtime >> 16) as u16;
     short_hdr.c_mtimes[1] = (file_hdr.c_mtime & 0xFFFF) as u16;
 
@@ -647,6 +714,7 @@ fn write_out_binary_header(
                 c_name, "file size", s, 0xFFFFu32
             ),
         );
+        suggest_newcx(&c_name);
         return 1;
     }
 
@@ -675,6 +743,66 @@ fn write_out_header(
     output_tape: &mut MutexGuard<TapeOutput>,
     file_hdr: &mut CpioFileStat,
     out_des: &mut File,
+) -> i32 {
+    let header_start = (output_tape.output_bytes + output_tape.output_size) as u64;
+    // 头部中写入转换后的名字，写完后恢复原来的名字，调用者还要用它访问文件
+    let orig_name = file_hdr.get_c_name();
+    let orig_linkname = file_hdr.c_tar_linkname.clone();
+    if orig_name != CPIO_TRAILER_NAME && !convert_header_names(file_hdr) {
+        return 1;
+    }
+    let ret = write_out_format_header(output_tape, file_hdr, out_des);
+
+    if ret == 0 && get_build_index().is_some() && file_hdr.get_c_name() != CPIO_TRAILER_NAME {
+        index_record(IndexEntry {
+            header_offset: header_start,
+            body_offset: (output_tape.output_bytes + output_tape.output_size) as u64,
+            size: file_hdr
+                .c_sparse
+                .as_ref()
+                .map_or(file_hdr.c_filesize as u64, |map| map.data_size()),
+            mode: file_hdr.c_mode,
+            mtime: file_hdr.c_mtime,
+            name: file_hdr.get_c_name(),
+        });
+    }
+    cpio_set_c_name(file_hdr, orig_name);
+    file_hdr.c_tar_linkname = orig_linkname;
+    ret
+}
+
+// 按 --strip-components、--prefix 和 --transform 改写成员名和硬链接指向的名字，
+// 再按 --from-charset/--to-charset 转换。符号链接的目标在读出时已经改写和转换
+fn convert_header_names(file_hdr: &mut CpioFileStat) -> bool {
+    let Some(name) = adjust_member_path(&file_hdr.get_c_name(), false) else {
+        return false;
+    };
+    let Some(name) = transform_member_name(&name) else {
+        return false;
+    };
+    let Some(name) = charset_convert_name(&name) else {
+        return false;
+    };
+    if (file_hdr.c_mode & CP_IFMT) != CP_IFLNK {
+        if let Some(ref link_name) = file_hdr.c_tar_linkname {
+            let Some(link_name) = adjust_member_path(link_name, true) else {
+                return false;
+            };
+            let link_name = transform_name(&link_name, NameKind::Hardlink);
+            let Some(link_name) = charset_convert_name(&link_name) else {
+                return false;
+            };
+            file_hdr.c_tar_linkname = Some(link_name);
+        }
+    }
+    cpio_set_c_name(file_hdr, name);
+    true
+}
+
+fn write_out_format_header(
+    output_tape: &mut MutexGuard<TapeOutput>,
+    file_hdr: &mut CpioFileStat,
+    out_des: &mut File,
 ) -> i32 {
     let mut dev: dev_t = 0;
     let mut rdev: dev_t = 0;
@@ -686,10 +814,13 @@ fn write_out_header(
         ArchiveFormat::Crcascii => {
             write_out_new_ascii_header(output_tape, "070702", file_hdr, out_des)
         }
+        ArchiveFormat::Newxascii => {
+            write_out_new_ascii_header(output_tape, "070703", file_hdr, out_des)
+        }
         ArchiveFormat::Oldascii => write_out_old_ascii_header(
             output_tape,
-            makedev(file_hdr.c_dev_maj as u8, file_hdr.c_dev_min as u8) as u64,
-            makedev(file_hdr.c_rdev_maj as u8, file_hdr.c_rdev_min as u8) as u64,
+            makedev(file_hdr.c_dev_maj as u32, file_hdr.c_dev_min),
+            makedev(file_hdr.c_rdev_maj as u32, file_hdr.c_rdev_min),
             file_hdr,
             out_des,
         ) as i32,
@@ -699,14 +830,14 @@ fn write_out_header(
         }
         ArchiveFormat::Tar | ArchiveFormat::Ustar => {
             if is_tar_filename_too_long(&file_hdr.get_c_name()) {
-                eprintln!("{}: file name too long", file_hdr.get_c_name());
+                eprintln!("{}: file name too long", quote_name(&file_hdr.get_c_name()));
                 return 1;
             }
             write_out_tar_header(output_tape, file_hdr, out_des)
         }
         ArchiveFormat::Binary => write_out_binary_header(
             output_tape,
-            makedev(file_hdr.c_rdev_maj as u8, file_hdr.c_rdev_min as u8) as dev_t,
+            makedev(file_hdr.c_rdev_maj as u32, file_hdr.c_rdev_min) as dev_t,
             file_hdr,
             out_des,
         ),
@@ -718,19 +849,18 @@ fn write_out_header(
     }
 }
 
-fn assign_string(pvar: &mut String, value: &str) {
-    pvar.clear();
-    let trimmed_name = value.trim_end_matches('\0');
-    pvar.push_str(trimmed_name);
+fn assign_string(pvar: &mut OsString, value: &[u8]) {
+    let len = value.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    *pvar = OsStr::from_bytes(&value[..len]).to_os_string();
 }
 
-fn write_xattrs(metadata_fd: RawFd, path: &str) -> i32 {
+fn write_xattrs(metadata_fd: RawFd, path: &OsStr) -> i32 {
     if metadata_fd < 0 {
         return 0;
     }
 
     // Convert path to CString for FFI calls
-    let c_path = match CString::new(path) {
+    let c_path = match CString::new(path.as_bytes()) {
         Ok(p) => p,
         Err(_) => return -libc::EINVAL,
     };
@@ -854,6 +984,93 @@ fn write_xattrs(metadata_fd: RawFd, path: &str) -> i32 {
     0
 }
 
+// 重放续传日志，恢复延迟的硬链接和 inode 表。返回最后一个完成的成员
+// 之后的归档偏移，以及已经处理过的文件名
+fn replay_journal(records: Vec<JournalRecord>) -> (u64, VecDeque<OsString>) {
+    let mut resume_offset = 0;
+    let mut done_names = VecDeque::new();
+
+    for record in records {
+        match record {
+            JournalRecord::Done { offset, name } => {
+                resume_offset = offset;
+                done_names.push_back(name);
+            }
+            JournalRecord::Inode {
+                ino,
+                maj,
+                min,
+                name,
+            } => {
+                add_inode(ino, name, maj, min);
+            }
+            JournalRecord::Defer(header) => add_link_defer(&header),
+            JournalRecord::Linked { ino, maj, min } => {
+                GLOBAL_DEFERMENTS.lock().unwrap().retain(|d| {
+                    d.header.c_ino != ino
+                        || d.header.c_dev_maj as u64 != maj
+                        || d.header.c_dev_min as u64 != min
+                });
+            }
+            _ => {}
+        }
+    }
+    (resume_offset, done_names)
+}
+
+// --compress-program：在另一个进程中运行 COMMAND，它的标准输出是原来的归档，
+// 之后写出的归档都经过管道送到它的标准输入，压缩和读取文件同时进行。返回
+// 压缩进程和换下来的归档
+fn start_compress_program(out_file_des: &mut File, command: &str) -> (Child, File) {
+    if isrmt(out_file_des) {
+        error(
+            PAXEXIT_FAILURE,
+            0,
+            format_args!("--compress-program cannot be used with a remote archive"),
+        );
+    }
+    let child = out_file_des.try_clone().and_then(|archive| {
+        Command::new("/bin/sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(archive)
+            .spawn()
+    });
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            error(
+                PAXEXIT_FAILURE,
+                e.raw_os_error().unwrap_or(0),
+                format_args!("cannot run {}", command),
+            );
+            unreachable!();
+        }
+    };
+    let pipe = File::from(OwnedFd::from(child.stdin.take().unwrap()));
+    (child, std::mem::replace(out_file_des, pipe))
+}
+
+// 换回原来的归档，关闭通向压缩程序的管道，等它写完归档
+fn finish_compress_program(out_file_des: &mut File, compressor: (Child, File), command: &str) {
+    let (mut child, archive) = compressor;
+    *out_file_des = archive;
+    match child.wait() {
+        Ok(status) if status.success() => {}
+        Ok(status) => error(
+            PAXEXIT_FAILURE,
+            0,
+            format_args!("{}: compress program failed ({})", command, status),
+        ),
+        Err(e) => error(
+            PAXEXIT_FAILURE,
+            e.raw_os_error().unwrap_or(0),
+            format_args!("{}: cannot wait for compress program", command),
+        ),
+    }
+}
+
 pub fn process_copy_out() -> io::Result<()> {
     let mut input_name = DYNAMIC_STRING_INITIALIZER;
 
@@ -862,7 +1079,7 @@ pub fn process_copy_out() -> io::Result<()> {
     let mut in_file_des: File;
     let mut out_file_des = get_archive_des()?;
 
-    let mut orig_file_name = String::new();
+    let mut orig_file_name = OsString::new();
     let mut template = "/tmp/cpio-metadata-XXXXXX".to_string();
 
     let mut ret: i32;
@@ -898,12 +1115,64 @@ pub fn process_copy_out() -> io::Result<()> {
         output_tape.output_is_seekable = metadata.file_type().is_file();
     }
 
-    if get_append_flag() {
+    let compress_program = get_compress_program();
+    let compressor = compress_program
+        .as_ref()
+        .map(|command| start_compress_program(&mut out_file_des, command));
+    if compressor.is_some() {
+        output_tape.output_is_special = false;
+        output_tape.output_is_seekable = false;
+    }
+
+    let resume_journal = get_resume_journal();
+    let mut resume_names = VecDeque::new();
+    let mut last_member: Option<OsString> = None;
+
+    if let Some(ref journal) = resume_journal {
+        // 只有已经写到归档文件中的成员才算完成
+        let limit = if output_tape.output_is_seekable {
+            out_file_des.metadata()?.len()
+        } else {
+            0
+        };
+        let resume_offset;
+        (resume_offset, resume_names) = journal_resume(journal, Some(limit), replay_journal);
+
+        if !resume_names.is_empty() {
+            set_last_header_start(resume_offset);
+            if let Err(e) = out_file_des.set_len(resume_offset) {
+                error(
+                    PAXEXIT_FAILURE,
+                    e.raw_os_error().unwrap_or(0),
+                    format_args!("cannot truncate output"),
+                );
+            }
+        } else if !get_append_flag()
+            && output_tape.output_is_seekable
+            && get_archive_name().is_some()
+        {
+            // 上次运行没有完成任何成员，从头开始
+            out_file_des.set_len(0)?;
+        }
+    }
+
+    if !resume_names.is_empty() {
+        change_dir();
+        prepare_append(&mut output_tape, &mut input_tape, &mut out_file_des);
+    } else if get_append_flag() {
         process_copy_in()?;
         prepare_append(&mut output_tape, &mut input_tape, &mut out_file_des);
     } else {
         change_dir();
     }
+    // 追加时输出缓冲区对应的归档起始偏移
+    let out_base = if !resume_names.is_empty() || get_append_flag() {
+        let start_of_header = get_last_header_start();
+        start_of_header - start_of_header % get_io_block_size() as u64
+    } else {
+        0
+    };
+    index_set_base(out_base);
 
     if get_metadata_type() != MetadataTypes::TypeNone {
         metadata_fd = unsafe { mkstemp(template.as_mut_ptr() as *mut libc::c_char) };
@@ -917,6 +1186,13 @@ pub fn process_copy_out() -> io::Result<()> {
     }
 
     let mut stdin_file = unsafe { File::from_raw_fd(libc::STDIN_FILENO) };
+    // --jobs：在其他线程中读取文件名列表并预读后面的文件
+    let mut read_ahead = if get_jobs() > 1 {
+        Some(ReadAhead::start(get_jobs()))
+    } else {
+        None
+    };
+    let mut list_directives = get_list_directives_flag().then(ListDirectives::new);
 
     loop {
         old_metadata = metadata;
@@ -934,8 +1210,30 @@ pub fn process_copy_out() -> io::Result<()> {
             }
             ds_sgetstr(template.as_bytes(), &mut input_name, get_name_end() as u8);
         } else {
+            // 上一个文件已处理完，记录归档当前的长度
+            if let Some(name) = last_member.take() {
+                journal_record(&JournalRecord::Done {
+                    offset: out_base + (output_tape.output_bytes + output_tape.output_size) as u64,
+                    name,
+                });
+            }
             // 读取文件名，如果EOF则退出循环
-            if ds_fgetstr(&mut stdin_file, &mut input_name, get_name_end() as u8).is_none() {
+            let next = match read_ahead {
+                Some(ref mut read_ahead) => read_ahead.next_name(&mut input_name),
+                None => match list_directives {
+                    Some(ref mut list) => next_listed_name(
+                        &mut output_tape,
+                        &mut input_tape,
+                        &mut out_file_des,
+                        &mut stdin_file,
+                        &mut input_name,
+                        list,
+                    ),
+                    None => ds_fgetstr(&mut stdin_file, &mut input_name, get_name_end() as u8)
+                        .map(|_| ()),
+                },
+            };
+            if next.is_none() {
                 break;
             }
         }
@@ -946,16 +1244,39 @@ pub fn process_copy_out() -> io::Result<()> {
         }
 
         let path_bytes = &input_name.ds_string[..input_name.ds_idx];
-        let path = String::from_utf8_lossy(path_bytes).to_string();
+        let path = OsStr::from_bytes(path_bytes).to_os_string();
+
+        if old_metadata == 0 {
+            // 续传时跳过已经写入归档的文件
+            if let Some(done_name) = resume_names.pop_front() {
+                if done_name != path {
+                    error(
+                        PAXEXIT_FAILURE,
+                        0,
+                        format_args!(
+                            "{}: file list does not match resume journal",
+                            quote_name(&path)
+                        ),
+                    );
+                }
+                continue;
+            }
+            if resume_journal.is_some() {
+                last_member = Some(path.clone());
+            }
+            if !name_selected(&path) {
+                continue;
+            }
+        }
 
         // 首先尝试获取符号链接的元数据，如果失败再尝试普通文件的元数据
-        let mut file_stat = match fs::symlink_metadata(path.clone()) {
+        let mut file_stat = match fs::symlink_metadata(&path) {
             Ok(stat) => stat,
             Err(_) => {
                 // 如果symlink_metadata失败，尝试普通的metadata
-                match fs::metadata(path.clone()) {
+                match fs::metadata(&path) {
                     Err(_) => {
-                        stat_error(path.as_str());
+                        stat_error(&quote_name(&path));
                         continue;
                     }
                     Ok(stat) => stat,
@@ -964,17 +1285,25 @@ pub fn process_copy_out() -> io::Result<()> {
         };
 
         stat_to_cpio(&mut file_stat, &mut file_hdr);
+        if get_rootless_flag() {
+            apply_override_stat(&path, &mut file_hdr);
+        }
+        map_header_ids(&mut file_hdr);
+        if old_metadata == 0 && !predicate_selected(&file_hdr) {
+            continue;
+        }
+        apply_member_overrides(&mut file_hdr);
 
         if (get_archive_format() == ArchiveFormat::Tar
             || get_archive_format() == ArchiveFormat::Ustar)
-            && file_hdr.c_mode & CP_IFDIR != 0
+            && file_hdr.c_mode & CP_IFMT == CP_IFDIR
             && !ds_endswith(&input_name, b'/')
         {
             ds_append(&mut input_name, b'/');
         }
 
         if old_metadata != 0 {
-            assign_string(&mut orig_file_name, &template);
+            assign_string(&mut orig_file_name, template.as_bytes());
             ds_sgetstr(
                 METADATA_FILENAME.as_bytes(),
                 &mut input_name,
@@ -984,13 +1313,13 @@ pub fn process_copy_out() -> io::Result<()> {
         } else {
             assign_string(
                 &mut orig_file_name,
-                &String::from_utf8_lossy(&input_name.ds_string),
+                &input_name.ds_string[..input_name.ds_idx],
             );
         }
 
         // let mut input_name_string = String::from_utf8_lossy(&input_name.ds_string).into_owned();
         cpio_safer_name_suffix(&mut orig_file_name, false, !get_no_abs_paths_flag(), true);
-        cpio_set_c_name(&mut file_hdr, orig_file_name.as_str());
+        cpio_set_c_name(&mut file_hdr, &orig_file_name);
 
         // Process file based on type
         match file_hdr.c_mode & CP_IFMT {
@@ -1010,6 +1339,7 @@ pub fn process_copy_out() -> io::Result<()> {
                 }
 
                 if (get_archive_format() == ArchiveFormat::Newascii
+                    || get_archive_format() == ArchiveFormat::Newxascii
                     || get_archive_format() == ArchiveFormat::Crcascii)
                     && file_hdr.c_nlink > 1
                 {
@@ -1030,6 +1360,24 @@ pub fn process_copy_out() -> io::Result<()> {
                     }
                 }
 
+                let mut prefetched = match read_ahead {
+                    Some(ref mut read_ahead) if old_metadata == 0 => {
+                        read_ahead.take(&in_file_des, file_hdr.c_filesize as u64)
+                    }
+                    _ => None,
+                };
+
+                // ustar 格式中有洞的文件只保存其中的数据
+                if get_archive_format() == ArchiveFormat::Ustar
+                    && get_sparse_flag()
+                    && old_metadata == 0
+                {
+                    file_hdr.c_sparse = tar_sparse_map(&in_file_des, file_hdr.c_filesize as u64);
+                    if file_hdr.c_sparse.is_some() {
+                        prefetched = None;
+                    }
+                }
+
                 if get_archive_format() == ArchiveFormat::Crcascii {
                     file_hdr.c_chksum = read_for_checksum(
                         &mut in_file_des,
@@ -1042,19 +1390,38 @@ pub fn process_copy_out() -> io::Result<()> {
                     continue;
                 }
 
-                copy_files_disk_to_tape(
-                    &mut output_tape,
-                    &mut input_tape,
-                    &mut in_file_des,
-                    &mut out_file_des,
-                    file_hdr.c_filesize as i32,
-                    &orig_file_name,
-                );
+                let mut stored_size = file_hdr.c_filesize as u64;
+                if let Some(ref map) = file_hdr.c_sparse {
+                    stored_size = write_out_tar_sparse_data(
+                        &mut output_tape,
+                        &mut input_tape,
+                        &mut in_file_des,
+                        &mut out_file_des,
+                        map,
+                        &orig_file_name,
+                    );
+                } else if let Some(mut data) = prefetched {
+                    tape_buffered_write(
+                        &mut output_tape,
+                        &mut data,
+                        &mut out_file_des,
+                        file_hdr.c_filesize as usize,
+                    );
+                } else {
+                    copy_files_disk_to_tape(
+                        &mut output_tape,
+                        &mut input_tape,
+                        &mut in_file_des,
+                        &mut out_file_des,
+                        file_hdr.c_filesize as u64,
+                        &orig_file_name,
+                    );
+                }
 
                 warn_if_file_changed(
                     &orig_file_name,
                     file_hdr.c_filesize as u64,
-                    file_hdr.c_mtime as u64,
+                    file_stat.mtime(),
                 );
 
                 if get_archive_format() == ArchiveFormat::Tar
@@ -1068,11 +1435,7 @@ pub fn process_copy_out() -> io::Result<()> {
                     );
                 }
 
-                tape_pad_output(
-                    &mut output_tape,
-                    &mut out_file_des,
-                    file_hdr.c_filesize as u64,
-                );
+                tape_pad_output(&mut output_tape, &mut out_file_des, stored_size);
 
                 if get_reset_time_flag() {
                     set_file_times(
@@ -1100,7 +1463,10 @@ pub fn process_copy_out() -> io::Result<()> {
                     error(
                         0,
                         0,
-                        format_args!("{} not dumped: not a regular file", orig_file_name),
+                        format_args!(
+                            "{} not dumped: not a regular file",
+                            quote_name(&orig_file_name)
+                        ),
                     );
                     continue;
                 } else if get_archive_format() == ArchiveFormat::Ustar {
@@ -1134,39 +1500,39 @@ pub fn process_copy_out() -> io::Result<()> {
 
             CP_IFLNK => {
                 let mut link_size: usize = 0;
-                let mut link_name = String::new();
+                let mut link_name = OsString::new();
                 let read_name = fs::read_link(&orig_file_name);
                 match read_name {
                     Ok(name) => {
-                        // 安全地转换为字符串，处理非UTF-8字符
-                        match name.to_str() {
-                            Some(s) => {
-                                link_name = s.to_string();
-                                link_size = link_name.len(); // 使用字符串长度而不是字节长度
-                            }
-                            None => {
-                                // 如果无法转换为UTF-8，使用to_string_lossy
-                                link_name = name.to_string_lossy().to_string();
-                                link_size = link_name.len();
-                            }
-                        }
+                        // 链接目标按原样保存，不一定是合法的 UTF-8
+                        link_name = name.into_os_string();
+                        link_size = link_name.len();
                     }
                     Err(e) => {
                         // 记录错误但不跳过文件，继续处理
-                        eprintln!("Warning: Cannot read symlink {}: {}", orig_file_name, e);
+                        eprintln!(
+                            "Warning: Cannot read symlink {}: {}",
+                            quote_name(&orig_file_name),
+                            e
+                        );
                         // 尝试使用原始路径作为链接内容
-                        link_name = format!("{}", orig_file_name);
+                        link_name = orig_file_name.clone();
                         link_size = link_name.len();
                     }
                 }
 
                 if link_size == 0 {
-                    readlink_warn(orig_file_name.as_str());
+                    readlink_warn(&quote_name(&orig_file_name));
                     continue;
                 }
 
                 cpio_safer_name_suffix(&mut link_name, false, !get_no_abs_paths_flag(), true);
 
+                let link_name = transform_name(&link_name, NameKind::Symlink);
+                let Some(link_name) = charset_convert_name(&link_name) else {
+                    continue;
+                };
+                link_size = link_name.len();
                 file_hdr.c_filesize = link_size as i64;
 
                 if get_archive_format() == ArchiveFormat::Tar
@@ -1176,7 +1542,10 @@ pub fn process_copy_out() -> io::Result<()> {
                         error(
                             0,
                             0,
-                            format_args!("{}: symbolic link too long", file_hdr.get_c_name()),
+                            format_args!(
+                                "{}: symbolic link too long",
+                                quote_name(&file_hdr.get_c_name())
+                            ),
                         );
                     } else {
                         file_hdr.c_tar_linkname = Some(link_name);
@@ -1191,7 +1560,7 @@ pub fn process_copy_out() -> io::Result<()> {
                     }
                     tape_buffered_write(
                         &mut output_tape,
-                        unsafe { link_name.as_mut_vec() },
+                        &mut link_name.into_vec(),
                         &mut out_file_des,
                         link_size,
                     );
@@ -1204,7 +1573,7 @@ pub fn process_copy_out() -> io::Result<()> {
         }
 
         if get_verbose_flag() {
-            eprintln!("{}", orig_file_name);
+            eprintln!("{}", quote_name(&orig_file_name));
         }
         if get_dot_flag() {
             eprint!(".");
@@ -1246,6 +1615,19 @@ pub fn process_copy_out() -> io::Result<()> {
 
     tape_clear_rest_of_block(&mut output_tape, &mut out_file_des);
     tape_empty_output_buffer(&mut output_tape, &mut out_file_des);
+    if let (Some(compressor), Some(command)) = (compressor, compress_program) {
+        finish_compress_program(&mut out_file_des, compressor, &command);
+    }
+
+    if let Some(ref journal) = resume_journal {
+        journal_finish(journal);
+    }
+    if let Some(index_file) = get_build_index() {
+        index_finish(
+            &index_file,
+            Some(out_base + output_tape.output_bytes as u64),
+        );
+    }
 
     if get_dot_flag() {
         eprintln!();
@@ -1263,3 +1645,98 @@ pub fn process_copy_out() -> io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copyin::read_in_new_ascii;
+    use std::sync::Mutex;
+
+    // 写出 newcx 头部再读回来
+    fn newcx_round_trip(filesize: i64, mtime: i64) -> CpioFileStat {
+        let _ = APPARGS.set(Mutex::new(AppArgs::new()));
+        let mut hdr = CpioFileStat::new();
+        hdr.set_c_name("big");
+        hdr.c_mode = CP_IFREG | 0o644;
+        hdr.c_nlink = 1;
+        hdr.c_filesize = filesize;
+        hdr.c_mtime = mtime;
+
+        let path =
+            std::env::temp_dir().join(format!("utcpio-newcx-{}-{}", std::process::id(), filesize));
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let output_tape = Mutex::new(TapeOutput::new(512));
+        let mut output_tape = output_tape.lock().unwrap();
+        assert_eq!(
+            write_out_new_ascii_header(&mut output_tape, "070703", &mut hdr, &mut file),
+            0
+        );
+        let size = output_tape.output_size;
+        file.write_all(&output_tape.output_buffer[..size]).unwrap();
+
+        file.seek(SeekFrom::Start(6)).unwrap();
+        let input_tape = Mutex::new(TapeInput::new(512));
+        let mut input_tape = input_tape.lock().unwrap();
+        let mut read = CpioFileStat::new();
+        read_in_new_ascii(&mut input_tape, &mut read, &mut file, 16);
+        fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn test_newcx_header_round_trip() {
+        // 5 GiB，2128 年
+        let read = newcx_round_trip(5 << 30, 5_000_000_000);
+        assert_eq!(read.get_c_name(), "big");
+        assert_eq!(read.c_mode, CP_IFREG | 0o644);
+        assert_eq!(read.c_filesize, 5 << 30);
+        assert_eq!(read.c_mtime, 5_000_000_000);
+
+        // 1969 年
+        let read = newcx_round_trip(1, -86_400);
+        assert_eq!(read.c_filesize, 1);
+        assert_eq!(read.c_mtime, -86_400);
+    }
+
+    // odc 和 bin 格式放不下的设备号报错，不写出头部
+    #[test]
+    fn test_old_formats_rdev_overflow() {
+        let _ = APPARGS.set(Mutex::new(AppArgs::new()));
+        let mut hdr = CpioFileStat::new();
+        hdr.set_c_name("dev");
+        hdr.c_mode = CP_IFCHR | 0o600;
+        hdr.c_nlink = 1;
+        hdr.c_magic = 0o070707;
+        let mut out = File::options().write(true).open("/dev/null").unwrap();
+        let output_tape = Mutex::new(TapeOutput::new(512));
+        let mut output_tape = output_tape.lock().unwrap();
+
+        // odc 的 rdev 是 6 位八进制数，bin 的是 16 位
+        for (maj, min, odc_fits, bin_fits) in [
+            (8, 1, true, true),
+            (255, 255, true, true),
+            (259, 0, true, false),
+            (8, 256, false, false),
+            (259, 0x100000, false, false),
+        ] {
+            let rdev = makedev(maj, min);
+            let written = output_tape.output_size;
+            let res = write_out_old_ascii_header(&mut output_tape, 0, rdev, &mut hdr, &mut out);
+            assert_eq!(res == 0, odc_fits, "odc {}:{}", maj, min);
+            assert_eq!(output_tape.output_size > written, odc_fits);
+
+            let written = output_tape.output_size;
+            let res = write_out_binary_header(&mut output_tape, rdev, &mut hdr, &mut out);
+            assert_eq!(res == 0, bin_fits, "bin {}:{}", maj, min);
+            assert_eq!(output_tape.output_size > written, bin_fits);
+            output_tape.output_size = 0;
+            output_tape.out_buff = 0;
+        }
+    }
+}
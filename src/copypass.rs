@@ -9,11 +9,16 @@
     unused_assignments
 )]
 
+use std::ffi::{CString, OsStr, OsString};
 use std::fs::{self, File, OpenOptions};
 use std::io;
-use std::os::fd::FromRawFd;
+use std::os::fd::{AsRawFd, FromRawFd};
 use std::os::linux::fs::MetadataExt as LinuxMetadataExt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
 
 use gnu::error::*;
 use nix::libc::umask;
@@ -25,11 +30,16 @@ use crate::dstring::*;
 use crate::externs::*;
 use crate::filetype::*;
 use crate::global::*;
+use crate::idmap::{map_gid, map_uid};
+use crate::pattern::name_selected;
+use crate::predicate::predicate_selected;
+use crate::readahead::StatAhead;
+use crate::transform::*;
 use crate::util::*;
 
 const AT_SYMLINK_NOFOLLOW: i32 = 4096;
 
-fn set_copypass_perms(file: Option<&File>, name: &str, st: &mut fs::Metadata) {
+fn set_copypass_perms(file: Option<&File>, name: &OsStr, st: &mut fs::Metadata) {
     let mut header = CpioFileStat::new();
     header.set_c_name(name);
     //    header.c_name = String::from(name);
@@ -37,6 +47,144 @@ fn set_copypass_perms(file: Option<&File>, name: &str, st: &mut fs::Metadata) {
     set_perms(file, &mut header)
 }
 
+// --link-dest：LINK_DEST 下同名的文件与输入文件相同时，直接硬链接到那个文件
+fn link_to_dest(output_path: &OsStr, reference: &OsStr, in_file_stat: &fs::Metadata) -> i32 {
+    let Ok(ref_stat) = fs::symlink_metadata(reference) else {
+        return -1;
+    };
+    // 链接共享同一个 inode，属主和权限也要与复制出来的文件一致
+    let owner_matches = get_no_chown_flag()
+        || (ref_stat.uid()
+            == if get_set_owner_flag() {
+                get_set_owner()
+            } else {
+                map_uid(in_file_stat.uid())
+            }
+            && ref_stat.gid()
+                == if get_set_group_flag() {
+                    get_set_group()
+                } else {
+                    map_gid(in_file_stat.gid())
+                });
+    if !ref_stat.is_file()
+        || ref_stat.len() != in_file_stat.len()
+        || ref_stat.mtime() != in_file_stat.mtime()
+        || ref_stat.mode() & 0o7777 != in_file_stat.mode() & 0o7777
+        || !owner_matches
+    {
+        return -1;
+    }
+    link_to_name(output_path, reference)
+}
+
+// 一个待复制数据的普通文件，输出文件已经创建
+struct CopyJob {
+    input_path: OsString,
+    output_path: OsString,
+    in_file_des: File,
+    out_file_des: File,
+    in_file_stat: fs::Metadata,
+}
+
+// 复制普通文件的数据并设置属性，失败时返回 false
+fn copy_regular_file(
+    output_tape: &mut MutexGuard<TapeOutput>,
+    input_tape: &mut MutexGuard<TapeInput>,
+    mut job: CopyJob,
+) -> bool {
+    // 在支持写时复制的文件系统上直接共享数据块
+    let cloned = get_reflink_mode() != ReflinkMode::Never
+        && unsafe {
+            libc::ioctl(
+                job.out_file_des.as_raw_fd(),
+                libc::FICLONE,
+                job.in_file_des.as_raw_fd(),
+            )
+        } == 0;
+
+    if cloned {
+        output_tape.output_bytes += job.in_file_stat.len() as usize;
+    } else if get_reflink_mode() == ReflinkMode::Always {
+        error(
+            0,
+            io::Error::last_os_error().raw_os_error().unwrap_or(0),
+            format_args!(
+                "cannot clone {} to {}",
+                quote_name(&job.input_path),
+                quote_name(&job.output_path)
+            ),
+        );
+        drop(job.out_file_des);
+        let _ = fs::remove_file(&job.output_path);
+        return false;
+    } else {
+        // 复制文件内容
+        copy_files_disk_to_disk(
+            output_tape,
+            input_tape,
+            &mut job.in_file_des,
+            &mut job.out_file_des,
+            job.in_file_stat.len(),
+            &job.input_path,
+        );
+
+        // 清空输出缓冲区
+        disk_empty_output_buffer(output_tape, &mut job.out_file_des, true);
+    }
+
+    // 设置文件权限
+    set_copypass_perms(
+        Some(&job.out_file_des),
+        &job.output_path,
+        &mut job.in_file_stat,
+    );
+
+    // 重置文件时间
+    if get_reset_time_flag() {
+        set_file_times(
+            Some(&job.in_file_des),
+            &job.input_path,
+            job.in_file_stat.mtime(),
+            job.in_file_stat.mtime(),
+            0,
+        );
+        set_file_times(
+            Some(&job.out_file_des),
+            &job.output_path,
+            job.in_file_stat.mtime(),
+            job.in_file_stat.mtime(),
+            0,
+        );
+    }
+
+    // 检查文件是否改变
+    warn_if_file_changed(
+        &job.input_path,
+        job.in_file_stat.size(),
+        job.in_file_stat.mtime(),
+    );
+    true
+}
+
+// --jobs 的工作线程，返回写出的字节数
+fn copy_worker(jobs: &Mutex<Receiver<CopyJob>>) -> usize {
+    let input_tape = Mutex::new(TapeInput::new(DISK_IO_BLOCK_SIZE));
+    let output_tape = Mutex::new(TapeOutput::new(DISK_IO_BLOCK_SIZE));
+    let mut input_tape = input_tape.lock().unwrap();
+    let mut output_tape = output_tape.lock().unwrap();
+
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        match job {
+            Ok(job) => {
+                copy_regular_file(&mut output_tape, &mut input_tape, job);
+            }
+            Err(_) => break,
+        }
+    }
+    output_tape.output_bytes
+}
+
 pub fn process_copy_pass() -> io::Result<()> {
     let mut input_name = DYNAMIC_STRING_INITIALIZER;
     let mut output_name = DYNAMIC_STRING_INITIALIZER;
@@ -62,27 +210,68 @@ pub fn process_copy_pass() -> io::Result<()> {
     let directory_name = get_directory_name().unwrap_or_default();
     let mut dirname_len = directory_name.len();
 
-    if get_change_directory_option().is_some() && !directory_name.starts_with('/') {
+    if get_change_directory_option().is_some() && !directory_name.as_bytes().starts_with(b"/") {
         let pwd = std::env::current_dir()?;
-        ds_concat(
-            &mut output_name,
-            pwd.to_path_buf().to_str().unwrap_or_default(),
-        );
+        ds_concat(&mut output_name, pwd.as_os_str().as_bytes());
         ds_append(&mut output_name, b'/');
     }
 
-    ds_concat(&mut output_name, directory_name.as_str());
+    ds_concat(&mut output_name, directory_name.as_bytes());
     ds_append(&mut output_name, b'/');
 
     dirname_len = ds_len(&mut output_name);
+    // 相对路径的 --link-dest 是相对于目标目录的
+    let link_dest = get_link_dest().map(|dir| {
+        let mut link_dest = if dir.starts_with('/') {
+            Vec::new()
+        } else {
+            output_name.ds_string[..dirname_len].to_vec()
+        };
+        link_dest.extend_from_slice(dir.as_bytes());
+        link_dest.push(b'/');
+        link_dest
+    });
     output_tape.output_is_seekable = true;
 
     // 改变工作目录
     change_dir();
 
-    let mut stdin_file = unsafe { File::from_raw_fd(libc::STDIN_FILENO) };
+    // --jobs：文件名的读取、链接和创建仍按顺序进行，文件的状态由另外的线程提前
+    // 读取，数据的复制交给工作线程
+    let (job_sender, workers) = if get_jobs() > 1 {
+        let (sender, receiver) = mpsc::sync_channel::<CopyJob>(get_jobs() * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers: Vec<_> = (0..get_jobs())
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || copy_worker(&receiver))
+            })
+            .collect();
+        (Some(sender), workers)
+    } else {
+        (None, Vec::new())
+    };
 
-    while ds_fgetstr(&mut stdin_file, &mut input_name, get_name_end() as u8).is_some() {
+    let mut stdin_file = unsafe { File::from_raw_fd(libc::STDIN_FILENO) };
+    let mut stat_ahead = if get_jobs() > 1 {
+        Some(StatAhead::start(get_jobs()))
+    } else {
+        None
+    };
+
+    loop {
+        let stat_result = match stat_ahead {
+            Some(ref mut stat_ahead) => match stat_ahead.next_name(&mut input_name) {
+                Some(stat_result) => Some(stat_result),
+                None => break,
+            },
+            None => {
+                if ds_fgetstr(&mut stdin_file, &mut input_name, get_name_end() as u8).is_none() {
+                    break;
+                }
+                None
+            }
+        };
         let mut link_res = -1;
 
         if input_name.ds_string[0] == 0 {
@@ -97,38 +286,40 @@ pub fn process_copy_pass() -> io::Result<()> {
             continue;
         }
 
-        let mut path_bytes = &input_name.ds_string[..input_name.ds_idx];
-        let input_path = String::from_utf8_lossy(path_bytes).to_string();
+        let path_bytes = &input_name.ds_string[..input_name.ds_idx];
+        let input_path = OsStr::from_bytes(path_bytes).to_os_string();
+        if !name_selected(&input_path) {
+            continue;
+        }
 
-        let mut in_file_stat = match fs::metadata(input_path.clone()) {
+        let mut in_file_stat = match stat_result.unwrap_or_else(|| fs::metadata(&input_path)) {
             Err(_) => {
-                stat_error(input_path.as_str());
+                stat_error(&quote_name(&input_path));
                 continue;
             }
             Ok(stat) => stat,
         };
+        let mut file_stat = CpioFileStat::new();
+        stat_to_cpio(&mut in_file_stat, &mut file_stat);
+        if !predicate_selected(&file_stat) {
+            continue;
+        }
 
-        let slash = &input_name.ds_string[..input_name.ds_idx];
-        let mut slash_str = match std::str::from_utf8(slash) {
-            Ok(s) => s,
-            Err(_) => {
-                error(0, 0, format_args!("invalid UTF-8 sequence in filename"));
-                continue;
-            }
-        };
-
-        while slash_str.starts_with("/") {
-            slash_str = &slash_str[1..];
+        let mut slash = path_bytes;
+        while slash.starts_with(b"/") {
+            slash = &slash[1..];
         }
+        let Some(relative) = transform_member_name(OsStr::from_bytes(slash)) else {
+            continue;
+        };
         ds_reset(&mut output_name, dirname_len);
-        ds_concat(&mut output_name, slash_str);
+        ds_concat(&mut output_name, relative.as_bytes());
 
         existing_dir = false;
 
-        path_bytes = &output_name.ds_string[..output_name.ds_idx];
-        let output_path = String::from_utf8_lossy(path_bytes).to_string();
+        let output_path = OsString::from_vec(output_name.ds_string[..output_name.ds_idx].to_vec());
 
-        if let Ok(out_file_stat) = fs::metadata(output_path.clone()) {
+        if let Ok(out_file_stat) = fs::metadata(&output_path) {
             if out_file_stat.is_dir() && in_file_stat.is_dir() {
                 existing_dir = true;
             } else if !get_unconditional_flag()
@@ -139,7 +330,7 @@ pub fn process_copy_pass() -> io::Result<()> {
                     0,
                     format_args!(
                         "{} not created: newer or same age version exists",
-                        output_path.clone()
+                        quote_name(&output_path)
                     ),
                 );
                 continue;
@@ -151,7 +342,7 @@ pub fn process_copy_pass() -> io::Result<()> {
                 error(
                     0,
                     e.raw_os_error().unwrap_or(0),
-                    format_args!("cannot remove current {}", output_path),
+                    format_args!("cannot remove current {}", quote_name(&output_path)),
                 );
                 continue;
             }
@@ -159,34 +350,41 @@ pub fn process_copy_pass() -> io::Result<()> {
 
         if s_isreg(in_file_stat.mode()) {
             if get_link_flag() {
-                link_res = link_to_name(output_path.as_str(), input_path.as_str());
+                link_res = link_to_name(&output_path, &input_path);
             }
 
             if link_res < 0 && in_file_stat.st_nlink() > 1 {
                 link_res = link_to_maj_min_ino(
-                    output_path.as_str(),
-                    major(in_file_stat.st_dev() as u32) as u32,
-                    minor(in_file_stat.st_dev() as u32) as u32,
+                    &output_path,
+                    major(in_file_stat.st_dev()),
+                    minor(in_file_stat.st_dev()),
                     in_file_stat.st_ino(),
                 );
             }
 
+            if link_res < 0 {
+                if let Some(ref link_dest) = link_dest {
+                    let reference = OsString::from_vec([link_dest, slash].concat());
+                    link_res = link_to_dest(&output_path, &reference, &in_file_stat);
+                }
+            }
+
             if link_res < 0 {
                 // 打开输入文件
-                let mut in_file_des = match File::open(&input_path) {
+                let in_file_des = match File::open(&input_path) {
                     Ok(file) => file,
                     Err(e) => {
                         error(
                             0,
                             e.raw_os_error().unwrap_or(0),
-                            format_args!("cannot open {}", input_path),
+                            format_args!("cannot open {}", quote_name(&input_path)),
                         );
                         continue;
                     }
                 };
 
                 // 创建输出文件
-                let mut out_file_des = match OpenOptions::new()
+                let out_file_des = match OpenOptions::new()
                     .create(true)
                     .write(true)
                     .mode(0o600)
@@ -205,57 +403,32 @@ pub fn process_copy_pass() -> io::Result<()> {
                         error(
                             0,
                             e.raw_os_error().unwrap_or(0),
-                            format_args!("cannot create {}", output_path),
+                            format_args!("cannot create {}", quote_name(&output_path)),
                         );
                         continue;
                     }
                 };
 
-                // 复制文件内容
-                copy_files_disk_to_disk(
-                    &mut output_tape,
-                    &mut input_tape,
-                    &mut in_file_des,
-                    &mut out_file_des,
-                    in_file_stat.len() as i32,
-                    &input_path,
-                );
-
-                // 清空输出缓冲区
-                disk_empty_output_buffer(&mut output_tape, &mut out_file_des, true);
-
-                // 设置文件权限
-                set_copypass_perms(Some(&out_file_des), &output_path, &mut in_file_stat);
-
-                // 重置文件时间
-                if get_reset_time_flag() {
-                    set_file_times(
-                        Some(&in_file_des),
-                        &input_path,
-                        in_file_stat.mtime(),
-                        in_file_stat.mtime(),
-                        0,
-                    );
-                    set_file_times(
-                        Some(&out_file_des),
-                        &output_path,
-                        in_file_stat.mtime(),
-                        in_file_stat.mtime(),
-                        0,
-                    );
+                let job = CopyJob {
+                    input_path: input_path.clone(),
+                    output_path: output_path.clone(),
+                    in_file_des,
+                    out_file_des,
+                    in_file_stat: in_file_stat.clone(),
+                };
+                match job_sender {
+                    Some(ref sender) => {
+                        let _ = sender.send(job);
+                    }
+                    None => {
+                        if !copy_regular_file(&mut output_tape, &mut input_tape, job) {
+                            continue;
+                        }
+                    }
                 }
-
-                // 检查文件是否改变
-                warn_if_file_changed(
-                    &input_path,
-                    in_file_stat.size(),
-                    in_file_stat.mtime() as u64,
-                );
             }
         } else if s_isdir(in_file_stat.mode()) {
-            let mut file_stat = CpioFileStat::new();
-            stat_to_cpio(&mut in_file_stat, &mut file_stat);
-            file_stat.set_c_name(output_path.as_str());
+            file_stat.set_c_name(&output_path);
             cpio_create_dir(&mut file_stat, existing_dir);
         } else if s_ischr(in_file_stat.mode())
             || s_isblk(in_file_stat.mode())
@@ -268,16 +441,17 @@ pub fn process_copy_pass() -> io::Result<()> {
             if link_res < 0 && in_file_stat.st_nlink() > 1 {
                 link_res = link_to_maj_min_ino(
                     &output_path,
-                    major(in_file_stat.st_dev() as u32) as u32,
-                    minor(in_file_stat.st_dev() as u32) as u32,
+                    major(in_file_stat.st_dev()),
+                    minor(in_file_stat.st_dev()),
                     in_file_stat.st_ino(),
                 );
             }
 
             if link_res < 0 {
+                let c_output_path = CString::new(output_path.as_bytes()).unwrap_or_default();
                 let mut res: i32 = unsafe {
                     libc::mknod(
-                        output_path.as_str().as_ptr() as *const libc::c_char,
+                        c_output_path.as_ptr(),
                         in_file_stat.st_mode(),
                         in_file_stat.st_rdev(),
                     )
@@ -287,7 +461,7 @@ pub fn process_copy_pass() -> io::Result<()> {
                     create_all_directories(&output_path);
                     res = unsafe {
                         libc::mknod(
-                            output_path.as_str().as_ptr() as *const libc::c_char,
+                            c_output_path.as_ptr(),
                             in_file_stat.st_mode(),
                             in_file_stat.st_rdev(),
                         )
@@ -295,10 +469,14 @@ pub fn process_copy_pass() -> io::Result<()> {
                 }
 
                 if res < 0 {
-                    error(0, res, format_args!("cannot create {}", output_path));
+                    error(
+                        0,
+                        res,
+                        format_args!("cannot create {}", quote_name(&output_path)),
+                    );
                     continue;
                 }
-                set_copypass_perms(None, output_path.as_str(), &mut in_file_stat);
+                set_copypass_perms(None, &output_path, &mut in_file_stat);
             } else if s_islnk(in_file_stat.mode()) {
                 let link_name = match fs::read_link(&input_path) {
                     Ok(name) => name,
@@ -306,11 +484,12 @@ pub fn process_copy_pass() -> io::Result<()> {
                         error(
                             0,
                             e.raw_os_error().unwrap_or(0),
-                            format_args!("cannot read link {}", input_path),
+                            format_args!("cannot read link {}", quote_name(&input_path)),
                         );
                         continue;
                     }
                 };
+                let link_name = transform_name(link_name.as_os_str(), NameKind::Symlink);
                 let res = std::os::unix::fs::symlink(&link_name, &output_path);
 
                 // 如果失败且设置了创建目录标志，尝试创建目录后重试
@@ -328,8 +507,8 @@ pub fn process_copy_pass() -> io::Result<()> {
                         e.raw_os_error().unwrap_or(0),
                         format_args!(
                             "cannot create symlink {} -> {}",
-                            output_path,
-                            link_name.display()
+                            quote_name(&output_path),
+                            quote_name(link_name.as_os_str())
                         ),
                     );
                     continue;
@@ -338,12 +517,12 @@ pub fn process_copy_pass() -> io::Result<()> {
                     let uid = if get_set_owner_flag() {
                         get_set_owner()
                     } else {
-                        in_file_stat.uid()
+                        map_uid(in_file_stat.uid())
                     };
                     let gid = if get_set_group_flag() {
                         get_set_group()
                     } else {
-                        in_file_stat.gid()
+                        map_gid(in_file_stat.gid())
                     };
 
                     if let Err(e) = std::os::unix::fs::lchown(&output_path, Some(uid), Some(gid)) {
@@ -357,7 +536,10 @@ pub fn process_copy_pass() -> io::Result<()> {
                                 error(
                                     0,
                                     e.raw_os_error().unwrap_or(0),
-                                    format_args!("cannot change owner of {}", output_path),
+                                    format_args!(
+                                        "cannot change owner of {}",
+                                        quote_name(&output_path)
+                                    ),
                                 );
                             }
                         }
@@ -373,17 +555,27 @@ pub fn process_copy_pass() -> io::Result<()> {
                     );
                 }
             } else {
-                error(0, 0, format_args!("{}: unknown file type", input_path));
+                error(
+                    0,
+                    0,
+                    format_args!("{}: unknown file type", quote_name(&input_path)),
+                );
             }
         }
         if get_verbose_flag() {
-            eprintln!("{}", output_path);
+            eprintln!("{}", quote_name(&output_path));
         }
         if get_dot_flag() {
             eprint!(".");
         }
     }
 
+    // 目录的属性要等所有文件都复制完成之后再设置
+    drop(job_sender);
+    for worker in workers {
+        output_tape.output_bytes += worker.join().unwrap_or(0);
+    }
+
     if get_dot_flag() {
         eprintln!();
     }
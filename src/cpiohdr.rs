@@ -6,6 +6,8 @@
 
 use lazy_static::lazy_static;
 use nix::libc::mode_t;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::sync::{Arc, Mutex};
 
 pub type RettypeMajor = i32;
@@ -185,7 +187,23 @@ pub struct CpioFileStat {
     pub c_chksum: u32,
     pub c_name: Vec<u8>, //这个字段用于存储文件名，不需要外部直接访问
     pub c_name_buflen: usize,
-    pub c_tar_linkname: Option<String>,
+    pub c_tar_linkname: Option<OsString>,
+    pub c_sparse: Option<SparseMap>, // tar 稀疏成员的数据段
+}
+
+// 稀疏文件在归档中只保存数据段，REALSIZE 是文件的实际长度，
+// SEGMENTS 是按偏移排列的（偏移，长度）
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseMap {
+    pub realsize: u64,
+    pub segments: Vec<(u64, u64)>,
+}
+
+impl SparseMap {
+    // 归档中实际保存的数据长度
+    pub fn data_size(&self) -> u64 {
+        self.segments.iter().map(|&(_, len)| len).sum()
+    }
 }
 
 impl CpioFileStat {
@@ -208,6 +226,7 @@ impl CpioFileStat {
             c_name: Vec::new(),
             c_name_buflen: 0,
             c_tar_linkname: None,
+            c_sparse: None,
         }
     }
 
@@ -230,16 +249,18 @@ impl CpioFileStat {
     //     }
     // }
 
-    pub fn set_c_name(&mut self, name: &str) {
-        let trimmed_name = name.trim_end_matches('\0');
-        self.c_name = trimmed_name.as_bytes().to_vec();
+    // 文件名按字节原样保存，不要求是合法的 UTF-8
+    pub fn set_c_name(&mut self, name: impl AsRef<OsStr>) {
+        let name = name.as_ref().as_bytes();
+        let len = name.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        self.c_name = name[..len].to_vec();
         self.c_name.push(b'\0'); // 添加结束符
         self.c_namesize = self.c_name.len();
         self.c_name_buflen = self.c_name.capacity()
     }
 
-    pub fn get_c_name(&mut self) -> String {
+    pub fn get_c_name(&self) -> OsString {
         let len = self.c_namesize - 1;
-        String::from_utf8_lossy(&self.c_name[..len]).into_owned()
+        OsString::from_vec(self.c_name[..len].to_vec())
     }
 }
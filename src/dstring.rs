@@ -12,7 +12,6 @@ use std::os::fd::{AsRawFd, RawFd};
 use std::sync::Mutex;
 
 use gnu::xmalloc::x2nrealloc;
-use libc::EOF;
 
 lazy_static! {
     static ref READER_CACHE: Mutex<HashMap<RawFd, BufReader<File>>> = Mutex::new(HashMap::new());
@@ -80,8 +79,8 @@ pub fn ds_fgetstr_common<'a>(
             }
         };
 
-        // 如果第一个字符就是结束符，返回None
-        if first_ch == eos || first_ch == EOF as u8 {
+        // 如果第一个字符就是结束符，返回None。名字中可以有任意字节，0xff 不是结束符
+        if first_ch == eos {
             drop(cache);
             return None;
         }
@@ -96,7 +95,7 @@ pub fn ds_fgetstr_common<'a>(
             match reader.read_exact(&mut buf) {
                 Ok(_) => {
                     let ch = buf[0];
-                    if ch == eos || ch == EOF as u8 {
+                    if ch == eos {
                         break;
                     }
 
@@ -117,7 +116,7 @@ pub fn ds_fgetstr_common<'a>(
     } else if let Some(input) = input_string {
         // 处理字符串输入
         for &ch in input {
-            if ch == eos || ch == EOF as u8 {
+            if ch == eos {
                 break;
             }
 
@@ -156,10 +155,12 @@ pub fn ds_append(s: &mut DynamicString, c: u8) {
     }
 }
 
-pub fn ds_concat(s: &mut DynamicString, str: &str) {
+// 名字不一定是合法的 UTF-8，按字节追加
+pub fn ds_concat(s: &mut DynamicString, str: impl AsRef<[u8]>) {
+    let str = str.as_ref();
     let len = str.len();
     ds_resize(s, len);
-    s.ds_string[s.ds_idx..s.ds_idx + len].copy_from_slice(str.as_bytes());
+    s.ds_string[s.ds_idx..s.ds_idx + len].copy_from_slice(str);
     s.ds_idx += len;
     s.ds_string[s.ds_idx] = 0;
 }
@@ -16,17 +16,34 @@ pub enum ArchiveFormat {
     Oldascii,
     Newascii,
     Crcascii,
+    Newxascii, // 070703，修改时间和文件大小为 16 位十六进制数
     Tar,
     Ustar,
     Hpoldascii,
     Hpbinary,
 }
 
+// copy-pass 中 --reflink 的取值
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReflinkMode {
+    Never,
+    Auto,
+    Always,
+}
+
+// -i、-o、-p 选定的工作方式，与 copy_function 一起设置
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CopyMode {
+    In,
+    Out,
+    Pass,
+}
+
 lazy_static! {
     //pub static ref SVR4_COMPAT: Mutex<bool> = Mutex::new(false);
     //pub static ref DEBUG_FLAG: Mutex<bool> = Mutex::new(false);
     pub static ref NEWDIR_UMASK: Mutex<u32> = Mutex::new(0);
-    pub static ref LAST_HEADER_START: Mutex<i32> = Mutex::new(0);
+    pub static ref LAST_HEADER_START: Mutex<u64> = Mutex::new(0);
     pub static ref  SWAPPING_HALFWORDS: Mutex<bool> = Mutex::new(false);
     pub static ref  SWAPPING_BYTES: Mutex<bool> = Mutex::new(false);
 }
@@ -44,10 +61,10 @@ pub fn set_swapping_bytes(swapping: bool) {
 pub fn get_swapping_bytes() -> bool {
     *SWAPPING_BYTES.lock().unwrap()
 }
-pub fn set_last_header_start(start: i32) {
+pub fn set_last_header_start(start: u64) {
     *LAST_HEADER_START.lock().unwrap() = start;
 }
-pub fn get_last_header_start() -> i32 {
+pub fn get_last_header_start() -> u64 {
     *LAST_HEADER_START.lock().unwrap()
 }
 
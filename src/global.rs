@@ -102,14 +102,52 @@ pub fn resize_output_buffer(new_size: usize) {
     tape_out.resize(new_size); // 修改 TapeInput
 }
 
-pub fn major(device: u32) -> u8 {
-    ((device >> 8) & 0xff) as u8
+// 与 glibc 的 gnu_dev_major/gnu_dev_minor/gnu_dev_makedev 编码一致：
+// 主设备号 12+20 位、次设备号 8+24 位，小于 4096/256 时与传统的 maj << 8 | min 相同
+pub fn major(device: u64) -> u32 {
+    (((device >> 32) & 0xffff_f000) | ((device >> 8) & 0x0000_0fff)) as u32
 }
 
-pub fn minor(device: u32) -> u8 {
-    (device & 0xff) as u8
+pub fn minor(device: u64) -> u32 {
+    (((device >> 12) & 0xffff_ff00) | (device & 0x0000_00ff)) as u32
 }
 
-pub fn makedev(major: u8, minor: u8) -> u32 {
-    ((major as u32) << 8) | (minor as u32)
+pub fn makedev(major: u32, minor: u32) -> u64 {
+    let major = major as u64;
+    let minor = minor as u64;
+    ((major & 0x0000_0fff) << 8)
+        | ((major & 0xffff_f000) << 32)
+        | (minor & 0x0000_00ff)
+        | ((minor & 0xffff_ff00) << 12)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_numbers() {
+        // 小的设备号与传统编码相同
+        assert_eq!(makedev(8, 1), 0x801);
+        assert_eq!(major(0x801), 8);
+        assert_eq!(minor(0x801), 1);
+
+        for (maj, min) in [
+            (0, 0),
+            (259, 0),
+            (259, 7),
+            (8, 256),
+            (4095, 255),
+            (4096, 0x1234),
+            (1, 0xfffff),
+            (259, 0x100000),
+            (0xffff_ffff, 0xffff_ffff),
+        ] {
+            let dev = makedev(maj, min);
+            assert_eq!(dev, libc::makedev(maj, min));
+            assert_eq!((major(dev), minor(dev)), (maj, min));
+            assert_eq!(major(dev), libc::major(dev));
+            assert_eq!(minor(dev), libc::minor(dev));
+        }
+    }
 }
@@ -5,10 +5,14 @@
 use libc::{gid_t, uid_t};
 use std::{
     collections::HashMap,
+    fs,
+    path::Path,
     sync::{OnceLock, RwLock},
 };
 use users::{get_group_by_gid, get_group_by_name, get_user_by_name, get_user_by_uid};
 
+use pax::paxerror::open_fatal;
+
 // // Cache structures using HashMap instead of linked lists
 // static mut USER_CACHE: Option<HashMap<uid_t, String>> = None;
 // static mut NOUSER_CACHE: Option<HashMap<String, ()>> = None;
@@ -31,6 +35,131 @@ fn get_cache() -> &'static Cache {
         nogroup_cache: RwLock::new(HashMap::new()),
     })
 }
+
+// --passwd-root、--passwd-file、--group-file 给出的 passwd 和 group 文件，
+// 给出后名字和 ID 只在这些文件中查找，不再使用本机的 NSS
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: gid_t,
+}
+
+struct NameFiles {
+    passwd: Option<Vec<PasswdEntry>>,
+    group: Option<Vec<GroupEntry>>,
+}
+
+static NAME_FILES: OnceLock<NameFiles> = OnceLock::new();
+
+// 解析 "NAME:PASSWORD:UID:GID:..." 格式的行，跳过注释、NIS 的 '+'/'-' 项和格式错误的行
+fn parse_fields(data: &str, min_fields: usize) -> Vec<Vec<&str>> {
+    data.lines()
+        .filter(|line| !line.starts_with(['#', '+', '-']))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= min_fields && !fields[0].is_empty())
+        .collect()
+}
+
+pub fn parse_passwd(data: &str) -> Vec<PasswdEntry> {
+    parse_fields(data, 4)
+        .into_iter()
+        .filter_map(|fields| {
+            Some(PasswdEntry {
+                name: fields[0].to_string(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+pub fn parse_group(data: &str) -> Vec<GroupEntry> {
+    parse_fields(data, 3)
+        .into_iter()
+        .filter_map(|fields| {
+            Some(GroupEntry {
+                name: fields[0].to_string(),
+                gid: fields[2].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+fn read_name_file(path: &Path) -> String {
+    match fs::read(path) {
+        Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+        Err(_) => {
+            open_fatal(&path.to_string_lossy());
+            String::new()
+        }
+    }
+}
+
+// 必须在第一次查找之前调用，没有给出的文件仍然使用本机的 NSS
+pub fn idcache_init(passwd_file: Option<&Path>, group_file: Option<&Path>) {
+    let _ = NAME_FILES.set(NameFiles {
+        passwd: passwd_file.map(|path| parse_passwd(&read_name_file(path))),
+        group: group_file.map(|path| parse_group(&read_name_file(path))),
+    });
+}
+
+fn passwd_entries() -> Option<&'static [PasswdEntry]> {
+    NAME_FILES.get()?.passwd.as_deref()
+}
+
+fn group_entries() -> Option<&'static [GroupEntry]> {
+    NAME_FILES.get()?.group.as_deref()
+}
+
+// 不经过缓存的查找，与 getpwnam、getpwuid、getgrnam、getgrgid 对应
+pub fn lookup_user(name: &str) -> Option<PasswdEntry> {
+    match passwd_entries() {
+        Some(entries) => entries.iter().find(|entry| entry.name == name).cloned(),
+        None => get_user_by_name(name).map(|user| PasswdEntry {
+            name: name.to_string(),
+            uid: user.uid(),
+            gid: user.primary_group_id(),
+        }),
+    }
+}
+
+fn lookup_user_name(uid: uid_t) -> Option<String> {
+    match passwd_entries() {
+        Some(entries) => entries
+            .iter()
+            .find(|entry| entry.uid == uid)
+            .map(|entry| entry.name.clone()),
+        None => get_user_by_uid(uid).map(|user| user.name().to_string_lossy().into_owned()),
+    }
+}
+
+pub fn lookup_group(name: &str) -> Option<GroupEntry> {
+    match group_entries() {
+        Some(entries) => entries.iter().find(|entry| entry.name == name).cloned(),
+        None => get_group_by_name(name).map(|group| GroupEntry {
+            name: name.to_string(),
+            gid: group.gid(),
+        }),
+    }
+}
+
+pub fn lookup_group_name(gid: gid_t) -> Option<String> {
+    match group_entries() {
+        Some(entries) => entries
+            .iter()
+            .find(|entry| entry.gid == gid)
+            .map(|entry| entry.name.clone()),
+        None => get_group_by_gid(gid).map(|group| group.name().to_string_lossy().into_owned()),
+    }
+}
+
 pub fn getuser(uid: uid_t) -> String {
     let cache = get_cache();
 
@@ -42,10 +171,7 @@ pub fn getuser(uid: uid_t) -> String {
     }
 
     // 查询系统
-    let name = match get_user_by_uid(uid) {
-        Some(user) => user.name().to_string_lossy().into_owned(),
-        None => uid.to_string(),
-    };
+    let name = lookup_user_name(uid).unwrap_or_else(|| uid.to_string());
 
     // 更新缓存
     if let Ok(mut user_cache) = cache.user_cache.write() {
@@ -74,9 +200,9 @@ pub fn getuidbyname(user: &str) -> Option<uid_t> {
     }
 
     // 查询系统
-    match get_user_by_name(user) {
+    match lookup_user(user) {
         Some(user_info) => {
-            let uid = user_info.uid();
+            let uid = user_info.uid;
             if let Ok(mut user_cache) = cache.user_cache.write() {
                 user_cache.insert(uid, user.to_string());
             }
@@ -102,10 +228,7 @@ pub fn getgroup(gid: gid_t) -> String {
     }
 
     // 查询系统
-    let name = match get_group_by_gid(gid) {
-        Some(group) => group.name().to_string_lossy().into_owned(),
-        None => gid.to_string(),
-    };
+    let name = lookup_group_name(gid).unwrap_or_else(|| gid.to_string());
 
     // 更新缓存
     if let Ok(mut group_cache) = cache.group_cache.write() {
@@ -134,9 +257,9 @@ pub fn getgidbyname(group: &str) -> Option<gid_t> {
     }
 
     // 查询系统
-    match get_group_by_name(group) {
+    match lookup_group(group) {
         Some(group_info) => {
-            let gid = group_info.gid();
+            let gid = group_info.gid;
             if let Ok(mut group_cache) = cache.group_cache.write() {
                 group_cache.insert(gid, group.to_string());
             }
@@ -184,4 +307,39 @@ mod tests {
         assert!(gid.is_some());
         assert_eq!(gid.unwrap(), 0);
     }
+
+    #[test]
+    fn test_parse_passwd_group() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n# comment\n+nis::::::\n\
+                      builder:x:1000:100::/home/builder:/bin/sh\nbroken:x:1\nbad:x:a:1:::\n";
+        assert_eq!(
+            parse_passwd(passwd),
+            vec![
+                PasswdEntry {
+                    name: "root".to_string(),
+                    uid: 0,
+                    gid: 0
+                },
+                PasswdEntry {
+                    name: "builder".to_string(),
+                    uid: 1000,
+                    gid: 100
+                },
+            ]
+        );
+        let group = "root:x:0:\nusers:x:100:builder,other\nshort:x\n";
+        assert_eq!(
+            parse_group(group),
+            vec![
+                GroupEntry {
+                    name: "root".to_string(),
+                    gid: 0
+                },
+                GroupEntry {
+                    name: "users".to_string(),
+                    gid: 100
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,263 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 属主和属组的映射（--owner-map、--group-map、--idmap）。
+// 映射文件每行一项 "OLD NEW"：OLD 是用户名（组名）或 "+ID"，NEW 是名字、"+ID"
+// 或 "NAME:ID"，'#' 开头的行和空行被忽略。--idmap INSIDE:OUTSIDE:COUNT 与用户
+// 命名空间的 uid_map 相同，把 INSIDE 开始的 COUNT 个 ID 依次映射为 OUTSIDE 开始
+// 的 ID，同时作用于 uid 和 gid。映射表中的项优先于 --idmap 的范围，都不匹配的
+// ID 保持不变。copy-out 时映射写入归档头部的 ID，copy-in 和 copy-pass 时映射
+// 设置给文件的属主；--reverse-map 使所有的映射反向进行，即从 NEW 映射回 OLD。
+
+use std::fs;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use gnu::error::*;
+use pax::paxerror::open_fatal;
+use pax::paxlib::PAXEXIT_FAILURE;
+
+use crate::appargs::*;
+use crate::cpiohdr::CpioFileStat;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapEntry {
+    pub old: u32,
+    pub new: u32,
+    pub new_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdRange {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+#[derive(Default)]
+struct IdMap {
+    users: Vec<MapEntry>,
+    groups: Vec<MapEntry>,
+    ranges: Vec<IdRange>,
+    reverse: bool,
+}
+
+lazy_static! {
+    static ref IDMAP: Mutex<IdMap> = Mutex::new(IdMap::default());
+}
+
+// "+ID" 是数字 ID，其他的是名字，按 LOOKUP 查找
+fn parse_id(text: &str, lookup: fn(&str) -> Option<u32>) -> Option<u32> {
+    match text.strip_prefix('+') {
+        Some(digits) => digits.parse().ok(),
+        None => lookup(text),
+    }
+}
+
+pub fn parse_map(data: &str, lookup: fn(&str) -> Option<u32>) -> Result<Vec<MapEntry>, String> {
+    let mut entries = Vec::new();
+    for (number, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [old, new] = fields[..] else {
+            return Err(format!("line {}: expected `OLD NEW'", number + 1));
+        };
+        let old = parse_id(old, lookup)
+            .ok_or_else(|| format!("line {}: invalid name or ID `{}'", number + 1, old))?;
+        let (new_id, new_name) = match new.split_once(':') {
+            Some((name, id)) => (id.parse().ok(), Some(name.to_string())),
+            None if new.starts_with('+') => (parse_id(new, lookup), None),
+            None => (lookup(new), Some(new.to_string())),
+        };
+        let new =
+            new_id.ok_or_else(|| format!("line {}: invalid name or ID `{}'", number + 1, new))?;
+        entries.push(MapEntry { old, new, new_name });
+    }
+    Ok(entries)
+}
+
+// 读取 --owner-map 或 --group-map 给出的映射文件，文件打不开或有错误时退出
+pub fn read_map(file_name: &str, lookup: fn(&str) -> Option<u32>) -> Vec<MapEntry> {
+    let data = match fs::read(file_name) {
+        Ok(data) => data,
+        Err(_) => {
+            open_fatal(file_name);
+            return Vec::new();
+        }
+    };
+    match parse_map(&String::from_utf8_lossy(&data), lookup) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error(PAXEXIT_FAILURE, 0, format_args!("{}: {}", file_name, e));
+            Vec::new()
+        }
+    }
+}
+
+pub fn parse_range(text: &str) -> Option<IdRange> {
+    let mut fields = text.split(':').map(|field| field.parse::<u32>().ok());
+    let (Some(Some(inside)), Some(Some(outside)), Some(Some(count)), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return None;
+    };
+    // 范围不能超出 ID 的取值范围
+    if count == 0
+        || inside.checked_add(count - 1).is_none()
+        || outside.checked_add(count - 1).is_none()
+    {
+        return None;
+    }
+    Some(IdRange {
+        inside,
+        outside,
+        count,
+    })
+}
+
+pub fn idmap_init(
+    users: Vec<MapEntry>,
+    groups: Vec<MapEntry>,
+    ranges: Vec<IdRange>,
+    reverse: bool,
+) {
+    *IDMAP.lock().unwrap() = IdMap {
+        users,
+        groups,
+        ranges,
+        reverse,
+    };
+}
+
+fn map_id(id: u32, entries: &[MapEntry], ranges: &[IdRange], reverse: bool) -> u32 {
+    let entry = if reverse {
+        entries
+            .iter()
+            .find(|entry| entry.new == id)
+            .map(|entry| entry.old)
+    } else {
+        entries
+            .iter()
+            .find(|entry| entry.old == id)
+            .map(|entry| entry.new)
+    };
+    if let Some(mapped) = entry {
+        return mapped;
+    }
+    for range in ranges {
+        let (from, to) = if reverse {
+            (range.outside, range.inside)
+        } else {
+            (range.inside, range.outside)
+        };
+        if id >= from && id - from < range.count {
+            return to + (id - from);
+        }
+    }
+    id
+}
+
+pub fn map_uid(uid: u32) -> u32 {
+    let idmap = IDMAP.lock().unwrap();
+    map_id(uid, &idmap.users, &idmap.ranges, idmap.reverse)
+}
+
+pub fn map_gid(gid: u32) -> u32 {
+    let idmap = IDMAP.lock().unwrap();
+    map_id(gid, &idmap.groups, &idmap.ranges, idmap.reverse)
+}
+
+// copy-out 时映射写入归档头部的 ID，-R 指定的属主和属组不再映射
+pub fn map_header_ids(hdr: &mut CpioFileStat) {
+    if !get_set_owner_flag() {
+        hdr.c_uid = map_uid(hdr.c_uid);
+    }
+    if !get_set_group_flag() {
+        hdr.c_gid = map_gid(hdr.c_gid);
+    }
+}
+
+// 映射表中为映射后的 ID 指定的名字，tar 头部中用它代替本机上查到的名字
+fn mapped_name(id: u32, entries: &[MapEntry], reverse: bool) -> Option<String> {
+    if reverse {
+        return None;
+    }
+    entries
+        .iter()
+        .find(|entry| entry.new == id)
+        .and_then(|entry| entry.new_name.clone())
+}
+
+pub fn mapped_user_name(uid: u32) -> Option<String> {
+    let idmap = IDMAP.lock().unwrap();
+    mapped_name(uid, &idmap.users, idmap.reverse)
+}
+
+pub fn mapped_group_name(gid: u32) -> Option<String> {
+    let idmap = IDMAP.lock().unwrap();
+    mapped_name(gid, &idmap.groups, idmap.reverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<u32> {
+        match name {
+            "alice" => Some(1000),
+            "bob" => Some(1001),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_map() {
+        let data = "# comment\n\nalice +0\n+1001 builder:2000\n+5 bob\n";
+        let entries = parse_map(data, lookup).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                MapEntry {
+                    old: 1000,
+                    new: 0,
+                    new_name: None
+                },
+                MapEntry {
+                    old: 1001,
+                    new: 2000,
+                    new_name: Some("builder".to_string())
+                },
+                MapEntry {
+                    old: 5,
+                    new: 1001,
+                    new_name: Some("bob".to_string())
+                },
+            ]
+        );
+        assert!(parse_map("carol +0\n", lookup).is_err());
+        assert!(parse_map("alice\n", lookup).is_err());
+        assert!(parse_map("alice x:y\n", lookup).is_err());
+    }
+
+    #[test]
+    fn test_map_id() {
+        let entries = parse_map("+0 +1\n", lookup).unwrap();
+        let ranges = vec![parse_range("0:100000:65536").unwrap()];
+        assert_eq!(map_id(0, &entries, &ranges, false), 1);
+        assert_eq!(map_id(5, &entries, &ranges, false), 100005);
+        assert_eq!(map_id(65536, &entries, &ranges, false), 65536);
+        assert_eq!(map_id(1, &entries, &ranges, true), 0);
+        assert_eq!(map_id(165535, &entries, &ranges, true), 65535);
+        assert_eq!(map_id(99999, &entries, &ranges, true), 99999);
+
+        assert!(parse_range("0:100000").is_none());
+        assert!(parse_range("0:100000:0").is_none());
+        assert!(parse_range("1:4294967295:2").is_none());
+        assert!(parse_range("0:1:2:3").is_none());
+    }
+}
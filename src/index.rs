@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 归档索引（--build-index=FILE / --index=FILE）。
+// 索引是一个文本文件，第一行是格式标识和归档长度（未知时为 -），之后每个
+// 成员一行：头部偏移、数据偏移、大小、模式、修改时间和文件名，字段之间用
+// 制表符分隔。
+
+use std::ffi::OsString;
+use std::fs;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use gnu::error::*;
+use pax::paxlib::*;
+
+use crate::util::{escape_field, unescape_field};
+
+const INDEX_MAGIC: &str = "utcpio-index\t1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub header_offset: u64,
+    pub body_offset: u64,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: i64,
+    pub name: OsString,
+}
+
+pub struct ArchiveIndex {
+    pub archive_size: Option<u64>,
+    pub entries: Vec<IndexEntry>,
+}
+
+lazy_static! {
+    static ref INDEX_ENTRIES: Mutex<Vec<IndexEntry>> = Mutex::new(Vec::new());
+    static ref INDEX_BASE: Mutex<u64> = Mutex::new(0);
+}
+
+// 之后记录的偏移都是相对于归档中 BASE 处的，追加归档时使用
+pub fn index_set_base(base: u64) {
+    *INDEX_BASE.lock().unwrap() = base;
+}
+
+pub fn index_record(mut entry: IndexEntry) {
+    let base = *INDEX_BASE.lock().unwrap();
+    entry.header_offset += base;
+    entry.body_offset += base;
+    INDEX_ENTRIES.lock().unwrap().push(entry);
+}
+
+fn format_index(archive_size: Option<u64>, entries: &[IndexEntry]) -> String {
+    let mut data = match archive_size {
+        Some(size) => format!("{}\t{}\n", INDEX_MAGIC, size),
+        None => format!("{}\t-\n", INDEX_MAGIC),
+    };
+    for entry in entries {
+        data.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            entry.header_offset,
+            entry.body_offset,
+            entry.size,
+            entry.mode,
+            entry.mtime,
+            escape_field(&entry.name)
+        ));
+    }
+    data
+}
+
+fn parse_index(data: &str) -> Option<ArchiveIndex> {
+    let mut lines = data.lines();
+    let size = lines
+        .next()?
+        .strip_prefix(INDEX_MAGIC)?
+        .strip_prefix('\t')?;
+    let archive_size = if size == "-" {
+        None
+    } else {
+        Some(size.parse().ok()?)
+    };
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        entries.push(IndexEntry {
+            header_offset: fields[0].parse().ok()?,
+            body_offset: fields[1].parse().ok()?,
+            size: fields[2].parse().ok()?,
+            mode: fields[3].parse().ok()?,
+            mtime: fields[4].parse().ok()?,
+            name: unescape_field(fields[5])?,
+        });
+    }
+    Some(ArchiveIndex {
+        archive_size,
+        entries,
+    })
+}
+
+// 写出记录的索引，ARCHIVE_SIZE 是归档的总长度
+pub fn index_finish(path: &str, archive_size: Option<u64>) {
+    let entries = std::mem::take(&mut *INDEX_ENTRIES.lock().unwrap());
+    if let Err(e) = fs::write(path, format_index(archive_size, &entries)) {
+        error(
+            PAXEXIT_FAILURE,
+            e.raw_os_error().unwrap_or(0),
+            format_args!("cannot write index {}", path),
+        );
+    }
+}
+
+// 读入索引文件，不能读取或格式不对时给出警告并返回 None
+pub fn index_load(path: &str) -> Option<ArchiveIndex> {
+    match fs::read(path) {
+        Ok(data) => {
+            let index = parse_index(&String::from_utf8_lossy(&data));
+            if index.is_none() {
+                error(0, 0, format_args!("{}: malformed index", path));
+            }
+            index
+        }
+        Err(e) => {
+            error(
+                0,
+                e.raw_os_error().unwrap_or(0),
+                format_args!("cannot read index {}", path),
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_index() {
+        let entries = vec![
+            IndexEntry {
+                header_offset: 0,
+                body_offset: 112,
+                size: 5,
+                mode: 0o100644,
+                mtime: 1700000000,
+                name: "a\tb".into(),
+            },
+            IndexEntry {
+                header_offset: 120,
+                body_offset: 232,
+                size: 0,
+                mode: 0o40755,
+                mtime: -1,
+                name: "dir\\x".into(),
+            },
+        ];
+        let index = parse_index(&format_index(Some(512), &entries)).unwrap();
+        assert_eq!(index.archive_size, Some(512));
+        assert_eq!(index.entries, entries);
+
+        let index = parse_index(&format_index(None, &[])).unwrap();
+        assert_eq!(index.archive_size, None);
+        assert!(index.entries.is_empty());
+
+        assert!(parse_index("utcpio-index\t2\t-\n").is_none());
+        assert!(parse_index("utcpio-index\t1\t-\n0\t1\t2\n").is_none());
+    }
+}
@@ -5,6 +5,7 @@
 use std::io;
 
 mod appargs;
+mod charset;
 mod copyin;
 mod copyout;
 mod copypass;
@@ -15,31 +16,52 @@ mod filemode;
 mod filetype;
 mod global;
 mod idcache;
+mod idmap;
+mod index;
 mod initramfs;
+mod modechange;
+mod namelist;
+mod pattern;
+mod predicate;
+mod readahead;
+mod resume;
+mod rootless;
 mod tar;
+mod transaction;
+mod transform;
 mod userspec;
 mod util;
 
-use clap::{Arg, ArgAction, ArgGroup, Command};
+use clap::{value_parser, Arg, ArgAction, ArgGroup, Command};
 use gnu::quotearg::quotearg_colon;
+use pax::paxerror::open_fatal;
 use pax::paxexit::pax_exit;
 
 use std::env::{self};
+use std::ffi::OsString;
 use std::fs::File;
 use std::io::Read;
-use std::os::fd::FromRawFd;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Mutex;
 
 use appargs::*;
+use charset::*;
 use copyin::*;
 use copyout::*;
 use copypass::*;
 use dstring::clear_reader_cache;
 use externs::*;
 use global::*;
+use idcache::{getgidbyname, getuidbyname, idcache_init};
+use idmap::*;
 use initramfs::*;
+use pattern::*;
+use predicate::*;
+use transform::*;
 use userspec::*;
+use modechange::mode_compile;
 use util::*;
 
 use pax::paxlib::*;
@@ -76,9 +98,26 @@ const USAGE_STR: &str =
         [--dot] [--warning=FLAG] [--file=[[USER@]HOST:]FILE-NAME]\n      \
         [--message=STRING] [--rsh-command=COMMAND] [--swap] [--nonmatching]\n      \
         [--numeric-uid-gid] [--rename] [--swap-bytes] [--swap-halfwords]\n      \
-        [--to-stdout] [--pattern-file=FILE] [--only-verify-crc] [--append]\n      \
+        [--to-stdout] [--sync] [--transactional] [--pattern-file=FILE]\n      \
+        [--only-verify-crc] [--append] [--resume=JOURNAL] [--seek-remote]\n      \
+        [--build-index=FILE] [--index=FILE]\n      \
+        [--wildcards] [--regex] [--literal] [--[no-]anchored]\n      \
+        [--[no-]ignore-case] [--[no-]wildcards-match-slash]\n      \
+        [--from-charset=CHARSET] [--to-charset=CHARSET]\n      \
+        [--newer=DATE|FILE] [--older=DATE|FILE] [--size=[+-]N[ckMG]]\n      \
+        [--type=TYPES] [--user=USER] [--group=GROUP]\n      \
+        [--perm=[-/]MODE] [--nlink=[+-]N] [--and] [--or] [--not]\n      \
+        [--begin-group] [--end-group] [--transform=EXPRESSION]\n      \
+        [--strip-components=N] [--prefix=DIR] [--owner-map=FILE]\n      \
+        [--group-map=FILE] [--idmap=INSIDE:OUTSIDE:COUNT] [--reverse-map]\n      \
+        [--passwd-root=DIR] [--passwd-file=FILE] [--group-file=FILE]\n      \
+        [--rootless] [--mode=CHANGES] [--set-owner=NAME[:UID]]\n      \
+        [--set-group=NAME[:GID]] [--mtime=DATE|FILE] [--clamp-mtime]\n      \
+        [--files-from=FILE] [--list-directives]\n      \
         [--device-independent] [--reproducible] [--file-metadata=TYPE]\n      \
         [--ignore-devno] [--ignore-dirnlink] [--renumber-inodes] [--link]\n      \
+        [--reflink[=WHEN]] [--link-dest=DIR] [--jobs=N]\n      \
+        [--compress-program=COMMAND]\n      \
         [--absolute-filenames] [--no-absolute-filenames] [--null]\n      \
         [--reset-access-time] [--dereference] [--make-directories]\n      \
         [--preserve-modification-time] [--no-preserve-owner] [--sparse]\n      \
@@ -190,6 +229,95 @@ fn test_read() {
     }
 }
 
+// 命令行上的模式使用它前面的 --regex、--ignore-case 等选项指定的匹配方式
+fn match_options_at(matches: &clap::ArgMatches, index: usize) -> MatchOptions {
+    let mut changes: Vec<(usize, &str)> = MATCH_OPTION_NAMES
+        .iter()
+        .flat_map(|&name| {
+            matches
+                .indices_of(name)
+                .into_iter()
+                .flatten()
+                .filter(move |&i| i < index)
+                .map(move |i| (i, name))
+        })
+        .collect();
+    changes.sort();
+    let mut options = MatchOptions::new();
+    for (_, name) in changes {
+        options.apply(name);
+    }
+    options
+}
+
+// 取出命令行上的模式和 -E 模式文件中的模式，按它们在命令行上的顺序排列。
+// 跳过前 SKIP 个参数（copy-pass 的目标目录）
+fn collect_patterns(matches: &clap::ArgMatches, skip: usize) -> Vec<Pattern> {
+    let mut sources: Vec<(usize, Vec<Pattern>)> = Vec::new();
+    if let (Some(values), Some(indices)) = (
+        matches.get_many::<OsString>("patterns"),
+        matches.indices_of("patterns"),
+    ) {
+        for (text, index) in values.zip(indices).skip(skip) {
+            match Pattern::new(text, match_options_at(matches, index)) {
+                Ok(pattern) => sources.push((index, vec![pattern])),
+                Err(e) => error(
+                    PAXEXIT_FAILURE,
+                    0,
+                    format_args!("{}: invalid pattern: {}", quote_name(text), e),
+                ),
+            }
+        }
+    }
+    if let (Some(files), Some(indices)) = (
+        matches.get_many::<String>("pattern_file"),
+        matches.indices_of("pattern_file"),
+    ) {
+        for (file, index) in files.zip(indices) {
+            sources.push((
+                index,
+                read_pattern_file(file, match_options_at(matches, index)),
+            ));
+        }
+    }
+    sources.sort_by_key(|(index, _)| *index);
+    sources.into_iter().flat_map(|(_, patterns)| patterns).collect()
+}
+
+// 按命令行上的顺序取出选择谓词和 --and、--or 等连接词，组合成一个表达式
+fn collect_predicates(matches: &clap::ArgMatches) -> Option<Predicate> {
+    let mut tokens: Vec<(usize, Token)> = Vec::new();
+    for &name in PREDICATE_NAMES {
+        if let (Some(values), Some(indices)) =
+            (matches.get_many::<String>(name), matches.indices_of(name))
+        {
+            for (value, index) in values.zip(indices) {
+                match Predicate::parse(name, value) {
+                    Ok(predicate) => tokens.push((index, Token::Test(predicate))),
+                    Err(e) => USAGE_ERROR(0, format_args!("--{}: {}", name, e)),
+                }
+            }
+        }
+    }
+    for &name in CONNECTIVE_NAMES {
+        // 没有出现在命令行上的 ArgAction::Count 参数也有默认值的位置
+        if matches.value_source(name) != Some(clap::parser::ValueSource::CommandLine) {
+            continue;
+        }
+        for index in matches.indices_of(name).into_iter().flatten() {
+            tokens.extend(Token::connective(name).map(|token| (index, token)));
+        }
+    }
+    tokens.sort_by_key(|(index, _)| *index);
+    match parse_expression(tokens.into_iter().map(|(_, token)| token).collect()) {
+        Ok(expression) => expression,
+        Err(e) => {
+            USAGE_ERROR(0, format_args!("invalid predicate expression: {}", e));
+            None
+        }
+    }
+}
+
 fn parse_metadata_type(arg: &str) -> MetadataTypes {
     match arg {
         "none" => MetadataTypes::TypeNone,
@@ -305,6 +433,125 @@ fn process_args() {
             .help("Set the ownership of all files created to the specified USER and/or GROUP")
             .value_name("[USER][:.][GROUP]")
             .group("operation_modifiers_any"))
+        .arg(Arg::new("passwd_root")
+            .long("passwd-root")
+            .help("Look up user and group names in DIR/etc/passwd and DIR/etc/group")
+            .value_name("DIR")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("passwd_file")
+            .long("passwd-file")
+            .help("Look up user names in FILE instead of the system database")
+            .value_name("FILE")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("group_file")
+            .long("group-file")
+            .help("Look up group names in FILE instead of the system database")
+            .value_name("FILE")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("owner_map")
+            .long("owner-map")
+            .help("Map file owners according to the OLD NEW lines in FILE")
+            .value_name("FILE")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("group_map")
+            .long("group-map")
+            .help("Map file groups according to the OLD NEW lines in FILE")
+            .value_name("FILE")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("idmap")
+            .long("idmap")
+            .help("Map COUNT user and group IDs starting at INSIDE to IDs starting at OUTSIDE")
+            .value_name("INSIDE:OUTSIDE:COUNT")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("reverse_map")
+            .long("reverse-map")
+            .action(ArgAction::SetTrue)
+            .help("Apply --owner-map, --group-map and --idmap from NEW to OLD")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("newer")
+            .long("newer")
+            .help("Only process files modified after DATE or after FILE was modified")
+            .value_name("DATE|FILE")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("older")
+            .long("older")
+            .help("Only process files modified before DATE or before FILE was modified")
+            .value_name("DATE|FILE")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("size")
+            .long("size")
+            .help("Only process files of size N (more than +N, less than -N) bytes, kilobytes (k), megabytes (M) or gigabytes (G)")
+            .value_name("[+-]N[ckMG]")
+            .allow_hyphen_values(true)
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("type")
+            .long("type")
+            .help("Only process files of the given comma-separated TYPES (f, d, l, c, b, p, s)")
+            .value_name("TYPES")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("user")
+            .long("user")
+            .help("Only process files owned by USER (name or UID)")
+            .value_name("USER")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("group")
+            .long("group")
+            .help("Only process files belonging to GROUP (name or GID)")
+            .value_name("GROUP")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("perm")
+            .long("perm")
+            .help("Only process files whose permission bits are exactly MODE, have all of -MODE or any of /MODE set")
+            .value_name("[-/]MODE")
+            .allow_hyphen_values(true)
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("nlink")
+            .long("nlink")
+            .help("Only process files with N (more than +N, less than -N) links")
+            .value_name("[+-]N")
+            .allow_hyphen_values(true)
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("transform")
+            .long("transform")
+            .visible_alias("xform")
+            .help("Use sed replace EXPRESSION to transform file names")
+            .value_name("EXPRESSION")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("and")
+            .long("and")
+            .action(ArgAction::Count)
+            .help("Both the preceding and the following predicate must match (the default)")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("or")
+            .long("or")
+            .action(ArgAction::Count)
+            .help("Either the preceding or the following predicate must match")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("not")
+            .long("not")
+            .action(ArgAction::Count)
+            .help("The following predicate must not match")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("begin-group")
+            .long("begin-group")
+            .action(ArgAction::Count)
+            .help("Start a group of predicates")
+            .group("operation_modifiers_any"))
+        .arg(Arg::new("end-group")
+            .long("end-group")
+            .action(ArgAction::Count)
+            .help("End a group of predicates")
+            .group("operation_modifiers_any"))
         .group(ArgGroup::new("operation_modifiers_in_out")
             .required(false)
             .multiple(true))
@@ -325,6 +572,36 @@ fn process_args() {
             .help("Use COMMAND instead of rsh")
             .value_name("COMMAND")
             .group("operation_modifiers_in_out"))        
+        .arg(Arg::new("resume")
+            .long("resume")
+            .help("Record completed members in JOURNAL and, if JOURNAL already exists, continue an interrupted run after the last completed member")
+            .value_name("JOURNAL")
+            .group("operation_modifiers_in_out"))
+        .arg(Arg::new("build_index")
+            .long("build-index")
+            .help("Write an index of the archive members to FILE while creating or reading the archive")
+            .value_name("FILE")
+            .group("operation_modifiers_in_out"))
+        .arg(Arg::new("from_charset")
+            .long("from-charset")
+            .help("Convert member names and symbolic link targets from CHARSET (default UTF-8)")
+            .value_name("CHARSET")
+            .group("operation_modifiers_in_out"))
+        .arg(Arg::new("to_charset")
+            .long("to-charset")
+            .help("Convert member names and symbolic link targets to CHARSET (default UTF-8)")
+            .value_name("CHARSET")
+            .group("operation_modifiers_in_out"))
+        .arg(Arg::new("strip_components")
+            .long("strip-components")
+            .help("Strip N leading components from member names and hard link targets")
+            .value_name("N")
+            .group("operation_modifiers_in_out"))
+        .arg(Arg::new("rootless")
+            .long("rootless")
+            .action(clap::ArgAction::SetTrue)
+            .help("Record ownership, modes and device numbers in the user.containers.override_stat extended attribute")
+            .group("operation_modifiers_in_out"))
         .group(ArgGroup::new("operation_modifiers_in_only")
             .required(false)
             .multiple(true))
@@ -345,6 +622,52 @@ fn process_args() {
             .long("pattern-file")
             .help("Read additional patterns specifying filenames to extract or list from FILE")
             .value_name("FILE")
+            .action(ArgAction::Append)
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("wildcards")
+            .long("wildcards")
+            .action(ArgAction::Count)
+            .help("Match the following patterns as wildcards (the default)")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("regex")
+            .long("regex")
+            .action(ArgAction::Count)
+            .help("Match the following patterns as regular expressions")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("literal")
+            .long("literal")
+            .action(ArgAction::Count)
+            .help("Match the following patterns as literal names")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("anchored")
+            .long("anchored")
+            .action(ArgAction::Count)
+            .help("The following patterns must match from the start of the name (the default)")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("no-anchored")
+            .long("no-anchored")
+            .action(ArgAction::Count)
+            .help("The following patterns may match after any '/'")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("ignore-case")
+            .long("ignore-case")
+            .action(ArgAction::Count)
+            .help("Ignore case when matching the following patterns")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("no-ignore-case")
+            .long("no-ignore-case")
+            .action(ArgAction::Count)
+            .help("Match case when matching the following patterns (the default)")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("wildcards-match-slash")
+            .long("wildcards-match-slash")
+            .action(ArgAction::Count)
+            .help("Wildcards in the following patterns match '/' (the default)")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("no-wildcards-match-slash")
+            .long("no-wildcards-match-slash")
+            .action(ArgAction::Count)
+            .help("Wildcards in the following patterns do not match '/'")
             .group("operation_modifiers_in_only"))
         .arg(Arg::new("only_verify_crc")
             .long("only-verify-crc")
@@ -386,6 +709,26 @@ fn process_args() {
             .action(clap::ArgAction::SetTrue)
             .help("Extract files to standard output")
             .group("operation_modifiers_in_only"))
+        .arg(Arg::new("sync")
+            .long("sync")
+            .action(clap::ArgAction::SetTrue)
+            .help("Flush each extracted file to disk before moving it into place")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("transactional")
+            .long("transactional")
+            .action(clap::ArgAction::SetTrue)
+            .help("Stage all extracted files and move them into place only if the whole archive was extracted without errors")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("seek_remote")
+            .long("seek-remote")
+            .action(clap::ArgAction::SetTrue)
+            .help("The remote archive is a regular file: seek over skipped members instead of reading them")
+            .group("operation_modifiers_in_only"))
+        .arg(Arg::new("index")
+            .long("index")
+            .help("Use the index in FILE to go directly to the members matching the patterns")
+            .value_name("FILE")
+            .group("operation_modifiers_in_only"))
         .arg(Arg::new("input_archive")
             .short('I')
             .long("input-archive")
@@ -437,6 +780,42 @@ fn process_args() {
             .help("Include file metadata")
             .value_name("TYPE")
             .group("operation_modifiers_out_only"))
+        .arg(Arg::new("prefix")
+            .long("prefix")
+            .help("Add DIR as a leading path to every member name")
+            .value_name("DIR")
+            .value_parser(value_parser!(OsString))
+            .group("operation_modifiers_out_only"))
+        .arg(Arg::new("mode")
+            .long("mode")
+            .help("Change the permissions of archive members as chmod CHANGES does")
+            .value_name("CHANGES")
+            .group("operation_modifiers_out_only"))
+        .arg(Arg::new("set_owner")
+            .long("set-owner")
+            .help("Store NAME (and UID) as the owner of archive members")
+            .value_name("NAME[:UID]")
+            .group("operation_modifiers_out_only"))
+        .arg(Arg::new("set_group")
+            .long("set-group")
+            .help("Store NAME (and GID) as the group of archive members")
+            .value_name("NAME[:GID]")
+            .group("operation_modifiers_out_only"))
+        .arg(Arg::new("mtime")
+            .long("mtime")
+            .help("Store DATE, or the modification time of FILE, as the modification time of archive members")
+            .value_name("DATE|FILE")
+            .group("operation_modifiers_out_only"))
+        .arg(Arg::new("clamp_mtime")
+            .long("clamp-mtime")
+            .action(clap::ArgAction::SetTrue)
+            .help("Only change modification times newer than --mtime")
+            .group("operation_modifiers_out_only"))
+        .arg(Arg::new("list_directives")
+            .long("list-directives")
+            .action(clap::ArgAction::SetTrue)
+            .help("Accept -C DIR and -R OWNER directives and comments in the file list")
+            .group("operation_modifiers_out_only"))
         .group(ArgGroup::new("operation_modifiers_pass_only")
             .required(false)
             .multiple(true))
@@ -446,6 +825,19 @@ fn process_args() {
             .action(clap::ArgAction::SetTrue)
             .help("Link files instead of copying them, when possible")
             .group("operation_modifiers_pass_only"))        
+        .arg(Arg::new("reflink")
+            .long("reflink")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("auto")
+            .help("Clone the data of regular files instead of copying it. WHEN is 'auto' (fall back to copying, the default) or 'always'")
+            .value_name("WHEN")
+            .group("operation_modifiers_pass_only"))
+        .arg(Arg::new("link_dest")
+            .long("link-dest")
+            .help("Hard link files that are unchanged compared with the same file under DIR instead of copying them")
+            .value_name("DIR")
+            .group("operation_modifiers_pass_only"))
         .group(ArgGroup::new("operation_modifiers_in_out_2")
             .required(false)
             .multiple(true))
@@ -480,6 +872,21 @@ fn process_args() {
             .action(clap::ArgAction::SetTrue)
             .help("Reset the access times of files after reading them")
             .group("operation_modifiers_out_pass"))
+        .arg(Arg::new("jobs")
+            .long("jobs")
+            .help("Read ahead (copy-out) or copy (copy-pass) the data of regular files with N threads")
+            .value_name("N")
+            .group("operation_modifiers_out_pass"))
+        .arg(Arg::new("compress_program")
+            .long("compress-program")
+            .help("Pass the archive through COMMAND, which compresses it in a separate process")
+            .value_name("COMMAND")
+            .group("operation_modifiers_out_only"))
+        .arg(Arg::new("files_from")
+            .long("files-from")
+            .help("Read the list of file names from FILE instead of the standard input")
+            .value_name("FILE")
+            .group("operation_modifiers_out_pass"))
         .group(ArgGroup::new("operation_modifiers_in_pass")
             .required(false)
             .multiple(true))
@@ -523,7 +930,7 @@ fn process_args() {
         .override_usage(USAGE_STR)
         .arg(Arg::new("patterns")
             .num_args(0..)
-            .trailing_var_arg(true));
+            .value_parser(value_parser!(OsString)));
 
     let mut cmd_clone = cmd.clone();
 
@@ -633,13 +1040,6 @@ fn process_args() {
         let mt = parse_metadata_type(file_metadata_out_str);
         set_metadata_type(mt);
     }
-    if matches.contains_id("pattern_file") {
-        // -E
-        let pattern_file_str = matches
-            .get_one::<String>("pattern_file")
-            .expect("pattern_file should be present");
-        set_pattern_file_name(Some(pattern_file_str.clone()));
-    }
     if matches.contains_id("file") {
         // -F
         let file_str = matches
@@ -657,20 +1057,22 @@ fn process_args() {
             return USAGE_ERROR(0, format_args!("Archive format multiply defined"));
         }
 
-        let _ = match format_str.to_lowercase().as_str() {
-            "crc" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Crcascii),
-            "newc" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Newascii),
-            "odc" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Oldascii),
-            "bin" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Binary),
-            "ustar" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Ustar),
-            "tar" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Tar),
-            "hpodc" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Hpoldascii),
-            "hpbin" => Ok::<ArchiveFormat, ()>(ArchiveFormat::Hpbinary),
+        let format = match format_str.to_lowercase().as_str() {
+            "crc" => ArchiveFormat::Crcascii,
+            "newc" => ArchiveFormat::Newascii,
+            "newcx" => ArchiveFormat::Newxascii,
+            "odc" => ArchiveFormat::Oldascii,
+            "bin" => ArchiveFormat::Binary,
+            "ustar" => ArchiveFormat::Ustar,
+            "tar" => ArchiveFormat::Tar,
+            "hpodc" => ArchiveFormat::Hpoldascii,
+            "hpbin" => ArchiveFormat::Hpbinary,
             _ => {
-                USAGE_ERROR(0, format_args!("invalid archive format `{}'; valid formats are: crc newc odc bin ustar tar (all-caps also recognized)", format_str));
+                USAGE_ERROR(0, format_args!("invalid archive format `{}'; valid formats are: crc newc newcx odc bin ustar tar (all-caps also recognized)", format_str));
                 return;
             }
         };
+        set_archive_format(format);
     }
     // -i
     if matches.get_flag("extract") {
@@ -679,6 +1081,7 @@ fn process_args() {
             USAGE_ERROR(0, format_args!("Mode already defined"));
         }
         set_copy_function(process_copy_in);
+        set_copy_mode(CopyMode::In);
     }
     // -I
     if matches.contains_id("input_archive") {
@@ -745,6 +1148,7 @@ fn process_args() {
             USAGE_ERROR(0, format_args!("Mode already defined"));
         }
         set_copy_function(process_copy_out);
+        set_copy_mode(CopyMode::Out);
     }
 
     // -O
@@ -766,6 +1170,7 @@ fn process_args() {
             USAGE_ERROR(0, format_args!("Mode already defined"));
         }
         set_copy_function(process_copy_pass);
+        set_copy_mode(CopyMode::Pass);
     }
     // --ignore-devno
     if matches.get_flag("ignore_devno") {
@@ -814,6 +1219,18 @@ fn process_args() {
         set_quiet_flag(true);
     }
 
+    // --passwd-root、--passwd-file、--group-file，要在查找任何名字之前处理
+    let passwd_root = matches.get_one::<String>("passwd_root").map(Path::new);
+    let passwd_file = matches
+        .get_one::<String>("passwd_file")
+        .map(PathBuf::from)
+        .or_else(|| passwd_root.map(|root| root.join("etc/passwd")));
+    let group_file = matches
+        .get_one::<String>("group_file")
+        .map(PathBuf::from)
+        .or_else(|| passwd_root.map(|root| root.join("etc/group")));
+    idcache_init(passwd_file.as_deref(), group_file.as_deref());
+
     // -R
     if matches.contains_id("owner") {
         // -R
@@ -842,6 +1259,72 @@ fn process_args() {
             }
         };
     }
+    // --set-owner、--set-group 与 -R 相同，另外可以指定写入 tar 头部的名字
+    if let Some(spec) = matches.get_one::<String>("set_owner") {
+        match parse_owner_spec(spec) {
+            Ok((uid, name)) => {
+                set_set_owner(uid);
+                set_set_owner_flag(true);
+                set_owner_name(name);
+            }
+            Err(_) => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--set-owner'", spec),
+            ),
+        }
+    }
+    if let Some(spec) = matches.get_one::<String>("set_group") {
+        match parse_group_spec(spec) {
+            Ok((gid, name)) => {
+                set_set_group(gid);
+                set_set_group_flag(true);
+                set_group_name(name);
+            }
+            Err(_) => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--set-group'", spec),
+            ),
+        }
+    }
+    // --mode
+    if let Some(mode_str) = matches.get_one::<String>("mode") {
+        match mode_compile(mode_str) {
+            Some(changes) => set_mode_changes(changes),
+            None => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--mode'", mode_str),
+            ),
+        }
+    }
+    // --mtime、--clamp-mtime
+    if let Some(mtime_str) = matches.get_one::<String>("mtime") {
+        match parse_time_reference(mtime_str) {
+            Some(mtime) => set_mtime_override(Some(mtime)),
+            None => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--mtime'", mtime_str),
+            ),
+        }
+    }
+    if matches.get_flag("clamp_mtime") {
+        if get_mtime_override().is_none() {
+            USAGE_ERROR(0, format_args!("--clamp-mtime requires --mtime"));
+        }
+        set_clamp_mtime_flag(true);
+    }
+    // --files-from：文件名列表代替标准输入
+    if let Some(list_name) = matches.get_one::<String>("files_from") {
+        match File::open(list_name) {
+            Ok(list_file) => unsafe {
+                libc::dup2(list_file.as_raw_fd(), libc::STDIN_FILENO);
+            },
+            Err(_) => open_fatal(list_name),
+        }
+    }
+    // --list-directives
+    if matches.get_flag("list_directives") {
+        set_list_directives_flag(true);
+    }
     // -s
     if matches.get_flag("swap_bytes") {
         // -s
@@ -896,10 +1379,114 @@ fn process_args() {
     if matches.get_flag("to_stdout") {
         set_to_stdout_option(true);
     }
+    // --sync
+    if matches.get_flag("sync") {
+        set_sync_flag(true);
+    }
+    // --rootless
+    if matches.get_flag("rootless") {
+        set_rootless_flag(true);
+    }
+    // --transactional
+    if matches.get_flag("transactional") {
+        set_transactional_flag(true);
+    }
+    // --seek-remote
+    if matches.get_flag("seek_remote") {
+        set_seek_remote_flag(true);
+    }
+    // --resume
+    if matches.contains_id("resume") {
+        let resume_str = matches
+            .get_one::<String>("resume")
+            .expect("resume should be present");
+        set_resume_journal(Some(resume_str.clone()));
+    }
+    // --reflink
+    if let Some(reflink_str) = matches.get_one::<String>("reflink") {
+        match reflink_str.as_str() {
+            "auto" => set_reflink_mode(ReflinkMode::Auto),
+            "always" => set_reflink_mode(ReflinkMode::Always),
+            _ => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--reflink'", reflink_str),
+            ),
+        }
+    }
+    // --link-dest
+    if let Some(link_dest_str) = matches.get_one::<String>("link_dest") {
+        set_link_dest(Some(link_dest_str.clone()));
+    }
+    // --owner-map、--group-map、--idmap
+    let owner_map = matches
+        .get_one::<String>("owner_map")
+        .map_or_else(Vec::new, |file| read_map(file, getuidbyname));
+    let group_map = matches
+        .get_one::<String>("group_map")
+        .map_or_else(Vec::new, |file| read_map(file, getgidbyname));
+    let mut ranges = Vec::new();
+    for range_str in matches.get_many::<String>("idmap").into_iter().flatten() {
+        match parse_range(range_str) {
+            Some(range) => ranges.push(range),
+            None => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--idmap'", range_str),
+            ),
+        }
+    }
+    idmap_init(owner_map, group_map, ranges, matches.get_flag("reverse_map"));
+
+    // --strip-components
+    if let Some(count_str) = matches.get_one::<String>("strip_components") {
+        match count_str.parse::<usize>() {
+            Ok(count) => set_strip_components(count),
+            _ => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--strip-components'", count_str),
+            ),
+        }
+    }
+    // --prefix
+    if let Some(prefix) = matches.get_one::<OsString>("prefix") {
+        if prefix.is_empty() {
+            USAGE_ERROR(0, format_args!("invalid argument `' for `--prefix'"));
+        }
+        set_name_prefix(Some(prefix.clone()));
+    }
+    // --jobs
+    if let Some(jobs_str) = matches.get_one::<String>("jobs") {
+        match jobs_str.parse::<usize>() {
+            Ok(jobs) if jobs >= 1 => set_jobs(jobs),
+            _ => USAGE_ERROR(
+                0,
+                format_args!("invalid argument `{}' for `--jobs'", jobs_str),
+            ),
+        }
+    }
+    // --compress-program
+    if let Some(command) = matches.get_one::<String>("compress_program") {
+        set_compress_program(Some(command.clone()));
+    }
+    // --build-index
+    if let Some(index_str) = matches.get_one::<String>("build_index") {
+        set_build_index(Some(index_str.clone()));
+    }
+    // --index
+    if let Some(index_str) = matches.get_one::<String>("index") {
+        set_index_file(Some(index_str.clone()));
+    }
+    // --from-charset, --to-charset
+    if let Some(charset) = matches.get_one::<String>("from_charset") {
+        set_from_charset(Some(charset.clone()));
+    }
+    if let Some(charset) = matches.get_one::<String>("to_charset") {
+        set_to_charset(Some(charset.clone()));
+    }
 
     if get_copy_function().is_none() {
         if get_table_flag() {
             set_copy_function(process_copy_in);
+            set_copy_mode(CopyMode::In);
         } else {
             USAGE_ERROR(0, format_args!("You must specify one of -oipt options."));
         }
@@ -915,6 +1502,25 @@ fn process_args() {
         CHECK_USAGE!(reset_time_glag, "--reset", "--extract");
 
         CHECK_USAGE!(link_flag, "--link", "--extract");
+        CHECK_USAGE!(
+            get_reflink_mode() != ReflinkMode::Never,
+            "--reflink",
+            "--extract"
+        );
+        CHECK_USAGE!(get_link_dest().is_some(), "--link-dest", "--extract");
+        CHECK_USAGE!(get_jobs() > 1, "--jobs", "--extract");
+        CHECK_USAGE!(
+            get_compress_program().is_some(),
+            "--compress-program",
+            "--extract"
+        );
+        CHECK_USAGE!(get_name_prefix().is_some(), "--prefix", "--extract");
+        CHECK_USAGE!(!get_mode_changes().is_empty(), "--mode", "--extract");
+        CHECK_USAGE!(matches.contains_id("set_owner"), "--set-owner", "--extract");
+        CHECK_USAGE!(matches.contains_id("set_group"), "--set-group", "--extract");
+        CHECK_USAGE!(get_mtime_override().is_some(), "--mtime", "--extract");
+        CHECK_USAGE!(matches.contains_id("files_from"), "--files-from", "--extract");
+        CHECK_USAGE!(get_list_directives_flag(), "--list-directives", "--extract");
         //CHECK_USAGE!(unsafe { get_xstat() != lstat }, "--dereference", "--extract");
         // CHECK_USAGE!(false, "--dereference", "--extract");
         CHECK_USAGE!(get_append_flag(), "--append", "--extract");
@@ -925,10 +1531,32 @@ fn process_args() {
             "--extract"
         );
         CHECK_USAGE!(get_ignore_devno_option(), "--ignore-devno", "--extract");
+        CHECK_USAGE!(
+            get_resume_journal().is_some() && get_transactional_flag(),
+            "--resume",
+            "--transactional"
+        );
+        CHECK_USAGE!(
+            get_build_index().is_some() && get_index_file().is_some(),
+            "--build-index",
+            "--index"
+        );
+        CHECK_USAGE!(
+            get_rootless_flag() && get_no_chown_flag(),
+            "--no-preserve-owner",
+            "--rootless"
+        );
+        CHECK_USAGE!(
+            get_build_index().is_some() && get_resume_journal().is_some(),
+            "--build-index",
+            "--resume"
+        );
         if get_to_stdout_option() {
             CHECK_USAGE!(get_create_dir_flag(), "--make-directories", "--to-stdout");
             CHECK_USAGE!(get_rename_flag(), "--rename", "--to-stdout");
             CHECK_USAGE!(get_no_chown_flag(), "--no-preserve-owner", "--to-stdout");
+            CHECK_USAGE!(get_sync_flag(), "--sync", "--to-stdout");
+            CHECK_USAGE!(get_transactional_flag(), "--transactional", "--to-stdout");
             CHECK_USAGE!(
                 get_set_owner_flag() || get_set_group_flag(),
                 "--owner",
@@ -948,11 +1576,9 @@ fn process_args() {
         if get_archive_format() == ArchiveFormat::Crcascii {
             set_crc_i_flag(true);
         }
-        if let Some(patterns) = matches.get_many::<String>("patterns") {
-            let pattern_vec: Vec<String> = patterns.map(|s| s.to_string()).collect();
-            set_num_patterns(pattern_vec.len() as i32);
-            set_save_patterns(pattern_vec);
-        }
+        let patterns = collect_patterns(&matches, 0);
+        set_num_patterns(patterns.len() as i32);
+        set_save_patterns(patterns);
 
         if get_input_archive_name().is_some() {
             set_archive_name(get_input_archive_name().clone());
@@ -970,7 +1596,18 @@ fn process_args() {
         CHECK_USAGE!(get_table_flag(), "--list", "--create");
         CHECK_USAGE!(get_unconditional_flag(), "--unconditional", "--create");
         CHECK_USAGE!(get_link_flag(), "--link", "--create");
-        CHECK_USAGE!(get_sparse_flag(), "--sparse", "--create");
+        CHECK_USAGE!(
+            get_reflink_mode() != ReflinkMode::Never,
+            "--reflink",
+            "--create"
+        );
+        CHECK_USAGE!(get_link_dest().is_some(), "--link-dest", "--create");
+        // ustar 格式用 --sparse 选择写出稀疏成员
+        CHECK_USAGE!(
+            get_sparse_flag() && get_archive_format() != ArchiveFormat::Ustar,
+            "--sparse",
+            "--create"
+        );
         CHECK_USAGE!(
             get_retain_time_flag(),
             "--preserve-modification-time",
@@ -984,6 +1621,39 @@ fn process_args() {
             "--create"
         );
         CHECK_USAGE!(get_to_stdout_option(), "--to-stdout", "--create");
+        CHECK_USAGE!(get_sync_flag(), "--sync", "--create");
+        CHECK_USAGE!(get_transactional_flag(), "--transactional", "--create");
+        CHECK_USAGE!(get_index_file().is_some(), "--index", "--create");
+        // 指令要按顺序执行，不能预读文件名列表
+        CHECK_USAGE!(
+            get_list_directives_flag() && get_jobs() > 1,
+            "--jobs",
+            "--list-directives"
+        );
+        CHECK_USAGE!(
+            get_list_directives_flag() && get_resume_journal().is_some(),
+            "--resume",
+            "--list-directives"
+        );
+        CHECK_USAGE!(
+            get_build_index().is_some() && get_resume_journal().is_some(),
+            "--build-index",
+            "--resume"
+        );
+        // 压缩后的归档不能定位，也不能续写
+        if get_compress_program().is_some() {
+            CHECK_USAGE!(get_append_flag(), "--compress-program", "--append");
+            CHECK_USAGE!(
+                get_resume_journal().is_some(),
+                "--compress-program",
+                "--resume"
+            );
+            CHECK_USAGE!(
+                get_build_index().is_some(),
+                "--compress-program",
+                "--build-index"
+            );
+        }
 
         if get_append_flag() && get_archive_name().is_none() && get_output_archive_name().is_none()
         {
@@ -1007,6 +1677,11 @@ fn process_args() {
             USAGE_ERROR(1, format_args!("Both -O and -F are used in copy-out mode"));
         }
 
+        // 命令行上的模式选出要写入归档的文件
+        let patterns = collect_patterns(&matches, 0);
+        set_num_patterns(patterns.len() as i32);
+        set_save_patterns(patterns);
+
         if get_archive_format() == ArchiveFormat::Unknown {
             set_archive_format(ArchiveFormat::Binary);
         }
@@ -1065,6 +1740,46 @@ fn process_args() {
             "--pass-through"
         );
         CHECK_USAGE!(get_to_stdout_option(), "--to-stdout", "--pass-through");
+        CHECK_USAGE!(get_sync_flag(), "--sync", "--pass-through");
+        CHECK_USAGE!(
+            get_transactional_flag(),
+            "--transactional",
+            "--pass-through"
+        );
+        CHECK_USAGE!(get_resume_journal().is_some(), "--resume", "--pass-through");
+        CHECK_USAGE!(
+            get_compress_program().is_some(),
+            "--compress-program",
+            "--pass-through"
+        );
+        CHECK_USAGE!(
+            get_build_index().is_some(),
+            "--build-index",
+            "--pass-through"
+        );
+        CHECK_USAGE!(get_index_file().is_some(), "--index", "--pass-through");
+        CHECK_USAGE!(
+            get_strip_components() > 0,
+            "--strip-components",
+            "--pass-through"
+        );
+        CHECK_USAGE!(get_name_prefix().is_some(), "--prefix", "--pass-through");
+        CHECK_USAGE!(get_rootless_flag(), "--rootless", "--pass-through");
+        CHECK_USAGE!(!get_mode_changes().is_empty(), "--mode", "--pass-through");
+        CHECK_USAGE!(matches.contains_id("set_owner"), "--set-owner", "--pass-through");
+        CHECK_USAGE!(matches.contains_id("set_group"), "--set-group", "--pass-through");
+        CHECK_USAGE!(get_mtime_override().is_some(), "--mtime", "--pass-through");
+        CHECK_USAGE!(
+            get_list_directives_flag(),
+            "--list-directives",
+            "--pass-through"
+        );
+        CHECK_USAGE!(
+            get_from_charset().is_some(),
+            "--from-charset",
+            "--pass-through"
+        );
+        CHECK_USAGE!(get_to_charset().is_some(), "--to-charset", "--pass-through");
         CHECK_USAGE!(
             get_renumber_inodes_option(),
             "--renumber-inodes",
@@ -1076,15 +1791,46 @@ fn process_args() {
             "--pass-through"
         );
 
-        if let Some(patterns) = matches.get_many::<String>("patterns") {
-            let pattern_vec: Vec<String> = patterns.map(|s| s.to_string()).collect();
+        if let Some(patterns) = matches.get_many::<OsString>("patterns") {
+            let pattern_vec: Vec<OsString> = patterns.cloned().collect();
             if !pattern_vec.is_empty() {
                 set_directory_name(Some(pattern_vec[0].clone()));
             }
         }
+        // 目标目录之后的参数是选出要复制的文件的模式
+        let patterns = collect_patterns(&matches, 1);
+        set_num_patterns(patterns.len() as i32);
+        set_save_patterns(patterns);
         // unsafe { directory_name = args[index as usize].as_ptr() as *mut c_char }; // 需要定义 directory_name，注意这里使用了 unsafe 代码
     };
 
+    set_selection(collect_predicates(&matches));
+
+    // --transform 可以给出多次，按命令行上的顺序作用于名字
+    let mut transforms = Vec::new();
+    for expression in matches.get_many::<String>("transform").into_iter().flatten() {
+        match parse_transforms(expression) {
+            Ok(parsed) => transforms.extend(parsed),
+            Err(e) => USAGE_ERROR(
+                0,
+                format_args!("{}: invalid transform expression: {}", expression, e),
+            ),
+        }
+    }
+    set_transforms(transforms);
+
+    if get_from_charset().is_some() || get_to_charset().is_some() {
+        let from = get_from_charset().unwrap_or_else(|| DEFAULT_CHARSET.to_string());
+        let to = get_to_charset().unwrap_or_else(|| DEFAULT_CHARSET.to_string());
+        if !charset_init(&from, &to) {
+            error(
+                PAXEXIT_FAILURE,
+                0,
+                format_args!("conversion from `{}' to `{}' is not supported", from, to),
+            );
+        }
+    }
+
     if get_archive_name().is_some() {
         if get_copy_function() != Some(process_copy_in)
             && get_copy_function() != Some(process_copy_out)
@@ -1148,7 +1894,7 @@ fn main() {
     // setlocale(LocaleCategory::LcAll, "");
     // textdomain(PACKAGE).unwrap();
 
-    set_program_name(&env::args().collect::<Vec<String>>()[0]);
+    set_program_name(&env::args_os().next().unwrap_or_default().to_string_lossy().into_owned());
 
     let app_args = AppArgs::new();
     let _ = APPARGS.set(Mutex::new(app_args));
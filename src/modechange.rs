@@ -0,0 +1,182 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 解析和应用 chmod 风格的权限修改（--mode），与 gnulib 的 modechange 相同。
+// MODE 是八进制数，或者用 ',' 分隔的 [ugoa]*([-+=]([rwxXst]*|[ugo]))+。
+// 与 tar 一样不考虑 umask，省略 [ugoa] 时作用于所有的位。
+
+const CHMOD_MODE_BITS: u32 = 0o7777;
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+const S_ISVTX: u32 = 0o1000;
+const S_IRWXU: u32 = 0o700;
+const S_IRWXG: u32 = 0o070;
+const S_IRWXO: u32 = 0o007;
+const ALL_READ: u32 = 0o444;
+const ALL_WRITE: u32 = 0o222;
+const ALL_EXEC: u32 = 0o111;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChangeFlag {
+    // 按 value 修改
+    Ordinary,
+    // 'X'：目录或者已有执行位时才加上执行位
+    XIfAnyX,
+    // [ugo]：复制已有的权限
+    CopyExisting,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModeChange {
+    op: char,
+    flag: ChangeFlag,
+    affected: u32,
+    value: u32,
+    mentioned: u32,
+}
+
+pub fn mode_compile(text: &str) -> Option<Vec<ModeChange>> {
+    if !text.is_empty() && text.bytes().all(|c| (b'0'..=b'7').contains(&c)) {
+        let value = u32::from_str_radix(text, 8).ok()?;
+        if value > CHMOD_MODE_BITS {
+            return None;
+        }
+        return Some(vec![ModeChange {
+            op: '=',
+            flag: ChangeFlag::Ordinary,
+            affected: CHMOD_MODE_BITS,
+            value,
+            mentioned: CHMOD_MODE_BITS,
+        }]);
+    }
+
+    let mut changes = Vec::new();
+    for clause in text.split(',') {
+        let mut chars = clause.chars().peekable();
+        let mut who = 0;
+        while let Some(&c) = chars.peek() {
+            who |= match c {
+                'u' => S_ISUID | S_IRWXU,
+                'g' => S_ISGID | S_IRWXG,
+                'o' => S_ISVTX | S_IRWXO,
+                'a' => CHMOD_MODE_BITS,
+                _ => break,
+            };
+            chars.next();
+        }
+        let affected = if who == 0 { CHMOD_MODE_BITS } else { who };
+
+        // 至少要有一个操作
+        if !matches!(chars.peek(), Some('=' | '+' | '-')) {
+            return None;
+        }
+        while let Some(op) = chars.next() {
+            if !matches!(op, '=' | '+' | '-') {
+                return None;
+            }
+            let (flag, value) = match chars.peek() {
+                Some(&c @ ('u' | 'g' | 'o')) => {
+                    chars.next();
+                    let value = match c {
+                        'u' => S_IRWXU,
+                        'g' => S_IRWXG,
+                        _ => S_IRWXO,
+                    };
+                    (ChangeFlag::CopyExisting, value)
+                }
+                _ => {
+                    let mut flag = ChangeFlag::Ordinary;
+                    let mut value = 0;
+                    while let Some(&c) = chars.peek() {
+                        match c {
+                            'r' => value |= ALL_READ,
+                            'w' => value |= ALL_WRITE,
+                            'x' => value |= ALL_EXEC,
+                            'X' => flag = ChangeFlag::XIfAnyX,
+                            's' => value |= S_ISUID | S_ISGID,
+                            't' => value |= S_ISVTX,
+                            _ => break,
+                        }
+                        chars.next();
+                    }
+                    (flag, value)
+                }
+            };
+            changes.push(ModeChange {
+                op,
+                flag,
+                affected,
+                value,
+                mentioned: who,
+            });
+        }
+    }
+    Some(changes)
+}
+
+pub fn mode_adjust(oldmode: u32, dir: bool, changes: &[ModeChange]) -> u32 {
+    let mut newmode = oldmode & CHMOD_MODE_BITS;
+    for change in changes {
+        // 没有明确指定时，目录的 setuid 和 setgid 位保持不变
+        let omit_change = if dir { S_ISUID | S_ISGID } else { 0 } & !change.mentioned;
+        let mut value = change.value;
+        match change.flag {
+            ChangeFlag::Ordinary => {}
+            ChangeFlag::CopyExisting => {
+                value &= newmode;
+                let mut copied = 0;
+                for bits in [ALL_READ, ALL_WRITE, ALL_EXEC] {
+                    if value & bits != 0 {
+                        copied |= bits;
+                    }
+                }
+                value = copied;
+            }
+            ChangeFlag::XIfAnyX => {
+                if newmode & ALL_EXEC != 0 || dir {
+                    value |= ALL_EXEC;
+                }
+            }
+        }
+        value &= change.affected & !omit_change;
+        match change.op {
+            '=' => {
+                let preserved = !change.affected | omit_change;
+                newmode = (newmode & preserved) | value;
+            }
+            '+' => newmode |= value,
+            _ => newmode &= !value,
+        }
+    }
+    newmode & CHMOD_MODE_BITS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adjust(mode: &str, oldmode: u32, dir: bool) -> u32 {
+        mode_adjust(oldmode, dir, &mode_compile(mode).unwrap())
+    }
+
+    #[test]
+    fn test_mode_adjust() {
+        assert_eq!(adjust("go-w,u+rwX", 0o666, false), 0o644);
+        assert_eq!(adjust("go-w,u+rwX", 0o775, false), 0o755);
+        assert_eq!(adjust("a+X", 0o640, true), 0o751);
+        assert_eq!(adjust("a+X", 0o640, false), 0o640);
+        assert_eq!(adjust("u=rw,go=r", 0o4777, false), 0o644);
+        assert_eq!(adjust("u=rwx,o=", 0o2775, true), 0o2770);
+        assert_eq!(adjust("g=u", 0o740, false), 0o770);
+        assert_eq!(adjust("o+t,u+s", 0o755, false), 0o5755);
+        assert_eq!(adjust("-w", 0o666, false), 0o444);
+        assert_eq!(adjust("0644", 0o4777, true), 0o644);
+
+        assert!(mode_compile("").is_none());
+        assert!(mode_compile("u").is_none());
+        assert!(mode_compile("u+q").is_none());
+        assert!(mode_compile("go-w,").is_none());
+        assert!(mode_compile("17777").is_none());
+    }
+}
@@ -0,0 +1,216 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// copy-out 文件名列表中的指令（--list-directives）。
+// 列表的每一行（-0 时每个以 NUL 结尾的字符串）是一个文件名或者一条指令：
+//   # 注释                    忽略，空行也被忽略
+//   -C DIR、--directory=DIR   后面的文件名相对于 DIR，相对的 DIR 相对于当前目录
+//   -R SPEC、--owner=SPEC     后面的文件的属主和属组与命令行上的 -R SPEC 相同
+//   -R、--owner               恢复命令行上的属主设置
+// 以 '-' 开头的其他行是错误，这样的文件名要写成 "./-NAME"。
+
+use std::env;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use gnu::error::*;
+use pax::paxerror::chdir_fatal;
+use pax::paxlib::PAXEXIT_FAILURE;
+
+use crate::appargs::*;
+use crate::userspec::parse_user_spec;
+use crate::util::quote_name;
+
+#[derive(Debug, PartialEq)]
+pub enum ListLine<'a> {
+    Name,
+    Skip,
+    ChangeDir(&'a OsStr),
+    Owner(Option<&'a str>),
+}
+
+// 指令的参数："-X ARG" 或 "--long=ARG"，没有参数时为 Some("")
+fn directive_arg<'a>(line: &'a [u8], short: &[u8], long: &[u8]) -> Option<&'a [u8]> {
+    if let Some(rest) = line.strip_prefix(short) {
+        if rest.is_empty() {
+            return Some(rest);
+        }
+        if rest[0] == b' ' || rest[0] == b'\t' {
+            return Some(rest.trim_ascii_start());
+        }
+    }
+    let rest = line.strip_prefix(long)?;
+    match rest.first() {
+        None => Some(rest),
+        Some(b'=') => Some(&rest[1..]),
+        Some(_) => None,
+    }
+}
+
+pub fn parse_list_line(line: &[u8]) -> Result<ListLine<'_>, String> {
+    if line.is_empty() || line[0] == b'#' {
+        return Ok(ListLine::Skip);
+    }
+    if line[0] != b'-' {
+        return Ok(ListLine::Name);
+    }
+    let quoted = || quote_name(OsStr::from_bytes(line));
+    if let Some(dir) = directive_arg(line, b"-C", b"--directory") {
+        if dir.is_empty() {
+            return Err(format!("{}: missing directory", quoted()));
+        }
+        return Ok(ListLine::ChangeDir(OsStr::from_bytes(dir)));
+    }
+    if let Some(spec) = directive_arg(line, b"-R", b"--owner") {
+        if spec.is_empty() {
+            return Ok(ListLine::Owner(None));
+        }
+        return match std::str::from_utf8(spec) {
+            Ok(spec) => Ok(ListLine::Owner(Some(spec))),
+            Err(_) => Err(format!("{}: invalid owner", quoted())),
+        };
+    }
+    Err(format!("{}: unknown list directive", quoted()))
+}
+
+// 命令行上的属主设置，-R 指令只在它的基础上修改
+struct OwnerDefaults {
+    owner: u32,
+    group: u32,
+    owner_flag: bool,
+    group_flag: bool,
+    owner_name: Option<String>,
+    group_name: Option<String>,
+}
+
+impl OwnerDefaults {
+    fn save() -> Self {
+        OwnerDefaults {
+            owner: get_set_owner(),
+            group: get_set_group(),
+            owner_flag: get_set_owner_flag(),
+            group_flag: get_set_group_flag(),
+            owner_name: get_owner_name(),
+            group_name: get_group_name(),
+        }
+    }
+
+    fn restore(&self) {
+        set_set_owner(self.owner);
+        set_set_group(self.group);
+        set_set_owner_flag(self.owner_flag);
+        set_set_group_flag(self.group_flag);
+        set_owner_name(self.owner_name.clone());
+        set_group_name(self.group_name.clone());
+    }
+}
+
+pub struct ListDirectives {
+    start_dir: PathBuf,
+    dir_changed: bool,
+    owner_defaults: OwnerDefaults,
+}
+
+impl ListDirectives {
+    pub fn new() -> Self {
+        let start_dir = match env::current_dir() {
+            Ok(dir) => dir,
+            Err(e) => {
+                error(
+                    PAXEXIT_FAILURE,
+                    e.raw_os_error().unwrap_or(0),
+                    format_args!("cannot get the current directory"),
+                );
+                PathBuf::from(".")
+            }
+        };
+        ListDirectives {
+            start_dir,
+            dir_changed: false,
+            owner_defaults: OwnerDefaults::save(),
+        }
+    }
+
+    pub fn dir_changed(&self) -> bool {
+        self.dir_changed
+    }
+
+    pub fn change_dir(&mut self, dir: &OsStr) {
+        if env::set_current_dir(dir).is_err() {
+            chdir_fatal(&quote_name(dir));
+        }
+        self.dir_changed = true;
+    }
+
+    // 列表结束后回到开始时的目录，索引等文件仍相对于它
+    pub fn restore_dir(&mut self) {
+        if self.dir_changed {
+            self.change_dir(&self.start_dir.clone().into_os_string());
+            self.dir_changed = false;
+        }
+    }
+
+    pub fn set_owner(&self, spec: Option<&str>) {
+        self.owner_defaults.restore();
+        let Some(spec) = spec else {
+            return;
+        };
+        match parse_user_spec(spec) {
+            Ok((uid, gid, username, groupname)) => {
+                if username.is_some() {
+                    set_set_owner(uid);
+                    set_set_owner_flag(true);
+                    set_owner_name(None);
+                }
+                if groupname.is_some() {
+                    set_set_group(gid);
+                    set_set_group_flag(true);
+                    set_group_name(None);
+                }
+            }
+            Err(e) => error(
+                PAXEXIT_FAILURE,
+                0,
+                format_args!("invalid owner `{}' in file list: {}", spec, e),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> Result<ListLine<'_>, String> {
+        Ok(ListLine::ChangeDir(OsStr::new(name)))
+    }
+
+    #[test]
+    fn test_parse_list_line() {
+        assert_eq!(parse_list_line(b"usr/bin/ls"), Ok(ListLine::Name));
+        assert_eq!(parse_list_line(b"./-C"), Ok(ListLine::Name));
+        assert_eq!(parse_list_line(b""), Ok(ListLine::Skip));
+        assert_eq!(parse_list_line(b"# -C build"), Ok(ListLine::Skip));
+        assert_eq!(parse_list_line(b"-C build/out"), dir("build/out"));
+        assert_eq!(parse_list_line(b"-C \t/opt"), dir("/opt"));
+        assert_eq!(parse_list_line(b"--directory=a b"), dir("a b"));
+        assert_eq!(
+            parse_list_line(b"-R root:wheel"),
+            Ok(ListLine::Owner(Some("root:wheel")))
+        );
+        assert_eq!(
+            parse_list_line(b"--owner=+0"),
+            Ok(ListLine::Owner(Some("+0")))
+        );
+        assert_eq!(parse_list_line(b"-R"), Ok(ListLine::Owner(None)));
+        assert_eq!(parse_list_line(b"--owner"), Ok(ListLine::Owner(None)));
+
+        assert!(parse_list_line(b"-C").is_err());
+        assert!(parse_list_line(b"--directory=").is_err());
+        assert!(parse_list_line(b"-Cbuild").is_err());
+        assert!(parse_list_line(b"--owners=root").is_err());
+        assert!(parse_list_line(b"-x").is_err());
+    }
+}
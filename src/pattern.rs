@@ -0,0 +1,382 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 文件名模式的匹配。
+// 每个模式带有自己的匹配方式：通配符（fnmatch）、正则表达式或按字面比较，
+// 以及是否锚定在名字开头、是否忽略大小写、通配符是否匹配 '/'。命令行上的
+// --regex、--ignore-case 等选项作用于它后面的模式，模式文件中单独一行的这些
+// 选项作用于文件中它后面的行。
+//
+// 模式文件的写法与 .gitignore 相似：'#' 开头的行是注释，"+ " 开头的行是包含
+// 模式，"- " 或 '!' 开头的行是排除模式，其他的行是包含模式，行首的 '\' 使
+// 后面的 '#'、'!'、'+' 或 '-' 失去特殊含义。名字由与它匹配的最后一个模式决定
+// 是否选中；与所有模式都不匹配的名字只在没有包含模式时选中。
+
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+
+use libc::{fnmatch, FNM_CASEFOLD, FNM_PATHNAME};
+use regex::bytes::{Regex, RegexBuilder};
+
+use gnu::error::*;
+use pax::paxerror::open_fatal;
+use pax::paxlib::PAXEXIT_FAILURE;
+
+use crate::appargs::*;
+use crate::util::quote_name;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternKind {
+    Wildcard,
+    Regex,
+    Literal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchOptions {
+    pub kind: PatternKind,
+    pub anchored: bool,
+    pub ignore_case: bool,
+    pub wildcards_match_slash: bool,
+}
+
+// 改变匹配方式的选项，在命令行和模式文件中都可以使用
+pub const MATCH_OPTION_NAMES: &[&str] = &[
+    "anchored",
+    "no-anchored",
+    "ignore-case",
+    "no-ignore-case",
+    "wildcards-match-slash",
+    "no-wildcards-match-slash",
+    "wildcards",
+    "regex",
+    "literal",
+];
+
+impl MatchOptions {
+    // 与原来的 fnmatch(pattern, name, 0) 相同：匹配整个名字，'*' 可以匹配 '/'
+    pub fn new() -> Self {
+        MatchOptions {
+            kind: PatternKind::Wildcard,
+            anchored: true,
+            ignore_case: false,
+            wildcards_match_slash: true,
+        }
+    }
+
+    // 应用 MATCH_OPTION_NAMES 中的一个选项（不带前面的 "--"）
+    pub fn apply(&mut self, option: &str) -> bool {
+        match option {
+            "anchored" => self.anchored = true,
+            "no-anchored" => self.anchored = false,
+            "ignore-case" => self.ignore_case = true,
+            "no-ignore-case" => self.ignore_case = false,
+            "wildcards-match-slash" => self.wildcards_match_slash = true,
+            "no-wildcards-match-slash" => self.wildcards_match_slash = false,
+            "wildcards" => self.kind = PatternKind::Wildcard,
+            "regex" => self.kind = PatternKind::Regex,
+            "literal" => self.kind = PatternKind::Literal,
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    text: OsString,
+    options: MatchOptions,
+    regex: Option<Regex>,
+    exclude: bool,
+}
+
+impl Pattern {
+    pub fn new(text: &OsStr, options: MatchOptions) -> Result<Pattern, String> {
+        let regex = if options.kind == PatternKind::Regex {
+            let Some(expr) = text.to_str() else {
+                return Err("regular expression is not valid UTF-8".to_string());
+            };
+            let build = |expr: &str| {
+                RegexBuilder::new(expr)
+                    .case_insensitive(options.ignore_case)
+                    .build()
+                    .map_err(|e| e.to_string())
+            };
+            // 先单独编译，使错误信息中是用户给出的表达式。锚定时必须匹配整个
+            // 名字，否则可以匹配名字中的任何部分
+            let regex = build(expr)?;
+            if options.anchored {
+                Some(build(&format!(r"\A(?:{})\z", expr))?)
+            } else {
+                Some(regex)
+            }
+        } else {
+            None
+        };
+        Ok(Pattern {
+            text: text.to_os_string(),
+            options,
+            regex,
+            exclude: false,
+        })
+    }
+
+    pub fn matches(&self, name: &OsStr) -> bool {
+        if let Some(ref regex) = self.regex {
+            return regex.is_match(name.as_bytes());
+        }
+        let name = name.as_bytes();
+        if self.options.anchored {
+            return self.matches_whole(name);
+        }
+        // 不锚定时也可以从任何一个 '/' 之后开始匹配
+        self.matches_whole(name)
+            || name
+                .iter()
+                .enumerate()
+                .any(|(i, &c)| c == b'/' && self.matches_whole(&name[i + 1..]))
+    }
+
+    fn matches_whole(&self, name: &[u8]) -> bool {
+        let pattern = self.text.as_bytes();
+        match self.options.kind {
+            PatternKind::Literal => {
+                if !self.options.ignore_case {
+                    return pattern == name;
+                }
+                match (std::str::from_utf8(pattern), std::str::from_utf8(name)) {
+                    (Ok(pattern), Ok(name)) => pattern.to_lowercase() == name.to_lowercase(),
+                    _ => pattern.eq_ignore_ascii_case(name),
+                }
+            }
+            _ => {
+                let (Ok(pattern), Ok(name)) = (CString::new(pattern), CString::new(name)) else {
+                    return false;
+                };
+                let mut flags = 0;
+                if !self.options.wildcards_match_slash {
+                    flags |= FNM_PATHNAME;
+                }
+                if self.options.ignore_case {
+                    flags |= FNM_CASEFOLD;
+                }
+                unsafe { fnmatch(pattern.as_ptr(), name.as_ptr(), flags) == 0 }
+            }
+        }
+    }
+}
+
+// 把模式文件的内容分成模式。单独一行的 "--regex" 等选项改变后面各行的
+// 匹配方式，OPTIONS 是文件开始时的匹配方式
+pub fn parse_pattern_lines(
+    data: &[u8],
+    mut options: MatchOptions,
+) -> Result<Vec<Pattern>, (OsString, String)> {
+    let mut patterns = Vec::new();
+    for line in data.split(|&c| c == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        if let Some(option) = line.strip_prefix(b"--") {
+            if let Ok(option) = std::str::from_utf8(option) {
+                if options.apply(option) {
+                    continue;
+                }
+            }
+        }
+        let (exclude, line) = if let Some(rest) = line.strip_prefix(b"+ ") {
+            (false, rest)
+        } else if let Some(rest) = line.strip_prefix(b"- ") {
+            (true, rest)
+        } else if let Some(rest) = line.strip_prefix(b"!") {
+            (true, rest)
+        } else if line.len() > 1 && line[0] == b'\\' && b"#!+-".contains(&line[1]) {
+            (false, &line[1..])
+        } else {
+            (false, line)
+        };
+        if line.is_empty() {
+            continue;
+        }
+        let text = OsStr::from_bytes(line);
+        match Pattern::new(text, options) {
+            Ok(pattern) => patterns.push(Pattern { exclude, ..pattern }),
+            Err(e) => return Err((text.to_os_string(), e)),
+        }
+    }
+    Ok(patterns)
+}
+
+// 读取 -E 给出的模式文件，文件打不开或其中有错误的模式时退出
+pub fn read_pattern_file(file_name: &str, options: MatchOptions) -> Vec<Pattern> {
+    let data = match fs::read(file_name) {
+        Ok(data) => data,
+        Err(_) => {
+            open_fatal(file_name);
+            return Vec::new();
+        }
+    };
+    match parse_pattern_lines(&data, options) {
+        Ok(patterns) => patterns,
+        Err((text, e)) => {
+            error(
+                PAXEXIT_FAILURE,
+                0,
+                format_args!(
+                    "{}: {}: invalid pattern: {}",
+                    file_name,
+                    quote_name(&text),
+                    e
+                ),
+            );
+            Vec::new()
+        }
+    }
+}
+
+// 与 NAME 匹配的最后一个模式决定是否选中它
+fn patterns_select(patterns: &[Pattern], name: &OsStr) -> bool {
+    match patterns.iter().rev().find(|pattern| pattern.matches(name)) {
+        Some(pattern) => !pattern.exclude,
+        None => patterns.iter().all(|pattern| pattern.exclude),
+    }
+}
+
+// 按模式和 -f 决定是否处理这个名字，没有模式时处理所有的名字
+pub fn name_selected(name: &OsStr) -> bool {
+    if get_num_patterns() <= 0 {
+        return true;
+    }
+    patterns_select(&get_save_patterns(), name) == get_copy_matching_files()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(text: &str, flags: &[&str]) -> Pattern {
+        let mut options = MatchOptions::new();
+        for flag in flags {
+            assert!(options.apply(flag));
+        }
+        Pattern::new(OsStr::new(text), options).unwrap()
+    }
+
+    fn matches(pattern: &Pattern, name: &[u8]) -> bool {
+        pattern.matches(OsStr::from_bytes(name))
+    }
+
+    #[test]
+    fn test_wildcard_options() {
+        let default = pattern("usr/*.so", &[]);
+        assert!(matches(&default, b"usr/lib/libc.so"));
+        assert!(!matches(&default, b"./usr/lib/libc.so"));
+
+        let no_slash = pattern("usr/*.so", &["no-wildcards-match-slash"]);
+        assert!(matches(&no_slash, b"usr/libc.so"));
+        assert!(!matches(&no_slash, b"usr/lib/libc.so"));
+
+        let unanchored = pattern("lib/*.so", &["no-anchored", "no-wildcards-match-slash"]);
+        assert!(matches(&unanchored, b"./usr/lib/libc.so"));
+        assert!(!matches(&unanchored, b"usr/xlib/libc.so"));
+
+        let folded = pattern("*.JPG", &["ignore-case"]);
+        assert!(matches(&folded, b"img/a.jpg"));
+        assert!(!matches(&pattern("*.JPG", &[]), b"img/a.jpg"));
+    }
+
+    #[test]
+    fn test_regex_and_literal() {
+        let anchored = pattern(r"lib[0-9]+\.so", &["regex"]);
+        assert!(matches(&anchored, b"lib64.so"));
+        assert!(!matches(&anchored, b"usr/lib64.so"));
+        let search = pattern(r"lib[0-9]+\.so", &["regex", "no-anchored"]);
+        assert!(matches(&search, b"usr/lib64.so.1"));
+        assert!(matches(&pattern("a.c", &["regex", "ignore-case"]), b"AbC"));
+        // 名字不是合法的 UTF-8 时用 (?-u:.) 匹配单个字节
+        assert!(!matches(&pattern(r"vm.\.img", &["regex"]), b"vm\xe9.img"));
+        assert!(matches(
+            &pattern(r"vm(?-u:.)\.img", &["regex"]),
+            b"vm\xe9.img"
+        ));
+        assert!(matches(
+            &pattern(r"vm.\.img", &["regex"]),
+            "vmé.img".as_bytes()
+        ));
+        assert!(Pattern::new(OsStr::new("(a"), {
+            let mut options = MatchOptions::new();
+            options.apply("regex");
+            options
+        })
+        .is_err());
+
+        let literal = pattern("a*[b]", &["literal"]);
+        assert!(matches(&literal, b"a*[b]"));
+        assert!(!matches(&literal, b"ax[b]"));
+        assert!(matches(
+            &pattern("ÄB", &["literal", "ignore-case"]),
+            "äb".as_bytes()
+        ));
+        assert!(matches(&pattern("b", &["literal", "no-anchored"]), b"a/b"));
+    }
+
+    #[test]
+    fn test_parse_pattern_lines() {
+        let data = b"*.c\n--regex\n--ignore-case\n.*\\.H\n\n--literal\n--x\r\n";
+        let patterns = parse_pattern_lines(data, MatchOptions::new()).unwrap();
+        let texts: Vec<&OsStr> = patterns.iter().map(|p| p.text.as_os_str()).collect();
+        assert_eq!(
+            texts,
+            [OsStr::new("*.c"), OsStr::new(".*\\.H"), OsStr::new("--x")]
+        );
+        assert!(matches(&patterns[0], b"a.c"));
+        assert!(matches(&patterns[1], b"a.h"));
+        assert!(matches(&patterns[2], b"--X"));
+        assert!(!matches(&patterns[2], b"a.c"));
+
+        let err = parse_pattern_lines(b"--regex\n[\n", MatchOptions::new()).unwrap_err();
+        assert_eq!(err.0, "[");
+    }
+
+    #[test]
+    fn test_include_exclude() {
+        let data = b"# rootfs\nusr/*\n!usr/share/*\n+ usr/share/man/*\n- *.a\n\\#tmp\n\\!x\n";
+        let patterns = parse_pattern_lines(data, MatchOptions::new()).unwrap();
+        let texts: Vec<(&OsStr, bool)> = patterns
+            .iter()
+            .map(|p| (p.text.as_os_str(), p.exclude))
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                (OsStr::new("usr/*"), false),
+                (OsStr::new("usr/share/*"), true),
+                (OsStr::new("usr/share/man/*"), false),
+                (OsStr::new("*.a"), true),
+                (OsStr::new("#tmp"), false),
+                (OsStr::new("!x"), false),
+            ]
+        );
+        let select = |name: &str| patterns_select(&patterns, OsStr::new(name));
+        assert!(select("usr/bin/ls"));
+        assert!(!select("usr/share/doc/README"));
+        assert!(select("usr/share/man/ls.1"));
+        assert!(!select("usr/lib/libc.a"));
+        assert!(select("#tmp"));
+        assert!(!select("etc/passwd"));
+
+        // 只有排除模式时选中其他所有的名字
+        let excludes = parse_pattern_lines(b"!*.o\n", MatchOptions::new()).unwrap();
+        assert!(patterns_select(&excludes, OsStr::new("a.c")));
+        assert!(!patterns_select(&excludes, OsStr::new("a.o")));
+    }
+}
@@ -0,0 +1,379 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 按文件属性选择成员，与 find 的测试相似。
+// --newer、--older、--size、--type、--user、--group、--perm 和 --nlink 检查成员的
+// CpioFileStat：copy-in 时是归档中的头部，copy-out 和 copy-pass 时是 stat_to_cpio
+// 的结果。谓词之间可以用 --and、--or、--not 以及 --begin-group/--end-group 组合，
+// 相邻的两个谓词之间默认是 --and，优先级从高到低为 --not、--and、--or。
+
+use crate::appargs::*;
+use crate::cpiohdr::CpioFileStat;
+use crate::filetype::*;
+use crate::idcache::{getgidbyname, getuidbyname};
+use crate::util::parse_time_reference;
+
+// 数值比较：+N 表示大于 N，-N 表示小于 N，N 表示等于 N
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare {
+    Less(u64),
+    Equal(u64),
+    Greater(u64),
+}
+
+impl Compare {
+    fn parse(text: &str) -> Option<(Compare, &str)> {
+        let (make, rest): (fn(u64) -> Compare, &str) = match text.as_bytes().first() {
+            Some(b'+') => (Compare::Greater, &text[1..]),
+            Some(b'-') => (Compare::Less, &text[1..]),
+            _ => (Compare::Equal, text),
+        };
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let number = rest[..digits].parse().ok()?;
+        Some((make(number), &rest[digits..]))
+    }
+
+    fn test(&self, value: u64) -> bool {
+        match *self {
+            Compare::Less(n) => value < n,
+            Compare::Equal(n) => value == n,
+            Compare::Greater(n) => value > n,
+        }
+    }
+}
+
+// --perm MODE 要求权限位与 MODE 完全相同，-MODE 要求 MODE 中的位都已设置，
+// /MODE 要求 MODE 中至少一个位已设置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PermTest {
+    Exact,
+    All,
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Newer(i64),
+    Older(i64),
+    Size(Compare, u64),
+    Type(Vec<u32>),
+    User(u32),
+    Group(u32),
+    Perm(PermTest, u32),
+    Nlink(Compare),
+    Not(Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+// 命令行上的谓词选项，参数名与长选项名相同
+pub const PREDICATE_NAMES: &[&str] = &[
+    "newer", "older", "size", "type", "user", "group", "perm", "nlink",
+];
+
+// 组合谓词的选项
+pub const CONNECTIVE_NAMES: &[&str] = &["and", "or", "not", "begin-group", "end-group"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Test(Predicate),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Token {
+    pub fn connective(name: &str) -> Option<Token> {
+        match name {
+            "and" => Some(Token::And),
+            "or" => Some(Token::Or),
+            "not" => Some(Token::Not),
+            "begin-group" => Some(Token::Open),
+            "end-group" => Some(Token::Close),
+            _ => None,
+        }
+    }
+}
+
+fn file_type(letter: char) -> Option<u32> {
+    match letter {
+        'f' => Some(CP_IFREG),
+        'd' => Some(CP_IFDIR),
+        'l' => Some(CP_IFLNK),
+        'c' => Some(CP_IFCHR),
+        'b' => Some(CP_IFBLK),
+        'p' => Some(CP_IFIFO),
+        's' => Some(CP_IFSOCK),
+        _ => None,
+    }
+}
+
+impl Predicate {
+    // 解析谓词选项 --NAME 的参数
+    pub fn parse(name: &str, value: &str) -> Result<Predicate, String> {
+        match name {
+            "newer" | "older" => {
+                let time = parse_time_reference(value)
+                    .ok_or_else(|| format!("invalid date or file name `{}'", value))?;
+                Ok(if name == "newer" {
+                    Predicate::Newer(time)
+                } else {
+                    Predicate::Older(time)
+                })
+            }
+            "size" => {
+                let invalid = || format!("invalid size `{}'", value);
+                let (compare, unit) = Compare::parse(value).ok_or_else(invalid)?;
+                let unit = match unit {
+                    "" | "c" => 1,
+                    "k" => 1 << 10,
+                    "M" => 1 << 20,
+                    "G" => 1 << 30,
+                    _ => return Err(invalid()),
+                };
+                Ok(Predicate::Size(compare, unit))
+            }
+            "type" => {
+                let types = value
+                    .split(',')
+                    .map(|letter| {
+                        let mut chars = letter.chars();
+                        match (chars.next().and_then(file_type), chars.next()) {
+                            (Some(mode), None) => Ok(mode),
+                            _ => Err(format!("invalid file type `{}'", letter)),
+                        }
+                    })
+                    .collect::<Result<Vec<u32>, String>>()?;
+                Ok(Predicate::Type(types))
+            }
+            "user" => getuidbyname(value)
+                .or_else(|| value.parse().ok())
+                .map(Predicate::User)
+                .ok_or_else(|| format!("invalid user `{}'", value)),
+            "group" => getgidbyname(value)
+                .or_else(|| value.parse().ok())
+                .map(Predicate::Group)
+                .ok_or_else(|| format!("invalid group `{}'", value)),
+            "perm" => {
+                let (test, mode) = match value.as_bytes().first() {
+                    Some(b'-') => (PermTest::All, &value[1..]),
+                    Some(b'/') => (PermTest::Any, &value[1..]),
+                    _ => (PermTest::Exact, value),
+                };
+                match u32::from_str_radix(mode, 8) {
+                    Ok(mode) if mode <= 0o7777 => Ok(Predicate::Perm(test, mode)),
+                    _ => Err(format!("invalid mode `{}'", value)),
+                }
+            }
+            "nlink" => match Compare::parse(value) {
+                Some((compare, "")) => Ok(Predicate::Nlink(compare)),
+                _ => Err(format!("invalid link count `{}'", value)),
+            },
+            _ => Err(format!("unknown predicate `{}'", name)),
+        }
+    }
+
+    pub fn matches(&self, hdr: &CpioFileStat) -> bool {
+        match self {
+            Predicate::Newer(time) => hdr.c_mtime > *time,
+            Predicate::Older(time) => hdr.c_mtime < *time,
+            // 与 find -size 相同，长度向上取整到整个单位
+            Predicate::Size(compare, unit) => {
+                compare.test((hdr.c_filesize.max(0) as u64).div_ceil(*unit))
+            }
+            Predicate::Type(types) => types.contains(&(hdr.c_mode & CP_IFMT)),
+            Predicate::User(uid) => hdr.c_uid == *uid,
+            Predicate::Group(gid) => hdr.c_gid == *gid,
+            Predicate::Perm(test, mode) => {
+                let perm = hdr.c_mode & 0o7777;
+                match test {
+                    PermTest::Exact => perm == *mode,
+                    PermTest::All => perm & mode == *mode,
+                    PermTest::Any => *mode == 0 || perm & mode != 0,
+                }
+            }
+            Predicate::Nlink(compare) => compare.test(hdr.c_nlink as u64),
+            Predicate::Not(inner) => !inner.matches(hdr),
+            Predicate::And(left, right) => left.matches(hdr) && right.matches(hdr),
+            Predicate::Or(left, right) => left.matches(hdr) || right.matches(hdr),
+        }
+    }
+}
+
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    next: Option<Token>,
+}
+
+impl Parser {
+    fn advance(&mut self) -> Option<Token> {
+        std::mem::replace(&mut self.next, self.tokens.next())
+    }
+
+    // EXPR := AND-EXPR [--or AND-EXPR]...
+    fn or_expr(&mut self) -> Result<Predicate, String> {
+        let mut left = self.and_expr()?;
+        while self.next == Some(Token::Or) {
+            self.advance();
+            let right = self.and_expr()?;
+            left = Predicate::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    // AND-EXPR := UNARY [[--and] UNARY]...
+    fn and_expr(&mut self) -> Result<Predicate, String> {
+        let mut left = self.unary()?;
+        loop {
+            match self.next {
+                Some(Token::And) => {
+                    self.advance();
+                }
+                Some(Token::Test(_)) | Some(Token::Not) | Some(Token::Open) => {}
+                _ => return Ok(left),
+            }
+            let right = self.unary()?;
+            left = Predicate::And(Box::new(left), Box::new(right));
+        }
+    }
+
+    // UNARY := --not UNARY | --begin-group EXPR --end-group | PREDICATE
+    fn unary(&mut self) -> Result<Predicate, String> {
+        match self.advance() {
+            Some(Token::Test(predicate)) => Ok(predicate),
+            Some(Token::Not) => Ok(Predicate::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let inner = self.or_expr()?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => Err("--begin-group without matching --end-group".to_string()),
+                }
+            }
+            Some(Token::Close) => Err("--end-group without matching --begin-group".to_string()),
+            Some(Token::And) | Some(Token::Or) => {
+                Err("--and or --or without a preceding predicate".to_string())
+            }
+            None => Err("expected a predicate at the end of the expression".to_string()),
+        }
+    }
+}
+
+// 把按命令行顺序排列的谓词和连接词组合成一个表达式，没有谓词时返回 None
+pub fn parse_expression(tokens: Vec<Token>) -> Result<Option<Predicate>, String> {
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    let mut tokens = tokens.into_iter();
+    let next = tokens.next();
+    let mut parser = Parser { tokens, next };
+    let expression = parser.or_expr()?;
+    match parser.next {
+        None => Ok(Some(expression)),
+        Some(Token::Close) => Err("--end-group without matching --begin-group".to_string()),
+        Some(_) => Err("unexpected option in the expression".to_string()),
+    }
+}
+
+// 按谓词表达式决定是否处理这个成员，没有谓词时处理所有的成员
+pub fn predicate_selected(hdr: &CpioFileStat) -> bool {
+    get_selection().map_or(true, |predicate| predicate.matches(hdr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(mode: u32, size: i64, nlink: usize, mtime: i64) -> CpioFileStat {
+        let mut hdr = CpioFileStat::new();
+        hdr.c_mode = mode;
+        hdr.c_filesize = size;
+        hdr.c_nlink = nlink;
+        hdr.c_mtime = mtime;
+        hdr
+    }
+
+    fn test(name: &str, value: &str) -> Token {
+        Token::Test(Predicate::parse(name, value).unwrap())
+    }
+
+    #[test]
+    fn test_predicates() {
+        let big = header(CP_IFREG | 0o4755, 3 << 20, 1, 1000);
+        let dir = header(CP_IFDIR | 0o755, 4096, 2, 2000);
+
+        let size = Predicate::parse("size", "+2M").unwrap();
+        assert!(size.matches(&big) && !size.matches(&dir));
+        // 向上取整：4096 字节是 4k，1 字节是 1k
+        assert!(Predicate::parse("size", "4k").unwrap().matches(&dir));
+        assert!(Predicate::parse("size", "1k")
+            .unwrap()
+            .matches(&header(CP_IFREG, 1, 1, 0)));
+        assert!(Predicate::parse("size", "-1k")
+            .unwrap()
+            .matches(&header(CP_IFREG, 0, 1, 0)));
+
+        let types = Predicate::parse("type", "d,l").unwrap();
+        assert!(types.matches(&dir) && !types.matches(&big));
+
+        assert!(Predicate::parse("perm", "-4000").unwrap().matches(&big));
+        assert!(!Predicate::parse("perm", "-4000").unwrap().matches(&dir));
+        assert!(Predicate::parse("perm", "/6000").unwrap().matches(&big));
+        assert!(Predicate::parse("perm", "755").unwrap().matches(&dir));
+        assert!(!Predicate::parse("perm", "755").unwrap().matches(&big));
+
+        assert!(Predicate::parse("nlink", "+1").unwrap().matches(&dir));
+        assert!(Predicate::parse("newer", "@1500").unwrap().matches(&dir));
+        assert!(Predicate::parse("older", "@1500").unwrap().matches(&big));
+        assert!(Predicate::parse("user", "12345").unwrap().matches(&{
+            let mut hdr = big.clone();
+            hdr.c_uid = 12345;
+            hdr
+        }));
+
+        assert!(Predicate::parse("size", "10x").is_err());
+        assert!(Predicate::parse("type", "fd").is_err());
+        assert!(Predicate::parse("perm", "888").is_err());
+        assert!(Predicate::parse("newer", "no such date").is_err());
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        let big = header(CP_IFREG | 0o644, 3 << 20, 1, 1000);
+        let small = header(CP_IFREG | 0o644, 10, 1, 1000);
+        let dir = header(CP_IFDIR | 0o755, 4096, 2, 2000);
+
+        // 相邻的谓词默认是 --and，--and 的优先级高于 --or
+        let expr = parse_expression(vec![
+            test("type", "f"),
+            test("size", "+1M"),
+            Token::Or,
+            test("type", "d"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert!(expr.matches(&big) && expr.matches(&dir) && !expr.matches(&small));
+
+        let expr = parse_expression(vec![
+            Token::Not,
+            Token::Open,
+            test("type", "d"),
+            Token::Or,
+            test("size", "-2k"),
+            Token::Close,
+        ])
+        .unwrap()
+        .unwrap();
+        assert!(expr.matches(&big) && !expr.matches(&dir) && !expr.matches(&small));
+
+        assert_eq!(parse_expression(vec![]), Ok(None));
+        assert!(parse_expression(vec![Token::Or, test("type", "f")]).is_err());
+        assert!(parse_expression(vec![Token::Open, test("type", "f")]).is_err());
+        assert!(parse_expression(vec![test("type", "f"), Token::Close]).is_err());
+        assert!(parse_expression(vec![test("type", "f"), Token::Not]).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// copy-out 和 copy-pass 的预读（--jobs=N）。
+// 一个线程按顺序读入文件名列表，N 个工作线程提前处理后面的文件：copy-out 时读入
+// 小的普通文件的内容，copy-pass 时读取文件的状态。主线程仍按原来的顺序取出文件名，
+// 所以归档和复制的结果与不预读时完全相同。大文件不预读，仍由主线程在内核中直接
+// 复制。
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::appargs::*;
+use crate::dstring::*;
+
+// 只预读不超过这个长度的文件，限制预读占用的内存
+const READ_AHEAD_FILE_MAX: u64 = 1024 * 1024;
+
+// 每个工作线程最多领先主线程的文件个数
+const READ_AHEAD_DEPTH: usize = 16;
+
+struct Prefetched {
+    stat: fs::Metadata,
+    data: Vec<u8>,
+}
+
+type Pending = Receiver<Option<Prefetched>>;
+type Job<T> = (Vec<u8>, SyncSender<T>);
+
+pub struct ReadAhead {
+    names: Receiver<(Vec<u8>, Pending)>,
+    pending: Option<Pending>,
+}
+
+pub struct StatAhead {
+    names: Receiver<(Vec<u8>, Receiver<io::Result<fs::Metadata>>)>,
+}
+
+fn prefetch_file(name: &[u8]) -> Option<Prefetched> {
+    // -a 要恢复主线程读取前的访问时间，不能提前读
+    if get_reset_time_flag() {
+        return None;
+    }
+    let path = OsStr::from_bytes(name);
+    let stat = fs::symlink_metadata(path).ok()?;
+    if !stat.is_file() || stat.len() > READ_AHEAD_FILE_MAX {
+        return None;
+    }
+    let mut data = Vec::with_capacity(stat.len() as usize);
+    File::open(path)
+        .ok()?
+        .take(stat.len())
+        .read_to_end(&mut data)
+        .ok()?;
+    Some(Prefetched { stat, data })
+}
+
+fn read_ahead_worker<T>(jobs: &Mutex<Receiver<Job<T>>>, work: fn(&[u8]) -> T) {
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        match job {
+            Ok((name, result)) => {
+                let _ = result.send(work(&name));
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+// 开始从标准输入读取文件名，并用 JOBS 个线程对每个文件名调用 WORK。返回的每个
+// 文件名带有一个接收 WORK 结果的通道
+fn start_read_ahead<T: Send + 'static>(
+    jobs: usize,
+    work: fn(&[u8]) -> T,
+) -> Receiver<(Vec<u8>, Receiver<T>)> {
+    let (name_sender, names) = mpsc::sync_channel(jobs * READ_AHEAD_DEPTH);
+    let (job_sender, job_receiver) = mpsc::sync_channel::<Job<T>>(jobs * READ_AHEAD_DEPTH);
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+    for _ in 0..jobs {
+        let job_receiver = Arc::clone(&job_receiver);
+        thread::spawn(move || read_ahead_worker(&job_receiver, work));
+    }
+
+    thread::spawn(move || {
+        let mut stdin_file = unsafe { File::from_raw_fd(libc::STDIN_FILENO) };
+        let mut name = DYNAMIC_STRING_INITIALIZER;
+        while ds_fgetstr(&mut stdin_file, &mut name, get_name_end() as u8).is_some() {
+            let bytes = name.ds_string[..name.ds_idx].to_vec();
+            let (result, pending) = mpsc::sync_channel(1);
+            if job_sender.send((bytes.clone(), result)).is_err()
+                || name_sender.send((bytes, pending)).is_err()
+            {
+                break;
+            }
+        }
+        // 标准输入仍由主线程负责关闭
+        std::mem::forget(stdin_file);
+    });
+
+    names
+}
+
+impl ReadAhead {
+    // 开始从标准输入读取文件名，并用 JOBS 个线程预读文件
+    pub fn start(jobs: usize) -> ReadAhead {
+        ReadAhead {
+            names: start_read_ahead(jobs, prefetch_file),
+            pending: None,
+        }
+    }
+
+    // 与 ds_fgetstr 一样读入下一个文件名，没有更多文件名时返回 None
+    pub fn next_name(&mut self, s: &mut DynamicString) -> Option<()> {
+        let (name, pending) = self.names.recv().ok()?;
+        ds_sgetstr(&name, s, get_name_end() as u8);
+        self.pending = Some(pending);
+        Some(())
+    }
+
+    // 取出上一个文件名对应的预读数据。FILE 是主线程打开的同一个文件，SIZE 是
+    // 头部中的文件大小，只有文件与预读时相同且没有改变时才使用预读的数据
+    pub fn take(&mut self, file: &File, size: u64) -> Option<Vec<u8>> {
+        let prefetched = self.pending.take()?.recv().ok()??;
+        let stat = file.metadata().ok()?;
+        if stat.dev() != prefetched.stat.dev()
+            || stat.ino() != prefetched.stat.ino()
+            || stat.len() != size
+            || prefetched.data.len() as u64 != size
+            || stat.mtime() != prefetched.stat.mtime()
+            || stat.mtime_nsec() != prefetched.stat.mtime_nsec()
+            || stat.ctime() != prefetched.stat.ctime()
+            || stat.ctime_nsec() != prefetched.stat.ctime_nsec()
+        {
+            return None;
+        }
+        Some(prefetched.data)
+    }
+}
+
+fn stat_file(name: &[u8]) -> io::Result<fs::Metadata> {
+    fs::metadata(OsStr::from_bytes(name))
+}
+
+impl StatAhead {
+    // 开始从标准输入读取文件名，并用 JOBS 个线程读取文件的状态
+    pub fn start(jobs: usize) -> StatAhead {
+        StatAhead {
+            names: start_read_ahead(jobs, stat_file),
+        }
+    }
+
+    // 与 ds_fgetstr 一样读入下一个文件名，并返回文件的状态；没有更多文件名时
+    // 返回 None
+    pub fn next_name(&mut self, s: &mut DynamicString) -> Option<io::Result<fs::Metadata>> {
+        let (name, pending) = self.names.recv().ok()?;
+        ds_sgetstr(&name, s, get_name_end() as u8);
+        Some(pending.recv().unwrap_or_else(|_| stat_file(&name)))
+    }
+}
@@ -0,0 +1,380 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 断点续传日志（--resume=JOURNAL）。
+// 日志是一个只追加的文本文件，每行一条记录，字段之间用制表符分隔。每处理完
+// 一个成员写一条 done 记录，包含归档中下一个成员的偏移；两条 done 之间的其他
+// 记录描述了处理该成员时对延迟硬链接、延迟目录属性等内部状态的修改。续传时
+// 重放最后一条 done 之前的记录即可恢复这些状态。
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use gnu::error::*;
+use pax::paxlib::*;
+
+use crate::cpiohdr::*;
+use crate::util::{escape_field, unescape_field};
+
+pub enum JournalRecord {
+    Done {
+        offset: u64,
+        name: OsString,
+    },
+    Inode {
+        ino: u64,
+        maj: u64,
+        min: u64,
+        name: Option<OsString>,
+    },
+    Defer(CpioFileStat),
+    Linked {
+        ino: u64,
+        maj: u64,
+        min: u64,
+    },
+    Undefer(OsString),
+    Dir {
+        invert: u32,
+        stat: CpioFileStat,
+    },
+    Repair {
+        mode: u32,
+        name: OsString,
+    },
+    Symlink {
+        dev: u64,
+        ino: u64,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+        mtime: i64,
+        source: OsString,
+        target: OsString,
+    },
+}
+
+lazy_static! {
+    static ref JOURNAL: Mutex<Option<File>> = Mutex::new(None);
+}
+
+fn format_stat(stat: &CpioFileStat) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        stat.c_magic,
+        stat.c_ino,
+        stat.c_mode,
+        stat.c_uid,
+        stat.c_gid,
+        stat.c_nlink,
+        stat.c_mtime,
+        stat.c_filesize,
+        stat.c_dev_maj,
+        stat.c_dev_min,
+        stat.c_rdev_maj,
+        stat.c_rdev_min,
+        stat.c_chksum,
+        escape_field(&stat.get_c_name())
+    )
+}
+
+fn parse_stat(fields: &[&str]) -> Option<CpioFileStat> {
+    if fields.len() != 14 {
+        return None;
+    }
+    let mut stat = CpioFileStat::new();
+    stat.c_magic = fields[0].parse().ok()?;
+    stat.c_ino = fields[1].parse().ok()?;
+    stat.c_mode = fields[2].parse().ok()?;
+    stat.c_uid = fields[3].parse().ok()?;
+    stat.c_gid = fields[4].parse().ok()?;
+    stat.c_nlink = fields[5].parse().ok()?;
+    stat.c_mtime = fields[6].parse().ok()?;
+    stat.c_filesize = fields[7].parse().ok()?;
+    stat.c_dev_maj = fields[8].parse().ok()?;
+    stat.c_dev_min = fields[9].parse().ok()?;
+    stat.c_rdev_maj = fields[10].parse().ok()?;
+    stat.c_rdev_min = fields[11].parse().ok()?;
+    stat.c_chksum = fields[12].parse().ok()?;
+    stat.set_c_name(&unescape_field(fields[13])?);
+    Some(stat)
+}
+
+fn format_record(record: &JournalRecord) -> String {
+    match record {
+        JournalRecord::Done { offset, name } => format!("done\t{}\t{}", offset, escape_field(name)),
+        JournalRecord::Inode {
+            ino,
+            maj,
+            min,
+            name,
+        } => format!(
+            "inode\t{}\t{}\t{}\t{}",
+            ino,
+            maj,
+            min,
+            escape_field(name.as_deref().unwrap_or_default())
+        ),
+        JournalRecord::Defer(stat) => format!("defer\t{}", format_stat(stat)),
+        JournalRecord::Linked { ino, maj, min } => format!("linked\t{}\t{}\t{}", ino, maj, min),
+        JournalRecord::Undefer(name) => format!("undefer\t{}", escape_field(name)),
+        JournalRecord::Dir { invert, stat } => format!("dir\t{}\t{}", invert, format_stat(stat)),
+        JournalRecord::Repair { mode, name } => format!("repair\t{}\t{}", mode, escape_field(name)),
+        JournalRecord::Symlink {
+            dev,
+            ino,
+            mode,
+            uid,
+            gid,
+            mtime,
+            source,
+            target,
+        } => format!(
+            "symlink\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            dev,
+            ino,
+            mode,
+            uid,
+            gid,
+            mtime,
+            escape_field(source),
+            escape_field(target)
+        ),
+    }
+}
+
+fn parse_record(line: &str) -> Option<JournalRecord> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let args = &fields[1..];
+    let record = match (fields[0], args.len()) {
+        ("done", 2) => JournalRecord::Done {
+            offset: args[0].parse().ok()?,
+            name: unescape_field(args[1])?,
+        },
+        ("inode", 4) => {
+            let name = unescape_field(args[3])?;
+            JournalRecord::Inode {
+                ino: args[0].parse().ok()?,
+                maj: args[1].parse().ok()?,
+                min: args[2].parse().ok()?,
+                name: if name.is_empty() { None } else { Some(name) },
+            }
+        }
+        ("defer", _) => JournalRecord::Defer(parse_stat(args)?),
+        ("linked", 3) => JournalRecord::Linked {
+            ino: args[0].parse().ok()?,
+            maj: args[1].parse().ok()?,
+            min: args[2].parse().ok()?,
+        },
+        ("undefer", 1) => JournalRecord::Undefer(unescape_field(args[0])?),
+        ("dir", _) if !args.is_empty() => JournalRecord::Dir {
+            invert: args[0].parse().ok()?,
+            stat: parse_stat(&args[1..])?,
+        },
+        ("repair", 2) => JournalRecord::Repair {
+            mode: args[0].parse().ok()?,
+            name: unescape_field(args[1])?,
+        },
+        ("symlink", 8) => JournalRecord::Symlink {
+            dev: args[0].parse().ok()?,
+            ino: args[1].parse().ok()?,
+            mode: args[2].parse().ok()?,
+            uid: args[3].parse().ok()?,
+            gid: args[4].parse().ok()?,
+            mtime: args[5].parse().ok()?,
+            source: unescape_field(args[6])?,
+            target: unescape_field(args[7])?,
+        },
+        _ => return None,
+    };
+    Some(record)
+}
+
+// 解析日志内容，只保留到最后一条偏移不超过 LIMIT 的 done 记录为止。
+// 返回这些记录以及它们在日志中所占的字节数
+fn parse_journal(data: &str, limit: Option<u64>) -> (Vec<JournalRecord>, usize) {
+    let mut records = Vec::new();
+    let mut kept = 0;
+    let mut kept_len = 0;
+    let mut pos = 0;
+
+    for line in data.split_inclusive('\n') {
+        // 最后一行可能只写了一半
+        let Some(text) = line.strip_suffix('\n') else {
+            break;
+        };
+        let Some(record) = parse_record(text) else {
+            break;
+        };
+        pos += line.len();
+        if let JournalRecord::Done { offset, .. } = record {
+            if limit.is_some_and(|limit| offset > limit) {
+                break;
+            }
+            records.push(record);
+            kept = records.len();
+            kept_len = pos;
+        } else {
+            records.push(record);
+        }
+    }
+    records.truncate(kept);
+    (records, kept_len)
+}
+
+// 日志中是否已有完成的成员
+pub fn journal_has_checkpoint(path: &str) -> bool {
+    match fs::read_to_string(path) {
+        Ok(data) => !parse_journal(&data, None).0.is_empty(),
+        Err(_) => false,
+    }
+}
+
+// 打开日志，用 REPLAY 重放其中的记录。日志中最后一条有效 done 之后的内容
+// 被截掉，重放完成后新的记录接着追加
+pub fn journal_resume<T>(
+    path: &str,
+    limit: Option<u64>,
+    replay: impl FnOnce(Vec<JournalRecord>) -> T,
+) -> T {
+    let data = match fs::read(path) {
+        Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                error(
+                    PAXEXIT_FAILURE,
+                    e.raw_os_error().unwrap_or(0),
+                    format_args!("cannot read resume journal {}", path),
+                );
+            }
+            String::new()
+        }
+    };
+    let (records, len) = parse_journal(&data, limit);
+
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|file| {
+            file.set_len(len as u64)?;
+            Ok(file)
+        });
+    let file = match file {
+        Ok(file) => file,
+        Err(e) => {
+            error(
+                PAXEXIT_FAILURE,
+                e.raw_os_error().unwrap_or(0),
+                format_args!("cannot open resume journal {}", path),
+            );
+            return replay(Vec::new());
+        }
+    };
+
+    // 重放时不能再写日志
+    let result = replay(records);
+    *JOURNAL.lock().unwrap() = Some(file);
+    result
+}
+
+pub fn journal_record(record: &JournalRecord) {
+    let mut journal = JOURNAL.lock().unwrap();
+    let Some(file) = journal.as_mut() else {
+        return;
+    };
+    let mut line = format_record(record);
+    line.push('\n');
+    // 整行一次写入，中断时最多只留下不完整的最后一行
+    if let Err(e) = file.write_all(line.as_bytes()) {
+        error(
+            0,
+            e.raw_os_error().unwrap_or(0),
+            format_args!("cannot write resume journal"),
+        );
+        *journal = None;
+    }
+}
+
+// 全部成员处理完成后删除日志
+pub fn journal_finish(path: &str) {
+    if JOURNAL.lock().unwrap().take().is_some() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    #[test]
+    fn test_parse_journal() {
+        let mut stat = CpioFileStat::new();
+        stat.c_ino = 12;
+        stat.c_mode = 0o100644;
+        stat.c_nlink = 2;
+        stat.set_c_name("dir/a\tb");
+
+        let mut data = String::new();
+        for record in [
+            JournalRecord::Defer(stat),
+            JournalRecord::Done {
+                offset: 512,
+                name: OsString::from_vec(b"dir/\xe9\tb".to_vec()),
+            },
+            JournalRecord::Repair {
+                mode: 0o40755,
+                name: "dir\\x\ny".into(),
+            },
+            JournalRecord::Done {
+                offset: 1024,
+                name: "dir".into(),
+            },
+            JournalRecord::Linked {
+                ino: 12,
+                maj: 0,
+                min: 0,
+            },
+        ] {
+            data.push_str(&format_record(&record));
+            data.push('\n');
+        }
+        data.push_str("done\t20");
+
+        let (mut records, len) = parse_journal(&data, None);
+        assert_eq!(records.len(), 4);
+        assert_eq!(&data[len..], "linked\t12\t0\t0\ndone\t20");
+        match &mut records[0] {
+            JournalRecord::Defer(stat) => {
+                assert_eq!(stat.c_ino, 12);
+                assert_eq!(stat.c_nlink, 2);
+                assert_eq!(stat.get_c_name(), "dir/a\tb");
+            }
+            _ => panic!("unexpected record"),
+        }
+        // 不是 UTF-8 的名字按字节保存
+        match &records[1] {
+            JournalRecord::Done { offset, name } => {
+                assert_eq!(*offset, 512);
+                assert_eq!(name.as_bytes(), b"dir/\xe9\tb");
+            }
+            _ => panic!("unexpected record"),
+        }
+        match &records[2] {
+            JournalRecord::Repair { mode, name } => {
+                assert_eq!(*mode, 0o40755);
+                assert_eq!(name, "dir\\x\ny");
+            }
+            _ => panic!("unexpected record"),
+        }
+
+        let (records, _) = parse_journal(&data, Some(1000));
+        assert_eq!(records.len(), 2);
+    }
+}
@@ -0,0 +1,237 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 非特权提取（--rootless）。
+// 提取时不改变文件的属主，而是把归档中的属主、属组、权限和文件类型记录在
+// user.containers.override_stat 扩展属性中，格式与 containers/storage 和
+// fuse-overlayfs 相同："UID:GID:0MODE:TYPE"，TYPE 是 file、dir、pipe、socket、
+// block-MAJOR-MINOR 或 char-MAJOR-MINOR。user.* 属性只能设置在普通文件和目录上，
+// 所以设备、FIFO 和套接字都创建为空的普通文件；符号链接的属主无处记录，
+// 与当前用户不同时给出一次警告。copy-out 时读出这个属性，归档中的属主、
+// 权限和类型与原来的归档相同。
+
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::appargs::*;
+use crate::cpiohdr::{CpioFileStat, RettypeMajor, RettypeMinor};
+use crate::filetype::*;
+use crate::util::{quote_name, warn_msg};
+
+pub const OVERRIDE_STAT_XATTR: &str = "user.containers.override_stat";
+
+static SYMLINK_OWNER_WARNED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverrideStat {
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    // 没有 TYPE 字段时为 None，保持文件本身的类型
+    pub file_type: Option<u32>,
+    pub rdev_maj: RettypeMajor,
+    pub rdev_min: RettypeMinor,
+}
+
+pub fn format_override_stat(uid: u32, gid: u32, hdr: &CpioFileStat) -> String {
+    let file_type = match hdr.c_mode & CP_IFMT {
+        CP_IFDIR => "dir".to_string(),
+        CP_IFIFO => "pipe".to_string(),
+        CP_IFSOCK => "socket".to_string(),
+        CP_IFBLK => format!("block-{}-{}", hdr.c_rdev_maj, hdr.c_rdev_min),
+        CP_IFCHR => format!("char-{}-{}", hdr.c_rdev_maj, hdr.c_rdev_min),
+        _ => "file".to_string(),
+    };
+    format!("{}:{}:0{:o}:{}", uid, gid, hdr.c_mode & 0o7777, file_type)
+}
+
+pub fn parse_override_stat(value: &str) -> Option<OverrideStat> {
+    let fields: Vec<&str> = value.trim_end_matches('\0').split(':').collect();
+    if fields.len() != 3 && fields.len() != 4 {
+        return None;
+    }
+    let mut stat = OverrideStat {
+        uid: fields[0].parse().ok()?,
+        gid: fields[1].parse().ok()?,
+        mode: u32::from_str_radix(fields[2], 8).ok()? & 0o7777,
+        file_type: None,
+        rdev_maj: 0,
+        rdev_min: 0,
+    };
+    if let Some(file_type) = fields.get(3) {
+        let device = |spec: &str| -> Option<(RettypeMajor, RettypeMinor)> {
+            let (major, minor) = spec.split_once('-')?;
+            Some((major.parse().ok()?, minor.parse().ok()?))
+        };
+        stat.file_type = Some(match *file_type {
+            "file" => CP_IFREG,
+            "dir" => CP_IFDIR,
+            "pipe" => CP_IFIFO,
+            "socket" => CP_IFSOCK,
+            _ => {
+                let (kind, dev) = if let Some(spec) = file_type.strip_prefix("block-") {
+                    (CP_IFBLK, device(spec)?)
+                } else {
+                    (CP_IFCHR, device(file_type.strip_prefix("char-")?)?)
+                };
+                (stat.rdev_maj, stat.rdev_min) = dev;
+                kind
+            }
+        });
+    }
+    Some(stat)
+}
+
+// 有打开的文件时用 fsetxattr，否则按名字设置
+pub fn set_override_stat(file: Option<&File>, path: &Path, value: &str) -> io::Result<()> {
+    let name = CString::new(OVERRIDE_STAT_XATTR).unwrap();
+    let res = match file {
+        Some(file) => unsafe {
+            libc::fsetxattr(
+                file.as_raw_fd(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        },
+        None => {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            unsafe {
+                libc::lsetxattr(
+                    c_path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_ptr() as *const libc::c_void,
+                    value.len(),
+                    0,
+                )
+            }
+        }
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn get_override_stat(path: &OsStr) -> io::Result<Option<String>> {
+    let c_path = CString::new(path.as_bytes())?;
+    let name = CString::new(OVERRIDE_STAT_XATTR).unwrap();
+    let mut buf = vec![0u8; 256];
+    let len = unsafe {
+        libc::lgetxattr(
+            c_path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    };
+    if len < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    buf.truncate(len as usize);
+    Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+}
+
+// 提取的符号链接 NAME 在归档中的属主不是当前用户时给出警告，只警告一次
+pub fn warn_symlink_owner(name: &OsStr, uid: u32, gid: u32) {
+    let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    if (uid != euid || gid != egid) && !SYMLINK_OWNER_WARNED.swap(true, Ordering::Relaxed) {
+        warn_msg(format_args!(
+            "{}: owner {}:{} of symbolic links is not recorded with --rootless",
+            quote_name(name),
+            uid,
+            gid
+        ));
+    }
+}
+
+// copy-out 时用 PATH 的扩展属性中记录的属主、权限和类型代替文件本身的，
+// -R 指定的属主和属组不受影响
+pub fn apply_override_stat(path: &OsStr, hdr: &mut CpioFileStat) {
+    let value = match get_override_stat(path) {
+        Ok(Some(value)) => value,
+        Ok(None) => return,
+        Err(e) => {
+            warn_msg(format_args!(
+                "{}: cannot read {}: {}",
+                quote_name(path),
+                OVERRIDE_STAT_XATTR,
+                e
+            ));
+            return;
+        }
+    };
+    let Some(stat) = parse_override_stat(&value) else {
+        warn_msg(format_args!(
+            "{}: invalid {} value `{}'",
+            quote_name(path),
+            OVERRIDE_STAT_XATTR,
+            value
+        ));
+        return;
+    };
+
+    if !get_set_owner_flag() {
+        hdr.c_uid = stat.uid;
+    }
+    if !get_set_group_flag() {
+        hdr.c_gid = stat.gid;
+    }
+    // 只有普通文件才可能是设备等文件的替身，目录保持目录
+    let mut file_type = hdr.c_mode & CP_IFMT;
+    if file_type == CP_IFREG {
+        if let Some(new_type) = stat.file_type.filter(|&t| t != CP_IFDIR) {
+            file_type = new_type;
+            hdr.c_rdev_maj = stat.rdev_maj;
+            hdr.c_rdev_min = stat.rdev_min;
+        }
+    }
+    hdr.c_mode = file_type | stat.mode;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_stat() {
+        let mut hdr = CpioFileStat::new();
+        hdr.c_mode = CP_IFCHR | 0o620;
+        hdr.c_rdev_maj = 136;
+        hdr.c_rdev_min = 3;
+        let value = format_override_stat(0, 5, &hdr);
+        assert_eq!(value, "0:5:0620:char-136-3");
+        assert_eq!(
+            parse_override_stat(&value),
+            Some(OverrideStat {
+                uid: 0,
+                gid: 5,
+                mode: 0o620,
+                file_type: Some(CP_IFCHR),
+                rdev_maj: 136,
+                rdev_min: 3,
+            })
+        );
+
+        hdr.c_mode = CP_IFDIR | 0o1777;
+        assert_eq!(format_override_stat(1000, 100, &hdr), "1000:100:01777:dir");
+
+        let stat = parse_override_stat("0:0:04755").unwrap();
+        assert_eq!((stat.mode, stat.file_type), (0o4755, None));
+        assert!(parse_override_stat("0:0").is_none());
+        assert!(parse_override_stat("0:0:0644:block-8").is_none());
+        assert!(parse_override_stat("0:0:0644:fifo").is_none());
+        assert!(parse_override_stat("a:0:0644").is_none());
+    }
+}
@@ -4,12 +4,17 @@
 
 #![allow(dead_code)]
 
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::sync::MutexGuard;
 
 use gnu::error::*;
 
 use crate::idcache::*;
+use crate::idmap::{mapped_group_name, mapped_user_name};
 
 use crate::appargs::*;
 use crate::cpiohdr::*;
@@ -17,7 +22,9 @@ use crate::externs::*;
 use crate::filetype::*;
 use crate::global::*;
 use crate::util::{
-    cpio_set_c_name, from_octal, tape_buffered_read, tape_buffered_write, to_ascii, warn_junk_bytes,
+    copy_files_disk_to_tape, copy_files_tape_to_disk, cpio_set_c_name, disk_empty_output_buffer,
+    file_has_holes, from_octal, punch_hole, quote_name, seek_data, seek_hole, tape_buffered_read,
+    tape_buffered_write, tape_toss_input, to_ascii, warn_junk_bytes,
 };
 
 pub const TMAGIC: &[u8] = b"ustar";
@@ -38,6 +45,9 @@ pub const BLKTYPE: u8 = b'4';
 pub const DIRTYPE: u8 = b'5';
 pub const FIFOTYPE: u8 = b'6';
 pub const CONTTYPE: u8 = b'7';
+pub const XHDTYPE: u8 = b'x'; // pax 扩展头部
+pub const XGLTYPE: u8 = b'g'; // pax 全局扩展头部
+pub const GNUTYPE_SPARSE: u8 = b'S'; // 旧 GNU 格式的稀疏文件
 
 // Size of `name' field.
 pub const TARNAMESIZE: usize = 100;
@@ -45,6 +55,17 @@ pub const TARLINKNAMESIZE: usize = 100;
 pub const TARPREFIXSIZE: usize = 155;
 pub const TARRECORDSIZE: usize = 512;
 
+// 旧 GNU 格式稀疏头部中各字段的位置
+const OLDGNU_SPARSE_OFFSET: usize = 386;
+const OLDGNU_SPARSES_IN_HEADER: usize = 4;
+const OLDGNU_ISEXTENDED: usize = 482;
+const OLDGNU_REALSIZE: usize = 483;
+const OLDGNU_SPARSES_IN_EXTENSION: usize = 21;
+const OLDGNU_EXTENSION_ISEXTENDED: usize = 504;
+
+// 扩展头部长度的上限，长度来自归档，超过时不读入内存
+const TAR_MAX_XHDR_SIZE: u64 = 8 << 20;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct TarHeader {
@@ -118,7 +139,7 @@ macro_rules! to_oct {
             $digits,
             &mut $tar_hdr.$tar_field,
             stringify!($tar_field),
-            $file_hdr.get_c_name().as_str(),
+            &quote_name(&$file_hdr.get_c_name()),
         ) != 0
         {
             return 1;
@@ -131,46 +152,25 @@ pub fn null_block(block: &[u8]) -> bool {
     block.iter().all(|&b| b == 0)
 }
 
-// Calculate checksum for a TAR header
-pub fn tar_checksum(tar_hdr: &mut TarHeader) -> u32 {
-    let mut sum: u32 = 0;
-    let p_start = tar_hdr as *const _ as *const u8;
-    let chksum_offset = unsafe { (&tar_hdr.chksum as *const _ as *const u8).offset_from(p_start) };
-    let mut p = p_start;
-    let q = unsafe { p.add(TARRECORDSIZE) };
-
-    // Sum up to checksum field
-    unsafe {
-        while p < p_start.offset(chksum_offset) {
-            sum += (*p as u32) & 0xff;
-            p = p.add(1);
-        }
-    }
-
-    // Add spaces for checksum field
-    for _ in 0..8 {
-        sum += b' ' as u32;
-        unsafe {
-            p = p.add(1);
-        }
-    }
-
-    // Sum up rest of header
-    unsafe {
-        while p < q {
-            sum += (*p as u32) & 0xff;
-            p = p.add(1);
-        }
-    }
-
-    sum
+// Calculate checksum for a TAR header record, counting the checksum field as spaces
+pub fn tar_checksum(record: &[u8]) -> u32 {
+    record[..TARRECORDSIZE]
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                b' ' as u32
+            } else {
+                b as u32
+            }
+        })
+        .sum()
 }
 
 // Split a long filename into prefix and name parts
-pub fn split_long_name(name: &str) -> (String, String) {
-    let bytes = name.as_bytes();
+pub fn split_long_name(bytes: &[u8]) -> (&[u8], &[u8]) {
     if bytes.len() <= TARNAMESIZE {
-        return (String::new(), name.to_string());
+        return (&[], bytes);
     }
 
     let max_len = bytes.len().min(TARPREFIXSIZE + 1);
@@ -180,17 +180,14 @@ pub fn split_long_name(name: &str) -> (String, String) {
         .unwrap_or(0);
 
     if split_pos == 0 || bytes.len() - split_pos - 1 > TARNAMESIZE {
-        (String::new(), name.to_string())
+        (&[], bytes)
     } else {
-        (
-            String::from_utf8_lossy(&bytes[..split_pos]).to_string(),
-            String::from_utf8_lossy(&bytes[split_pos + 1..]).to_string(),
-        )
+        (&bytes[..split_pos], &bytes[split_pos + 1..])
     }
 }
 
 // Check if a filename is too long for TAR format
-pub fn is_tar_filename_too_long(name: &str) -> bool {
+pub fn is_tar_filename_too_long(name: &OsStr) -> bool {
     let name_len = name.len();
 
     if name_len <= TARNAMESIZE {
@@ -205,24 +202,43 @@ pub fn is_tar_filename_too_long(name: &str) -> bool {
         return true;
     }
 
-    let (prefix, name) = split_long_name(name);
+    let (prefix, name) = split_long_name(name.as_bytes());
     prefix.is_empty() || name.len() > TARNAMESIZE
 }
 
-// Write out a TAR header
-pub fn write_out_tar_header(
+fn tar_typeflag(file_hdr: &CpioFileStat) -> u8 {
+    match file_hdr.c_mode & CP_IFMT {
+        CP_IFREG => {
+            if file_hdr.c_tar_linkname.is_some() {
+                LNKTYPE
+            } else {
+                REGTYPE
+            }
+        }
+        CP_IFDIR => DIRTYPE,
+        CP_IFCHR => CHRTYPE,
+        CP_IFBLK => BLKTYPE,
+        CP_IFIFO => FIFOTYPE,
+        CP_IFLNK => SYMTYPE,
+        _ => REGTYPE,
+    }
+}
+
+// Write out one TAR header record with the given type flag
+fn write_tar_record(
     output_tape: &mut MutexGuard<TapeOutput>,
     file_hdr: &mut CpioFileStat,
+    typeflag: u8,
     out_des: &mut File,
 ) -> i32 {
     let mut tar_hdr = new_tar_header();
 
     let c_name = file_hdr.get_c_name();
     // Process filename
-    let (prefix, name) = split_long_name(&c_name);
-    copys_with_nul(name.as_bytes(), &mut tar_hdr.name);
+    let (prefix, name) = split_long_name(c_name.as_bytes());
+    copys_with_nul(name, &mut tar_hdr.name);
     if !prefix.is_empty() {
-        copys_with_nul(prefix.as_bytes(), &mut tar_hdr.prefix);
+        copys_with_nul(prefix, &mut tar_hdr.prefix);
     }
 
     to_oct!(file_hdr, c_mode, MODE_ALL, 8, tar_hdr, mode);
@@ -231,22 +247,7 @@ pub fn write_out_tar_header(
     to_oct!(file_hdr, c_filesize, !0u64, 12, tar_hdr, size);
     to_oct!(file_hdr, c_mtime, !0u64, 12, tar_hdr, mtime);
 
-    // Set type flag
-    tar_hdr.typeflag = match file_hdr.c_mode & CP_IFMT {
-        CP_IFREG => {
-            if file_hdr.c_tar_linkname.is_some() {
-                LNKTYPE
-            } else {
-                REGTYPE
-            }
-        }
-        CP_IFDIR => DIRTYPE,
-        CP_IFCHR => CHRTYPE,
-        CP_IFBLK => BLKTYPE,
-        CP_IFIFO => FIFOTYPE,
-        CP_IFLNK => SYMTYPE,
-        _ => REGTYPE,
-    };
+    tar_hdr.typeflag = typeflag;
 
     // Handle links
     if let Some(ref link) = file_hdr.c_tar_linkname {
@@ -258,30 +259,177 @@ pub fn write_out_tar_header(
 
     // Set USTAR fields
     if get_archive_format() == ArchiveFormat::Ustar {
-        tar_hdr.magic.copy_from_slice(TMAGIC);
+        tar_hdr.magic[..TMAGIC.len()].copy_from_slice(TMAGIC);
         tar_hdr.version.copy_from_slice(TVERSION);
 
-        let mut name = getuser(file_hdr.c_uid);
+        let mut name = get_owner_name()
+            .or_else(|| mapped_user_name(file_hdr.c_uid))
+            .unwrap_or_else(|| getuser(file_hdr.c_uid));
         copys_with_nul(name.as_bytes(), &mut tar_hdr.uname);
 
-        name = getgroup(file_hdr.c_gid);
+        name = get_group_name()
+            .or_else(|| mapped_group_name(file_hdr.c_gid))
+            .unwrap_or_else(|| getgroup(file_hdr.c_gid));
         copys_with_nul(name.as_bytes(), &mut tar_hdr.gname);
 
         to_oct!(file_hdr, c_rdev_maj, !0u64, 8, tar_hdr, devmajor);
         to_oct!(file_hdr, c_rdev_min, !0u64, 8, tar_hdr, devminor);
     }
-    let checksum = tar_checksum(&mut tar_hdr);
-    to_ascii(&mut tar_hdr.chksum, checksum as u64, 8, LG_8, true);
 
-    let ptr = &tar_hdr as *const TarHeader as *const u8;
-    let len = size_of::<TarHeader>();
-    let mut buf = unsafe { Vec::from_raw_parts(ptr as *mut u8, len, len) };
+    let mut tar_rec = TarRecord {
+        buffer: [0; TARRECORDSIZE],
+    };
+    tar_rec.header = tar_hdr;
+    let buf = unsafe { &mut tar_rec.buffer };
+    let checksum = tar_checksum(buf);
+    to_ascii(&mut buf[148..156], checksum as u64, 8, LG_8, true);
+
+    tape_buffered_write(output_tape, buf, out_des, TARRECORDSIZE);
+
+    0
+}
+
+// 写入数据并补齐到整数个记录
+fn tar_write_padded(
+    output_tape: &mut MutexGuard<TapeOutput>,
+    mut data: Vec<u8>,
+    out_des: &mut File,
+) {
+    let size = data.len().div_ceil(TARRECORDSIZE) * TARRECORDSIZE;
+    data.resize(size, 0);
+    tape_buffered_write(output_tape, &mut data, out_des, size);
+}
+
+// 生成 pax 扩展头部的记录 "长度 关键字=值\n"，长度包括它自己
+// 值按原样保存，名字不一定是合法的 UTF-8
+fn format_pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len.to_string().len() + rest > len {
+        len += 1;
+    }
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+// 把 NAME 放进它所在目录下的 SUBDIR 中，用作 pax 扩展头部和稀疏成员的名字
+fn tar_hidden_name(name: &OsStr, subdir: &str) -> OsString {
+    let name = name.as_bytes();
+    let mut hidden = match name.iter().rposition(|&b| b == b'/') {
+        Some(i) => name[..=i].to_vec(),
+        None => b"./".to_vec(),
+    };
+    hidden.extend_from_slice(subdir.as_bytes());
+    hidden.push(b'/');
+    hidden.extend_from_slice(match name.iter().rposition(|&b| b == b'/') {
+        Some(i) => &name[i + 1..],
+        None => name,
+    });
+    OsString::from_vec(hidden)
+}
+
+// Write out a TAR header
+pub fn write_out_tar_header(
+    output_tape: &mut MutexGuard<TapeOutput>,
+    file_hdr: &mut CpioFileStat,
+    out_des: &mut File,
+) -> i32 {
+    let Some(map) = file_hdr.c_sparse.clone() else {
+        return write_tar_record(output_tape, file_hdr, tar_typeflag(file_hdr), out_des);
+    };
+
+    // 稀疏文件按 GNU tar 的 pax 1.0 格式写出：扩展头部中保存真实的名字和长度，
+    // 成员的数据以十进制的数据段表开头，之后只有各个数据段
+    let c_name = file_hdr.get_c_name();
+    let records = [
+        format_pax_record("GNU.sparse.major", b"1"),
+        format_pax_record("GNU.sparse.minor", b"0"),
+        format_pax_record("GNU.sparse.name", c_name.as_bytes()),
+        format_pax_record("GNU.sparse.realsize", map.realsize.to_string().as_bytes()),
+    ]
+    .concat();
+
+    let mut pax_hdr = file_hdr.clone();
+    pax_hdr.set_c_name(tar_hidden_name(&c_name, "PaxHeaders"));
+    pax_hdr.c_filesize = records.len() as i64;
+    if write_tar_record(output_tape, &mut pax_hdr, XHDTYPE, out_des) != 0 {
+        return 1;
+    }
+    tar_write_padded(output_tape, records, out_des);
+
+    let mut map_text = format!("{}\n", map.segments.len());
+    for (offset, len) in &map.segments {
+        map_text.push_str(&format!("{}\n{}\n", offset, len));
+    }
+    let map_size = map_text.len().div_ceil(TARRECORDSIZE) * TARRECORDSIZE;
 
-    tape_buffered_write(output_tape, &mut buf, out_des, TARRECORDSIZE);
+    let mut sparse_hdr = file_hdr.clone();
+    sparse_hdr.set_c_name(tar_hidden_name(&c_name, "GNUSparseFile.0"));
+    sparse_hdr.c_filesize = (map_size as u64 + map.data_size()) as i64;
+    if write_tar_record(output_tape, &mut sparse_hdr, REGTYPE, out_des) != 0 {
+        return 1;
+    }
+    tar_write_padded(output_tape, map_text.into_bytes(), out_des);
 
     0
 }
 
+// 找出文件中的数据段，文件没有洞时返回 None。文件以洞结尾时最后加上一个长度为 0
+// 的数据段记录文件长度
+pub fn tar_sparse_map(file: &File, size: u64) -> Option<SparseMap> {
+    if !file_has_holes(file) {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    while pos < size {
+        let data = seek_data(file, pos).min(size);
+        if data >= size {
+            break;
+        }
+        let hole = seek_hole(file, data).min(size);
+        if hole <= data {
+            break;
+        }
+        segments.push((data, hole - data));
+        pos = hole;
+    }
+    if segments
+        .last()
+        .map_or(true, |&(offset, len)| offset + len < size)
+    {
+        segments.push((size, 0));
+    }
+    Some(SparseMap {
+        realsize: size,
+        segments,
+    })
+}
+
+// 写出稀疏成员的各个数据段，返回写出的字节数
+pub fn write_out_tar_sparse_data(
+    output_tape: &mut MutexGuard<TapeOutput>,
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    out_des: &mut File,
+    map: &SparseMap,
+    filename: &OsStr,
+) -> u64 {
+    for &(offset, len) in map.segments.iter().filter(|&&(_, len)| len > 0) {
+        if in_des.seek(SeekFrom::Start(offset)).is_err() {
+            error(
+                0,
+                0,
+                format_args!("cannot seek on file {}", quote_name(filename)),
+            );
+        }
+        copy_files_disk_to_tape(output_tape, input_tape, in_des, out_des, len, filename);
+    }
+    map.data_size()
+}
+
 // Helper function to copy bytes with null termination
 fn copys_with_nul(src: &[u8], dest: &mut [u8]) {
     let len = src.len().min(dest.len() - 1);
@@ -291,12 +439,12 @@ fn copys_with_nul(src: &[u8], dest: &mut [u8]) {
 
 // Check if a buffer contains a valid TAR header
 pub fn is_tar_header(buf: &[u8]) -> i32 {
-    let mut tar_hdr: TarHeader = unsafe { *(buf.as_ptr() as *mut TarHeader) };
+    let tar_hdr: TarHeader = unsafe { *(buf.as_ptr() as *mut TarHeader) };
 
     //let chk_sum_str = bytes_to_string(&tar_hdr.chksum);
 
     let chk_sum = from_octal(&tar_hdr.chksum.to_vec());
-    let actual_sum = tar_checksum(&mut tar_hdr); // Now pass the mutable reference
+    let actual_sum = tar_checksum(buf);
 
     if chk_sum != actual_sum as u64 {
         return 0;
@@ -308,21 +456,24 @@ pub fn is_tar_header(buf: &[u8]) -> i32 {
 
     1
 }
-fn stash_tar_filename(prefix: Option<&str>, filename: &str) -> String {
-    let mut hold_tar_filename = String::with_capacity(TARNAMESIZE + TARPREFIXSIZE + 2);
+fn stash_tar_filename(prefix: Option<&[u8]>, filename: &[u8]) -> OsString {
+    let mut hold_tar_filename = Vec::with_capacity(TARNAMESIZE + TARPREFIXSIZE + 2);
 
     if let Some(p) = prefix {
         // 如果 prefix 存在
-        hold_tar_filename.push_str(&p[..TARPREFIXSIZE]);
-        hold_tar_filename.push('/');
-        hold_tar_filename.push_str(&filename[..TARNAMESIZE]);
-    } else {
-        // 如果 prefix 为 None 或者空字符串
-        hold_tar_filename.push_str(&filename[..TARNAMESIZE]);
+        hold_tar_filename.extend_from_slice(&p[..p.len().min(TARPREFIXSIZE)]);
+        hold_tar_filename.push(b'/');
     }
+    hold_tar_filename.extend_from_slice(&filename[..filename.len().min(TARNAMESIZE)]);
+
+    // 返回生成的文件名，按原样保留其中的字节
+    OsString::from_vec(hold_tar_filename)
+}
 
-    // 返回生成的文件名字符串
-    hold_tar_filename
+// 名字字段以 NUL 结尾，填满时没有 NUL
+fn tar_name_field(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[..len]
 }
 
 fn bytes_to_string(bytes: &[u8]) -> &str {
@@ -331,6 +482,163 @@ fn bytes_to_string(bytes: &[u8]) -> &str {
         .trim_matches(|c: char| c.is_whitespace() || c == '\0')
 }
 
+// 读取数字字段，GNU tar 对放不下的大数使用最高位为 1 的 base-256 编码
+fn tar_number(field: &[u8]) -> u64 {
+    if field[0] & 0x80 != 0 {
+        field[1..]
+            .iter()
+            .fold((field[0] & 0x7f) as u64, |value, &b| value << 8 | b as u64)
+    } else {
+        from_octal(&field.to_vec())
+    }
+}
+
+// 读入 SIZE 字节的成员数据，跳过之后的填充。SIZE 超过 TAR_MAX_XHDR_SIZE 时
+// 给出错误，跳过数据并返回空的内容
+fn tar_read_padded(
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    size: u64,
+) -> Vec<u8> {
+    let pad = (TARRECORDSIZE as u64 - size % TARRECORDSIZE as u64) % TARRECORDSIZE as u64;
+    if size > TAR_MAX_XHDR_SIZE {
+        error(
+            0,
+            0,
+            format_args!("extended header of {} bytes is too large, ignored", size),
+        );
+        tape_toss_input(input_tape, in_des, size.saturating_add(pad));
+        return Vec::new();
+    }
+    let mut data = vec![0u8; size as usize];
+    tape_buffered_read(input_tape, &mut data, in_des, size as usize);
+    tape_toss_input(input_tape, in_des, pad);
+    data
+}
+
+// 解析 pax 扩展头部中的 "长度 关键字=值\n" 记录
+fn parse_pax_records(data: &[u8], records: &mut HashMap<String, OsString>) {
+    let mut rest = data;
+    while !rest.is_empty() && rest[0] != 0 {
+        let Some(space) = rest.iter().position(|&b| b == b' ') else {
+            break;
+        };
+        let len = match std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+        {
+            Some(len) if len > space + 1 && len <= rest.len() => len,
+            _ => {
+                error(0, 0, format_args!("malformed extended header"));
+                break;
+            }
+        };
+        let record = &rest[space + 1..len - 1];
+        if let Some(eq) = record.iter().position(|&b| b == b'=') {
+            let key = String::from_utf8_lossy(&record[..eq]).to_string();
+            let value = OsStr::from_bytes(&record[eq + 1..]);
+            match records.get_mut(&key) {
+                // pax 0.0 格式的稀疏成员每个数据段有一对 offset 和 numbytes，
+                // 按出现的顺序用逗号连接
+                Some(values) if key == "GNU.sparse.offset" || key == "GNU.sparse.numbytes" => {
+                    values.push(",");
+                    values.push(value);
+                }
+                _ => {
+                    records.insert(key, value.to_os_string());
+                }
+            }
+        }
+        rest = &rest[len..];
+    }
+}
+
+fn parse_sparse_number(text: &str) -> u64 {
+    text.parse().unwrap_or_else(|_| {
+        error(0, 0, format_args!("malformed sparse archive member"));
+        0
+    })
+}
+
+// 逗号分隔的十进制数
+fn parse_sparse_list(list: &OsStr) -> Vec<u64> {
+    list.to_string_lossy()
+        .split(',')
+        .map(parse_sparse_number)
+        .collect()
+}
+
+// 读入 pax 1.0 稀疏成员数据开头的数据段表：段数，然后是每段的偏移和长度，都是
+// 十进制数，各占一行，补齐到整数个记录。返回数据段和表占用的字节数
+fn read_sparse_map_blocks(
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    max_size: u64,
+) -> (Vec<(u64, u64)>, u64) {
+    let mut text = Vec::new();
+    let mut map_size = 0;
+    let mut numbers = Vec::new();
+    while map_size < max_size {
+        let mut block = [0u8; TARRECORDSIZE];
+        tape_buffered_read(input_tape, &mut block, in_des, TARRECORDSIZE);
+        map_size += TARRECORDSIZE as u64;
+        text.extend_from_slice(&block);
+
+        // 最后一行可能还没有读完
+        let lines: Vec<&[u8]> = text.split(|&b| b == b'\n').collect();
+        numbers = lines[..lines.len() - 1]
+            .iter()
+            .map(|line| parse_sparse_number(&String::from_utf8_lossy(line)))
+            .collect();
+        if numbers
+            .first()
+            .is_some_and(|&count| numbers.len() as u64 > count * 2)
+        {
+            break;
+        }
+    }
+    let segments = numbers
+        .get(1..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect();
+    (segments, map_size)
+}
+
+// 读入旧 GNU 格式稀疏头部中的数据段，段数多时后面跟有扩展记录
+fn read_old_gnu_sparse_map(
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    header: &[u8],
+) -> SparseMap {
+    fn push_segments(segments: &mut Vec<(u64, u64)>, entries: &[u8]) {
+        for entry in entries.chunks_exact(24) {
+            if entry[0] == 0 {
+                break;
+            }
+            segments.push((tar_number(&entry[..12]), tar_number(&entry[12..])));
+        }
+    }
+
+    let mut segments = Vec::new();
+    push_segments(
+        &mut segments,
+        &header[OLDGNU_SPARSE_OFFSET..OLDGNU_SPARSE_OFFSET + 24 * OLDGNU_SPARSES_IN_HEADER],
+    );
+    let mut extended = header[OLDGNU_ISEXTENDED] != 0;
+    while extended {
+        let mut block = [0u8; TARRECORDSIZE];
+        tape_buffered_read(input_tape, &mut block, in_des, TARRECORDSIZE);
+        push_segments(&mut segments, &block[..24 * OLDGNU_SPARSES_IN_EXTENSION]);
+        extended = block[OLDGNU_EXTENSION_ISEXTENDED] != 0;
+    }
+    SparseMap {
+        realsize: tar_number(&header[OLDGNU_REALSIZE..OLDGNU_REALSIZE + 12]),
+        segments,
+    }
+}
+
 // Read a TAR header from input
 pub fn read_in_tar_header(
     input_tape: &mut MutexGuard<TapeInput>,
@@ -342,11 +650,12 @@ pub fn read_in_tar_header(
     let mut tar_rec = TarRecord {
         buffer: [0; TARRECORDSIZE],
     };
+    let mut pax_records = HashMap::new();
 
     // Read header block
     tape_buffered_read(
         input_tape,
-        &mut unsafe { tar_rec.buffer.to_vec() },
+        unsafe { &mut tar_rec.buffer },
         in_des,
         TARRECORDSIZE,
     );
@@ -359,13 +668,13 @@ pub fn read_in_tar_header(
 
     loop {
         // Safe because we're reading from a union where both variants are POD types
-        let mut tar_hdr = unsafe { tar_rec.header };
+        let tar_hdr = unsafe { tar_rec.header };
 
         //  let chk_sum_str = bytes_to_string(&tar_hdr.chksum);
 
         let chk_sum = from_octal(&tar_hdr.chksum.to_vec());
 
-        if chk_sum != tar_checksum(&mut tar_hdr) as u64 {
+        if chk_sum != tar_checksum(unsafe { &tar_rec.buffer }) as u64 {
             if !warned {
                 warn_junk_bytes(bytes_skipped);
                 warned = true;
@@ -374,39 +683,40 @@ pub fn read_in_tar_header(
             unsafe {
                 let buf = &mut tar_rec.buffer;
                 buf.copy_within(1..TARRECORDSIZE, 0);
-                tape_buffered_read(
-                    input_tape,
-                    &mut buf[TARRECORDSIZE - 1..TARRECORDSIZE].to_vec(),
-                    in_des,
-                    1,
-                );
+                tape_buffered_read(input_tape, &mut buf[TARRECORDSIZE - 1..], in_des, 1);
             }
             bytes_skipped += 1;
             continue;
         }
 
+        // pax 扩展头部作用于下一个成员，全局扩展头部忽略
+        if tar_hdr.typeflag == XHDTYPE || tar_hdr.typeflag == XGLTYPE {
+            let data = tar_read_padded(input_tape, in_des, tar_number(&tar_hdr.size));
+            if tar_hdr.typeflag == XHDTYPE {
+                parse_pax_records(&data, &mut pax_records);
+            }
+            tape_buffered_read(
+                input_tape,
+                unsafe { &mut tar_rec.buffer },
+                in_des,
+                TARRECORDSIZE,
+            );
+            continue;
+        }
+
         // Process filename
         if get_archive_format() != ArchiveFormat::Ustar {
-            let name_str = std::str::from_utf8(&tar_hdr.name)
-                .unwrap_or("0") // 如果转换失败，默认值为 "0"
-                .trim_matches(|c: char| c.is_whitespace() || c == '\0');
-
-            let tar_name = stash_tar_filename(None, name_str);
-
-            cpio_set_c_name(file_hdr, tar_name.as_str());
+            let tar_name = stash_tar_filename(None, tar_name_field(&tar_hdr.name));
 
-            // file_hdr.c_name = String::from_utf8_lossy(&tar_hdr.name)
-            //     .trim_matches('\0')
-            //     .to_string();
+            cpio_set_c_name(file_hdr, &tar_name);
+        } else if tar_hdr.version == *b" \0" {
+            // 旧 GNU 格式的 prefix 位置保存的是其他字段
+            file_hdr.set_c_name(OsStr::from_bytes(tar_name_field(&tar_hdr.name)));
         } else {
-            let prefix = bytes_to_string(&tar_hdr.prefix);
-            let name = bytes_to_string(&tar_hdr.name);
-            let c_name = if prefix.is_empty() {
-                name.to_string()
-            } else {
-                format!("{}/{}", prefix, name)
-            };
-            file_hdr.set_c_name(&c_name);
+            let prefix = tar_name_field(&tar_hdr.prefix);
+            let name = tar_name_field(&tar_hdr.name);
+            let prefix = (!prefix.is_empty()).then_some(prefix);
+            file_hdr.set_c_name(stash_tar_filename(prefix, name));
         }
 
         // Set basic fields
@@ -441,7 +751,7 @@ pub fn read_in_tar_header(
         }
 
         // Set remaining numeric fields
-        file_hdr.c_filesize = from_octal(&tar_hdr.size.to_vec()) as i64;
+        file_hdr.c_filesize = tar_number(&tar_hdr.size) as i64;
         file_hdr.c_mtime = from_octal(&tar_hdr.mtime.to_vec()) as i64;
         file_hdr.c_rdev_maj = from_octal(&tar_hdr.devmajor.to_vec()) as i32;
         file_hdr.c_rdev_min = from_octal(&tar_hdr.devminor.to_vec()) as u32;
@@ -454,52 +764,37 @@ pub fn read_in_tar_header(
             DIRTYPE => file_hdr.c_mode |= CP_IFDIR,
             CHRTYPE => {
                 file_hdr.c_mode |= CP_IFCHR;
-                file_hdr.c_tar_linkname = Some(
-                    String::from_utf8_lossy(&tar_hdr.linkname)
-                        .trim_matches('\0')
-                        .to_string(),
-                );
+                file_hdr.c_tar_linkname =
+                    Some(OsStr::from_bytes(tar_name_field(&tar_hdr.linkname)).to_os_string());
                 file_hdr.c_filesize = 0;
             }
             BLKTYPE => {
                 file_hdr.c_mode |= CP_IFBLK;
-                file_hdr.c_tar_linkname = Some(
-                    String::from_utf8_lossy(&tar_hdr.linkname)
-                        .trim_matches('\0')
-                        .to_string(),
-                );
+                file_hdr.c_tar_linkname =
+                    Some(OsStr::from_bytes(tar_name_field(&tar_hdr.linkname)).to_os_string());
                 file_hdr.c_filesize = 0;
             }
             FIFOTYPE => {
                 file_hdr.c_mode |= CP_IFIFO;
-                file_hdr.c_tar_linkname = Some(
-                    String::from_utf8_lossy(&tar_hdr.linkname)
-                        .trim_matches('\0')
-                        .to_string(),
-                );
+                file_hdr.c_tar_linkname =
+                    Some(OsStr::from_bytes(tar_name_field(&tar_hdr.linkname)).to_os_string());
                 file_hdr.c_filesize = 0;
             }
             SYMTYPE => {
                 file_hdr.c_mode |= CP_IFLNK;
-                file_hdr.c_tar_linkname = Some(
-                    String::from_utf8_lossy(&tar_hdr.linkname)
-                        .trim_matches('\0')
-                        .to_string(),
-                );
+                file_hdr.c_tar_linkname =
+                    Some(OsStr::from_bytes(tar_name_field(&tar_hdr.linkname)).to_os_string());
                 file_hdr.c_filesize = 0;
             }
             LNKTYPE => {
                 file_hdr.c_mode |= CP_IFREG;
-                file_hdr.c_tar_linkname = Some(
-                    String::from_utf8_lossy(&tar_hdr.linkname)
-                        .trim_matches('\0')
-                        .to_string(),
-                );
+                file_hdr.c_tar_linkname =
+                    Some(OsStr::from_bytes(tar_name_field(&tar_hdr.linkname)).to_os_string());
                 file_hdr.c_filesize = 0;
             }
             AREGTYPE => {
                 let c_name = file_hdr.get_c_name();
-                if c_name.ends_with('/') {
+                if c_name.as_bytes().ends_with(b"/") {
                     file_hdr.c_mode |= CP_IFDIR;
                 } else {
                     file_hdr.c_mode |= CP_IFREG;
@@ -507,6 +802,62 @@ pub fn read_in_tar_header(
             }
             _ => file_hdr.c_mode |= CP_IFREG,
         }
+
+        file_hdr.c_sparse = None;
+        if tar_hdr.typeflag == GNUTYPE_SPARSE {
+            file_hdr.c_sparse = Some(read_old_gnu_sparse_map(input_tape, in_des, unsafe {
+                &tar_rec.buffer
+            }));
+        } else if let Some(path) = pax_records.get("path") {
+            file_hdr.set_c_name(path);
+        }
+        if let Some(major) = pax_records.get("GNU.sparse.major") {
+            if major != "1" {
+                error(
+                    0,
+                    0,
+                    format_args!(
+                        "{}: unsupported sparse format {}",
+                        quote_name(&file_hdr.get_c_name()),
+                        major.to_string_lossy()
+                    ),
+                );
+            } else {
+                let realsize = pax_records
+                    .get("GNU.sparse.realsize")
+                    .map_or(0, |size| parse_sparse_number(&size.to_string_lossy()));
+                let (segments, map_size) =
+                    read_sparse_map_blocks(input_tape, in_des, file_hdr.c_filesize as u64);
+                file_hdr.c_filesize -= map_size as i64;
+                file_hdr.c_sparse = Some(SparseMap { realsize, segments });
+            }
+        } else if let Some(map) = pax_records.get("GNU.sparse.map") {
+            // pax 0.1 格式把数据段表放在扩展头部中
+            let numbers = parse_sparse_list(map);
+            file_hdr.c_sparse = Some(SparseMap {
+                realsize: pax_records
+                    .get("GNU.sparse.size")
+                    .map_or(0, |size| parse_sparse_number(&size.to_string_lossy())),
+                segments: numbers.chunks_exact(2).map(|p| (p[0], p[1])).collect(),
+            });
+        } else if let (Some(offsets), Some(sizes)) = (
+            pax_records.get("GNU.sparse.offset"),
+            pax_records.get("GNU.sparse.numbytes"),
+        ) {
+            // pax 0.0 格式
+            file_hdr.c_sparse = Some(SparseMap {
+                realsize: pax_records
+                    .get("GNU.sparse.size")
+                    .map_or(0, |size| parse_sparse_number(&size.to_string_lossy())),
+                segments: parse_sparse_list(offsets)
+                    .into_iter()
+                    .zip(parse_sparse_list(sizes))
+                    .collect(),
+            });
+        }
+        if let Some(name) = pax_records.get("GNU.sparse.name") {
+            file_hdr.set_c_name(name);
+        }
         break;
     }
 
@@ -516,3 +867,112 @@ pub fn read_in_tar_header(
 
     0
 }
+
+// 把稀疏成员的数据段写到各自的位置，段之间留成洞。输出不能定位时（例如管道）
+// 写入零
+pub fn copy_sparse_tape_to_disk(
+    input_tape: &mut MutexGuard<TapeInput>,
+    output_tape: &mut MutexGuard<TapeOutput>,
+    in_des: &mut File,
+    out_file: &mut File,
+    map: &SparseMap,
+) {
+    let seekable = out_file.metadata().map_or(false, |st| st.is_file());
+    let start = if seekable {
+        out_file.stream_position().unwrap_or(0)
+    } else {
+        0
+    };
+    let mut pos = 0;
+
+    let skip_to =
+        |output_tape: &mut MutexGuard<TapeOutput>, out_file: &mut File, pos: u64, end: u64| {
+            disk_empty_output_buffer(output_tape, out_file, true);
+            if seekable {
+                punch_hole(out_file, start + pos, end - pos);
+                if out_file.seek(SeekFrom::Start(start + end)).is_ok() {
+                    return;
+                }
+            }
+            let zeros = vec![0u8; 64 * 1024];
+            let mut left = end - pos;
+            while left > 0 {
+                let size = left.min(zeros.len() as u64) as usize;
+                if out_file.write_all(&zeros[..size]).is_err() {
+                    error(1, 0, format_args!("write error"));
+                }
+                left -= size as u64;
+            }
+        };
+
+    for &(offset, len) in &map.segments {
+        if offset > pos {
+            skip_to(output_tape, out_file, pos, offset);
+            pos = offset;
+        }
+        copy_files_tape_to_disk(input_tape, output_tape, in_des, out_file, len);
+        pos += len;
+    }
+
+    if map.realsize > pos {
+        skip_to(output_tape, out_file, pos, map.realsize);
+    }
+    // 文件以洞结尾时要设置文件长度
+    disk_empty_output_buffer(output_tape, out_file, true);
+    if seekable
+        && out_file
+            .metadata()
+            .map_or(false, |st| st.len() < start + map.realsize)
+    {
+        let _ = out_file.set_len(start + map.realsize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pax_records() {
+        assert_eq!(
+            format_pax_record("GNU.sparse.major", b"1"),
+            b"22 GNU.sparse.major=1\n"
+        );
+        // 长度的位数变化时要算上多出的一位
+        assert!(format_pax_record("path", &[b'a'; 90]).starts_with(b"99 "));
+        assert!(format_pax_record("path", &[b'a'; 91]).starts_with(b"101 "));
+
+        let data = [
+            format_pax_record("GNU.sparse.name", b"dir/vm\xe9.img"),
+            format_pax_record("GNU.sparse.realsize", b"107374182400"),
+        ]
+        .concat();
+        let mut records = HashMap::new();
+        parse_pax_records(&data, &mut records);
+        // 名字中不是 UTF-8 的字节原样保留
+        assert_eq!(records["GNU.sparse.name"].as_bytes(), b"dir/vm\xe9.img");
+        assert_eq!(records["GNU.sparse.realsize"], "107374182400");
+
+        // pax 0.0 格式中重复出现的数据段记录按顺序连接，其他关键字后出现的为准
+        let data = [
+            format_pax_record("GNU.sparse.offset", b"0"),
+            format_pax_record("GNU.sparse.numbytes", b"512"),
+            format_pax_record("GNU.sparse.offset", b"8192"),
+            format_pax_record("GNU.sparse.numbytes", b"0"),
+            format_pax_record("path", b"a"),
+            format_pax_record("path", b"b"),
+        ]
+        .concat();
+        let mut records = HashMap::new();
+        parse_pax_records(&data, &mut records);
+        assert_eq!(records["GNU.sparse.offset"], "0,8192");
+        assert_eq!(parse_sparse_list(&records["GNU.sparse.numbytes"]), [512, 0]);
+        assert_eq!(records["path"], "b");
+
+        assert_eq!(tar_number(b"00000001750\0"), 0o1750);
+        assert_eq!(
+            tar_number(&[0x80, 0, 0, 0, 0, 0, 0, 0x19, 0, 0, 0, 0]),
+            100 << 30
+        );
+    }
+}
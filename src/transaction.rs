@@ -0,0 +1,250 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 事务式解包（--transactional）。
+// 解包阶段所有文件都只写到目标目录中的临时文件名下，并记录在暂存表中；
+// 全部成员处理完且没有出错时再统一改名到位。改名前已存在的文件先保留一份
+// 备份并记入日志，提交过程中出错时按日志逆序恢复。
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use gnu::error::*;
+
+use crate::appargs::*;
+use crate::util::{cpio_temp_name, quote_name};
+
+struct StagedEntry {
+    tmp_name: OsString,
+    name: OsString,
+}
+
+struct JournalEntry {
+    name: OsString,
+    // 被替换文件的备份名；None 表示提交前目标不存在
+    backup: Option<OsString>,
+    backup_is_dir: bool,
+}
+
+struct Transaction {
+    staged: Vec<StagedEntry>,
+    created_dirs: Vec<OsString>,
+    failed: bool,
+}
+
+lazy_static! {
+    static ref TRANSACTION: Mutex<Transaction> = Mutex::new(Transaction {
+        staged: Vec::new(),
+        created_dirs: Vec::new(),
+        failed: false,
+    });
+}
+
+// 进程因致命错误退出时清理尚未提交的临时文件和新建目录
+extern "C" fn transaction_atexit() {
+    if let Ok(mut trans) = TRANSACTION.try_lock() {
+        discard_staged(&mut trans);
+    }
+}
+
+pub fn transaction_begin() {
+    unsafe {
+        libc::atexit(transaction_atexit);
+    }
+}
+
+pub fn transaction_stage(tmp_name: &OsStr, name: &OsStr) {
+    TRANSACTION.lock().unwrap().staged.push(StagedEntry {
+        tmp_name: tmp_name.to_os_string(),
+        name: name.to_os_string(),
+    });
+}
+
+// 返回已暂存的 NAME 对应的临时文件名，供硬链接使用
+pub fn transaction_staged_name(name: &OsStr) -> Option<OsString> {
+    TRANSACTION
+        .lock()
+        .unwrap()
+        .staged
+        .iter()
+        .rev()
+        .find(|e| e.name == name)
+        .map(|e| e.tmp_name.clone())
+}
+
+pub fn transaction_record_dir(name: &OsStr) {
+    if get_transactional_flag() {
+        TRANSACTION
+            .lock()
+            .unwrap()
+            .created_dirs
+            .push(name.to_os_string());
+    }
+}
+
+pub fn transaction_fail() {
+    if get_transactional_flag() {
+        TRANSACTION.lock().unwrap().failed = true;
+    }
+}
+
+fn discard_staged(trans: &mut Transaction) {
+    for entry in trans.staged.drain(..).rev() {
+        let _ = fs::remove_file(&entry.tmp_name);
+    }
+    for dir in trans.created_dirs.drain(..).rev() {
+        let _ = fs::remove_dir(&dir);
+    }
+}
+
+fn rollback(journal: &mut Vec<JournalEntry>) {
+    while let Some(entry) = journal.pop() {
+        let res = match entry.backup {
+            Some(ref backup) => {
+                if entry.backup_is_dir {
+                    let _ = fs::remove_file(&entry.name);
+                }
+                fs::rename(backup, &entry.name)
+            }
+            None => fs::remove_file(&entry.name),
+        };
+        if let Err(e) = res {
+            error(
+                0,
+                e.raw_os_error().unwrap_or(0),
+                format_args!("cannot restore {}", quote_name(&entry.name)),
+            );
+        }
+    }
+}
+
+// 为即将被替换的 NAME 保留备份。普通文件等用硬链接保留，目标始终存在；
+// 空目录只能改名保留
+fn backup_existing(name: &OsStr) -> io::Result<Option<(OsString, bool)>> {
+    let st = match fs::symlink_metadata(name) {
+        Ok(st) => st,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let backup = cpio_temp_name(name);
+
+    if st.is_dir() {
+        if fs::read_dir(name)?.next().is_some() {
+            return Err(io::Error::from_raw_os_error(libc::ENOTEMPTY));
+        }
+        fs::rename(name, &backup)?;
+        return Ok(Some((backup, true)));
+    }
+
+    if fs::hard_link(name, &backup).is_ok() {
+        return Ok(Some((backup, false)));
+    }
+    fs::rename(name, &backup)?;
+    Ok(Some((backup, false)))
+}
+
+fn commit_entry(entry: &StagedEntry, journal: &mut Vec<JournalEntry>) -> io::Result<()> {
+    let backup = backup_existing(&entry.name)?;
+    let (backup, backup_is_dir) = match backup {
+        Some((b, is_dir)) => (Some(b), is_dir),
+        None => (None, false),
+    };
+
+    if let Err(e) = fs::rename(&entry.tmp_name, &entry.name) {
+        if let Some(ref b) = backup {
+            let _ = fs::rename(b, &entry.name);
+        }
+        return Err(e);
+    }
+    journal.push(JournalEntry {
+        name: entry.name.clone(),
+        backup,
+        backup_is_dir,
+    });
+    Ok(())
+}
+
+// 将所有暂存文件改名到位。成功返回 true；失败时恢复所有已替换的文件，
+// 删除临时文件和新建的目录后返回 false
+pub fn transaction_commit() -> bool {
+    let mut trans = TRANSACTION.lock().unwrap();
+
+    if trans.failed {
+        discard_staged(&mut trans);
+        return false;
+    }
+
+    let staged = std::mem::take(&mut trans.staged);
+    let mut journal: Vec<JournalEntry> = Vec::new();
+
+    for (i, entry) in staged.iter().enumerate() {
+        if let Err(e) = commit_entry(entry, &mut journal) {
+            error(
+                0,
+                e.raw_os_error().unwrap_or(0),
+                format_args!("cannot create {}", quote_name(&entry.name)),
+            );
+            rollback(&mut journal);
+            for rest in &staged[i..] {
+                let _ = fs::remove_file(&rest.tmp_name);
+            }
+            discard_staged(&mut trans);
+            trans.failed = true;
+            return false;
+        }
+    }
+
+    for entry in journal {
+        if let Some(backup) = entry.backup {
+            let _ = if entry.backup_is_dir {
+                fs::remove_dir(&backup)
+            } else {
+                fs::remove_file(&backup)
+            };
+        }
+    }
+    trans.created_dirs.clear();
+    true
+}
+
+// 返回创建 PATH 时需要新建的各级目录，按创建顺序排列
+pub fn missing_directories(path: &Path) -> Vec<OsString> {
+    let mut dirs: Vec<OsString> = path
+        .ancestors()
+        .take_while(|p| !p.as_os_str().is_empty() && fs::symlink_metadata(p).is_err())
+        .map(|p| p.as_os_str().to_os_string())
+        .collect();
+    dirs.reverse();
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_directories() {
+        let base = std::env::temp_dir().join(format!("utcpio-trans-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+
+        let path = base.join("a/b/c");
+        let dirs = missing_directories(&path);
+        assert_eq!(
+            dirs,
+            vec![
+                base.join("a").into_os_string(),
+                base.join("a/b").into_os_string(),
+                base.join("a/b/c").into_os_string(),
+            ]
+        );
+        assert!(missing_directories(&base).is_empty());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+// SPDX-FileCopyrightText: 2025 UnionTech Software Technology Co., Ltd.
+//
+// # SPDX-License-Identifier: GPL-3.0-or-later
+
+// 用 sed 风格的替换表达式改写成员名和链接目标（--transform）。
+// 表达式的形式是 s/REGEX/REPL/FLAGS，'/' 可以换成其他字符，一个参数中可以用 ';'
+// 分隔多个表达式，--transform 也可以给出多次，按顺序依次作用于名字。REGEX 的语法
+// 与 --regex 模式相同；REPL 中 '&' 表示匹配的部分，'\1' 到 '\9' 表示子表达式。
+// FLAGS 中 'g' 替换所有的匹配，数字 N 从第 N 个匹配开始替换，'i' 忽略大小写，
+// 'r'、's'、'h' 分别表示作用于普通的成员名、符号链接的目标和硬链接的目标，对应的
+// 大写字母表示不作用于这类名字，三者默认都作用。
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+use regex::bytes::{Captures, Regex, RegexBuilder};
+
+use crate::appargs::*;
+use crate::util::{quote_name, warn_msg};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameKind {
+    Regular,
+    Symlink,
+    Hardlink,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Replacement {
+    Text(Vec<u8>),
+    Group(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Transform {
+    regex: Regex,
+    replacement: Vec<Replacement>,
+    global: bool,
+    occurrence: usize,
+    regular: bool,
+    symlink: bool,
+    hardlink: bool,
+}
+
+// 读到未转义的分隔符 DELIM 为止，"\DELIM" 换成 DELIM，其他转义原样保留
+fn take_part(chars: &mut std::iter::Peekable<std::str::Chars>, delim: char) -> Option<String> {
+    let mut part = String::new();
+    while let Some(c) = chars.next() {
+        if c == delim {
+            return Some(part);
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delim => part.push(next),
+                Some(next) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => return None,
+            }
+        } else {
+            part.push(c);
+        }
+    }
+    None
+}
+
+fn parse_replacement(text: &str) -> Vec<Replacement> {
+    let mut parts = Vec::new();
+    let mut literal = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let group = match c {
+            '&' => Some(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => Some(digit as usize - '0' as usize),
+                Some('n') => {
+                    literal.push(b'\n');
+                    None
+                }
+                Some(other) => {
+                    let mut buf = [0u8; 4];
+                    literal.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+                    None
+                }
+                None => {
+                    literal.push(b'\\');
+                    None
+                }
+            },
+            _ => {
+                let mut buf = [0u8; 4];
+                literal.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                None
+            }
+        };
+        if let Some(group) = group {
+            if !literal.is_empty() {
+                parts.push(Replacement::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(Replacement::Group(group));
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Replacement::Text(literal));
+    }
+    parts
+}
+
+// 解析 --transform 的参数，其中可以有多个用 ';' 分隔的表达式
+pub fn parse_transforms(text: &str) -> Result<Vec<Transform>, String> {
+    let mut transforms = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        match chars.next() {
+            Some('s') => {}
+            _ => return Err("expression must start with `s'".to_string()),
+        }
+        let delim = match chars.next() {
+            Some(c) if c != '\\' && c != '\n' => c,
+            _ => return Err("missing delimiter".to_string()),
+        };
+        let unterminated = || format!("missing terminating `{}'", delim);
+        let pattern = take_part(&mut chars, delim).ok_or_else(unterminated)?;
+        let replacement = take_part(&mut chars, delim).ok_or_else(unterminated)?;
+
+        let (mut global, mut ignore_case) = (false, false);
+        let (mut regular, mut symlink, mut hardlink) = (true, true, true);
+        let mut occurrence = String::new();
+        for c in chars.by_ref() {
+            match c {
+                ';' => break,
+                'g' => global = true,
+                'i' => ignore_case = true,
+                // 与 GNU tar 兼容，REGEX 总是扩展正则表达式
+                'x' => {}
+                'r' | 'R' => regular = c == 'r',
+                's' | 'S' => symlink = c == 's',
+                'h' | 'H' => hardlink = c == 'h',
+                '0'..='9' => occurrence.push(c),
+                _ => return Err(format!("unknown flag `{}'", c)),
+            }
+        }
+        let occurrence = match occurrence.parse() {
+            _ if occurrence.is_empty() => 1,
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("invalid occurrence `{}'", occurrence)),
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let replacement = parse_replacement(&replacement);
+        for part in &replacement {
+            if let Replacement::Group(n) = *part {
+                if n >= regex.captures_len() {
+                    return Err(format!("invalid reference \\{} on `s' command's RHS", n));
+                }
+            }
+        }
+        transforms.push(Transform {
+            regex,
+            replacement,
+            global,
+            occurrence,
+            regular,
+            symlink,
+            hardlink,
+        });
+        if chars.peek().is_none() {
+            return Ok(transforms);
+        }
+    }
+}
+
+impl Transform {
+    fn applies_to(&self, kind: NameKind) -> bool {
+        match kind {
+            NameKind::Regular => self.regular,
+            NameKind::Symlink => self.symlink,
+            NameKind::Hardlink => self.hardlink,
+        }
+    }
+
+    fn expand(&self, caps: &Captures, out: &mut Vec<u8>) {
+        for part in &self.replacement {
+            match part {
+                Replacement::Text(text) => out.extend_from_slice(text),
+                Replacement::Group(n) => {
+                    if let Some(group) = caps.get(*n) {
+                        out.extend_from_slice(group.as_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn apply(&self, name: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(name.len());
+        let mut last = 0;
+        for (count, caps) in self.regex.captures_iter(name).enumerate() {
+            if count + 1 < self.occurrence {
+                continue;
+            }
+            let whole = caps.get(0).unwrap();
+            out.extend_from_slice(&name[last..whole.start()]);
+            self.expand(&caps, &mut out);
+            last = whole.end();
+            if !self.global {
+                break;
+            }
+        }
+        out.extend_from_slice(&name[last..]);
+        out
+    }
+}
+
+// 按顺序用所有适用于 KIND 的表达式改写名字，没有 --transform 时按原样返回
+pub fn transform_name(name: &OsStr, kind: NameKind) -> OsString {
+    let transforms = get_transforms();
+    if transforms.is_empty() {
+        return name.to_os_string();
+    }
+    let mut bytes = name.as_bytes().to_vec();
+    for transform in transforms.iter().filter(|t| t.applies_to(kind)) {
+        bytes = transform.apply(&bytes);
+    }
+    OsString::from_vec(bytes)
+}
+
+// 改写成员名，结果为空时给出警告并返回 None，这个成员被跳过
+pub fn transform_member_name(name: &OsStr) -> Option<OsString> {
+    let new_name = transform_name(name, NameKind::Regular);
+    if new_name.is_empty() {
+        warn_msg(format_args!(
+            "{}: file name is empty after transformation, skipped",
+            quote_name(name)
+        ));
+        return None;
+    }
+    Some(new_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(expr: &str, name: &str) -> String {
+        let mut bytes = name.as_bytes().to_vec();
+        for transform in parse_transforms(expr).unwrap() {
+            bytes = transform.apply(&bytes);
+        }
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_transform() {
+        assert_eq!(apply("s/^usr/opt/", "usr/lib/usr"), "opt/lib/usr");
+        assert_eq!(apply("s/a/x/g", "banana"), "bxnxnx");
+        assert_eq!(apply("s/a/x/2", "banana"), "banxna");
+        assert_eq!(apply("s/a/x/2g", "banana"), "banxnx");
+        assert_eq!(apply("s,([^/]*)/(.*),\\2/\\1,", "a/b/c"), "b/c/a");
+        assert_eq!(apply("s/lib/[&]/", "usr/lib"), "usr/[lib]");
+        assert_eq!(apply("s/LIB/x/i;s|/|\\||g", "usr/lib"), "usr|x");
+        assert_eq!(apply("s/\\//_/g", "a/b"), "a_b");
+        assert_eq!(apply("s/b/\\&/", "abc"), "a&c");
+    }
+
+    #[test]
+    fn test_transform_flags_and_errors() {
+        let transforms = parse_transforms("s/a/b/rSH").unwrap();
+        assert!(transforms[0].applies_to(NameKind::Regular));
+        assert!(!transforms[0].applies_to(NameKind::Symlink));
+        assert!(!transforms[0].applies_to(NameKind::Hardlink));
+        let transforms = parse_transforms("s/a/b/RSh").unwrap();
+        assert!(transforms[0].applies_to(NameKind::Hardlink));
+        assert!(!transforms[0].applies_to(NameKind::Regular));
+
+        assert!(parse_transforms("y/a/b/").is_err());
+        assert!(parse_transforms("s/a/b").is_err());
+        assert!(parse_transforms("s/a/b/q").is_err());
+        assert!(parse_transforms("s/(a/b/").is_err());
+        assert!(parse_transforms("s/a/\\1/").is_err());
+        assert!(parse_transforms("s/a/b/0").is_err());
+    }
+}
@@ -12,7 +12,9 @@
 use core::str::FromStr;
 use nix::libc::{gid_t, uid_t};
 
-// 模拟 passwd 和 group 结构（纯 Rust）
+use crate::idcache::{lookup_group, lookup_group_name, lookup_user};
+
+// passwd 和 group 结构（纯 Rust）
 #[derive(Clone)]
 pub struct Passwd {
     pub pw_name: String,
@@ -26,51 +28,27 @@ pub struct Group {
     pub gr_gid: gid_t,
 }
 
+// 在本机的 NSS 或 --passwd-root 等选项给出的文件中查找
 fn getpwnam(name: &str) -> Option<Passwd> {
-    // 这里是模拟实现，实际应从系统或其他数据源获取
-    match name {
-        "root" => Some(Passwd {
-            pw_name: "root".to_string(),
-            pw_uid: 0,
-            pw_gid: 0,
-        }),
-        "user1" => Some(Passwd {
-            pw_name: "user1".to_string(),
-            pw_uid: 1000,
-            pw_gid: 1000,
-        }),
-        _ => None,
-    }
+    lookup_user(name).map(|entry| Passwd {
+        pw_name: entry.name,
+        pw_uid: entry.uid,
+        pw_gid: entry.gid,
+    })
 }
 
 fn getgrgid(gid: gid_t) -> Option<Group> {
-    // 模拟实现
-    match gid {
-        0 => Some(Group {
-            gr_name: "root".to_string(),
-            gr_gid: 0,
-        }),
-        1000 => Some(Group {
-            gr_name: "users".to_string(),
-            gr_gid: 1000,
-        }),
-        _ => None,
-    }
+    lookup_group_name(gid).map(|name| Group {
+        gr_name: name,
+        gr_gid: gid,
+    })
 }
 
 fn getgrnam(name: &str) -> Option<Group> {
-    // 模拟实现
-    match name {
-        "root" => Some(Group {
-            gr_name: "root".to_string(),
-            gr_gid: 0,
-        }),
-        "users" => Some(Group {
-            gr_name: "users".to_string(),
-            gr_gid: 1000,
-        }),
-        _ => None,
-    }
+    lookup_group(name).map(|entry| Group {
+        gr_name: entry.name,
+        gr_gid: entry.gid,
+    })
 }
 
 // 检查字符串是否只包含数字（替代 isnumber_p）
@@ -78,6 +56,35 @@ fn is_number(s: &str) -> bool {
     !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
 }
 
+// 解析 --set-owner 和 --set-group 的 NAME[:ID] 或 +ID。给出 ID 时名字不查找，
+// 只写入 tar 头部；只给出名字时按名字查找 ID
+fn parse_name_spec(
+    spec: &str,
+    lookup: impl Fn(&str) -> Option<u32>,
+) -> Option<(u32, Option<String>)> {
+    if let Some(id) = spec.strip_prefix('+') {
+        return is_number(id)
+            .then(|| u32::from_str(id).ok())
+            .flatten()
+            .map(|id| (id, None));
+    }
+    match spec.split_once(':') {
+        Some((name, id)) if !name.is_empty() && is_number(id) => {
+            Some((u32::from_str(id).ok()?, Some(name.to_string())))
+        }
+        Some(_) => None,
+        None => lookup(spec).map(|id| (id, Some(spec.to_string()))),
+    }
+}
+
+pub fn parse_owner_spec(spec: &str) -> Result<(uid_t, Option<String>), &'static str> {
+    parse_name_spec(spec, |name| getpwnam(name).map(|p| p.pw_uid)).ok_or("invalid user")
+}
+
+pub fn parse_group_spec(spec: &str) -> Result<(gid_t, Option<String>), &'static str> {
+    parse_name_spec(spec, |name| getgrnam(name).map(|g| g.gr_gid)).ok_or("invalid group")
+}
+
 pub fn parse_user_spec(
     spec: &str,
 ) -> Result<(uid_t, gid_t, Option<String>, Option<String>), &'static str> {
@@ -9,8 +9,11 @@
     dead_code
 )]
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::fs::Metadata;
@@ -22,20 +25,21 @@ use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
 use std::os::fd::AsRawFd;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::os::unix::fs::FileTypeExt;
 use std::os::unix::fs::MetadataExt;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::MutexGuard;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use std::sync::Once;
 
+use gnu::argp::get_program_name;
 use gnu::error::ENOSPC;
 use gnu::error::ENXIO;
 use gnu::fdutimensat::*;
 use gnu::quotearg::*;
 use gnu::safe_read::SAFE_READ_ERROR;
-use gnu::stripslash::*;
 //use gnu::xmalloc::*;
 
 use nix::libc;
@@ -48,25 +52,25 @@ use nix::libc::timespec;
 use nix::libc::AT_FDCWD;
 use nix::libc::EIO;
 
-use nix::sys::stat::major;
-use nix::sys::stat::minor;
-
 use pax::paxerror::*;
 use pax::paxlib::*;
 use pax::paxnames::*;
 use pax::rmt::*;
 
 use crate::appargs::*;
-use crate::copyin::*;
 use crate::cpiohdr::*;
 use crate::externs::*;
 use crate::filetype::*;
 use crate::global::*;
+use crate::idmap::{map_gid, map_uid};
+use crate::modechange::mode_adjust;
+use crate::resume::*;
+use crate::rootless::{format_override_stat, set_override_stat, OVERRIDE_STAT_XATTR};
+use crate::transaction::*;
 
 use crate::util::libc::gid_t;
 use crate::util::libc::uid_t;
 
-use gnu::dirname::*;
 use gnu::error::*;
 use gnu::util::validate_and_sanitize_path;
 
@@ -76,18 +80,41 @@ use std::sync::Mutex;
 static mut REEL_NUMBER: i32 = 1;
 const DISKBLOCKSIZE: i32 = 512;
 static mut NEXT_INODE: u64 = 0;
+static TEMP_NAME_SEQ: AtomicUsize = AtomicUsize::new(0);
+// 已经创建、还没有改名到位的临时文件，进程因致命错误退出时删除
+static PENDING_TEMP_FILES: Mutex<Vec<OsString>> = Mutex::new(Vec::new());
+static PENDING_TEMP_CLEANUP: Once = Once::new();
 
 // fn raw_fd_to_file(raw_fd: i32) -> File {
 //     unsafe { File::from_raw_fd(raw_fd) }
 // }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone)]
 pub struct InodeVal {
-    pub inode: u64,                // ino_t
-    pub major_num: u64,            // unsigned long
-    pub minor_num: u64,            // unsigned long
-    pub trans_inode: u64,          // ino_t
-    pub file_name: Option<String>, // char*
+    pub inode: u64,                  // ino_t
+    pub major_num: u64,              // unsigned long
+    pub minor_num: u64,              // unsigned long
+    pub trans_inode: u64,            // ino_t
+    pub file_name: Option<OsString>, // char*
+}
+
+// 哈希表只按 inode 和设备号查找，与 GNU cpio 的 inode_val_compare 一致
+impl PartialEq for InodeVal {
+    fn eq(&self, other: &Self) -> bool {
+        self.inode == other.inode
+            && self.major_num == other.major_num
+            && self.minor_num == other.minor_num
+    }
+}
+
+impl Eq for InodeVal {}
+
+impl std::hash::Hash for InodeVal {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.inode.hash(state);
+        self.major_num.hash(state);
+        self.minor_num.hash(state);
+    }
 }
 
 // Inode hash table. Allocated by first call to add_inode.
@@ -101,7 +128,6 @@ const MODE_RW: u32 = 0o666; // Example mode, adjust as needed
 // Placeholder variables and functions (using Mutex for thread safety)
 
 lazy_static! {
-    static ref COPY_FUNCTION: Mutex<Option<fn() -> io::Result<()>>> = Mutex::new(None);
     static ref APPEND_FLAG: Mutex<bool> = Mutex::new(false);
     static ref RSH_COMMAND_OPTION: Mutex<Option<String>> = Mutex::new(None);
 }
@@ -185,20 +211,29 @@ pub fn to_ascii(where_: &mut [u8], v: u64, digits: usize, logbase: u32, nul: boo
     v != 0
 }
 
-pub fn link_to_maj_min_ino(file_name: &str, st_dev_maj: u32, st_dev_min: u32, st_ino: u64) -> i32 {
+pub fn link_to_maj_min_ino(
+    file_name: &OsStr,
+    st_dev_maj: u32,
+    st_dev_min: u32,
+    st_ino: u64,
+) -> i32 {
     if let Some(link_name) = find_inode_file(st_ino, st_dev_maj as u64, st_dev_min as u64) {
         link_to_name(file_name, &link_name)
     } else {
         add_inode(
             st_ino,
-            Some(file_name.to_string()),
+            Some(file_name.to_os_string()),
             st_dev_maj as u64,
             st_dev_min as u64,
         );
         -1
     }
 }
-pub fn link_to_name(link_name: &str, link_target: &str) -> i32 {
+pub fn link_to_name(link_name: &OsStr, link_target: &OsStr) -> i32 {
+    if get_transactional_flag() {
+        return stage_link_to_name(link_name, link_target);
+    }
+
     let link_name_path = Path::new(link_name);
     let link_target_path = Path::new(link_target);
 
@@ -209,16 +244,155 @@ pub fn link_to_name(link_name: &str, link_target: &str) -> i32 {
         res = fs::hard_link(link_target_path, link_name_path);
     }
 
+    // 目标已存在时先链接到临时文件名，再改名覆盖，保证替换是原子的
+    if matches!(&res, Err(e) if e.kind() == io::ErrorKind::AlreadyExists) {
+        let tmp_name = cpio_temp_name(link_name);
+        res = fs::hard_link(link_target_path, &tmp_name)
+            .and_then(|_| cpio_rename_into_place(&tmp_name, link_name));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_name);
+        }
+    }
+
     match res {
         Ok(_) => {
             if get_verbose_flag() {
-                println!("{} linked to {}", link_target, link_name);
+                println!(
+                    "{} linked to {}",
+                    quote_name(link_target),
+                    quote_name(link_name)
+                );
             }
             0
         }
         Err(e) => {
             if get_link_flag() {
-                eprintln!("cannot link {} to {}: {}", link_target, link_name, e);
+                eprintln!(
+                    "cannot link {} to {}: {}",
+                    quote_name(link_target),
+                    quote_name(link_name),
+                    e
+                );
+            }
+            -e.raw_os_error().unwrap_or(1)
+        }
+    }
+}
+
+// 在目标所在目录中生成一个临时文件名，解包时先写入该文件，完成后再改名为目标
+pub fn cpio_temp_name(name: &OsStr) -> OsString {
+    let seq = TEMP_NAME_SEQ.fetch_add(1, Ordering::Relaxed);
+    let path = Path::new(name);
+    let mut tmp = OsString::from(".");
+    tmp.push(path.file_name().unwrap_or_default());
+    tmp.push(format!(".cpio{}-{}", std::process::id(), seq));
+
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.join(tmp).into_os_string(),
+        _ => tmp,
+    }
+}
+
+extern "C" fn remove_pending_temp_files() {
+    if let Ok(mut pending) = PENDING_TEMP_FILES.try_lock() {
+        for tmp_name in pending.drain(..) {
+            let _ = fs::remove_file(tmp_name);
+        }
+    }
+}
+
+// 记录刚创建的临时文件，直到它被改名到位或删除
+pub fn register_temp_file(tmp_name: &OsStr) {
+    PENDING_TEMP_CLEANUP.call_once(|| unsafe {
+        libc::atexit(remove_pending_temp_files);
+    });
+    PENDING_TEMP_FILES
+        .lock()
+        .unwrap()
+        .push(tmp_name.to_os_string());
+}
+
+pub fn unregister_temp_file(tmp_name: &OsStr) {
+    let mut pending = PENDING_TEMP_FILES.lock().unwrap();
+    if let Some(i) = pending.iter().rposition(|name| name == tmp_name) {
+        pending.swap_remove(i);
+    }
+}
+
+// 将临时文件改名为目标文件。目标已存在时直接覆盖；
+// 否则使用 RENAME_NOREPLACE，避免覆盖期间被其他进程创建的同名文件
+pub fn cpio_rename_into_place(tmp_name: &OsStr, name: &OsStr) -> io::Result<()> {
+    if fs::symlink_metadata(name).is_ok() {
+        return fs::rename(tmp_name, name);
+    }
+
+    let (tmp_cstr, name_cstr) = match (
+        std::ffi::CString::new(tmp_name.as_bytes()),
+        std::ffi::CString::new(name.as_bytes()),
+    ) {
+        (Ok(t), Ok(n)) => (t, n),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid path string",
+            ))
+        }
+    };
+    let res = unsafe {
+        libc::renameat2(
+            AT_FDCWD,
+            tmp_cstr.as_ptr(),
+            AT_FDCWD,
+            name_cstr.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if res == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        // 内核或文件系统不支持 renameat2 时，用 link + unlink 达到同样的效果
+        Some(libc::EINVAL) | Some(libc::ENOSYS) => {
+            fs::hard_link(tmp_name, name)?;
+            fs::remove_file(tmp_name)
+        }
+        _ => Err(err),
+    }
+}
+
+// 事务模式下的硬链接：链接目标可能还在暂存中，链接本身也先建在临时文件名上
+fn stage_link_to_name(link_name: &OsStr, link_target: &OsStr) -> i32 {
+    let target = transaction_staged_name(link_target).unwrap_or_else(|| link_target.to_os_string());
+    let tmp_name = cpio_temp_name(link_name);
+
+    let mut res = fs::hard_link(&target, &tmp_name);
+    if res.is_err() && get_create_dir_flag() {
+        create_all_directories(link_name);
+        res = fs::hard_link(&target, &tmp_name);
+    }
+
+    match res {
+        Ok(_) => {
+            transaction_stage(&tmp_name, link_name);
+            if get_verbose_flag() {
+                println!(
+                    "{} linked to {}",
+                    quote_name(link_target),
+                    quote_name(link_name)
+                );
+            }
+            0
+        }
+        Err(e) => {
+            if get_link_flag() {
+                eprintln!(
+                    "cannot link {} to {}: {}",
+                    quote_name(link_target),
+                    quote_name(link_name),
+                    e
+                );
             }
             -e.raw_os_error().unwrap_or(1)
         }
@@ -535,15 +709,97 @@ pub fn tape_buffered_peek(
     got_bytes as i32
 }
 
-pub fn tape_toss_input(input_tape: &mut MutexGuard<TapeInput>, in_des: &mut File, num_bytes: i32) {
+// 将输入定位到归档中 OFFSET 处（只能向前）
+pub fn tape_seek_input(input_tape: &mut MutexGuard<TapeInput>, in_des: &mut File, offset: u64) {
+    let current = (input_tape.input_bytes - input_tape.input_size) as u64;
+    tape_toss_input(input_tape, in_des, offset.saturating_sub(current));
+}
+
+// 用 lseek 把输入定位到归档中 OFFSET 处，可以向后。输入不能定位时返回 false
+pub fn tape_lseek_input_to(
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    offset: u64,
+) -> bool {
+    if !input_tape.input_is_seekable {
+        return false;
+    }
+    // 已经读入的 input_bytes 个字节对应文件中当前位置之前的内容
+    let current = rmtlseek(in_des, 0, libc::SEEK_CUR);
+    if current < input_tape.input_bytes as i64 {
+        return false;
+    }
+    let target = current - input_tape.input_bytes as i64 + offset as i64;
+    if rmtlseek(in_des, target, libc::SEEK_SET) != target {
+        return false;
+    }
+
+    input_tape.input_bytes = offset as usize;
+    input_tape.input_size = 0;
+    input_tape.in_buff = 0;
+    true
+}
+
+// 用 lseek 跳过输入中的 NUM_BYTES 字节，缓冲区中剩余的数据直接丢弃。
+// 成功返回 true；输入不能定位时返回 false，由调用者读入并丢弃
+fn tape_lseek_input(
+    input_tape: &mut MutexGuard<TapeInput>,
+    in_des: &mut File,
+    num_bytes: u64,
+) -> bool {
+    if !input_tape.input_is_seekable || num_bytes <= input_tape.input_size as u64 {
+        return false;
+    }
+    let skip = (num_bytes - input_tape.input_size as u64) as i64;
+
+    let before = rmtlseek(in_des, 0, libc::SEEK_CUR);
+    if before < 0 {
+        input_tape.input_is_seekable = false;
+        return false;
+    }
+    // 不能越过文件末尾，多卷归档的下一卷要靠读到 EOF 才能切换
+    if !isrmt(in_des) {
+        match in_des.metadata() {
+            Ok(st) if (before + skip) as u64 <= st.len() => (),
+            _ => return false,
+        }
+    }
+    // 远程磁带上的 lseek 可能什么都不做，检查定位后的位置
+    let after = rmtlseek(in_des, skip, libc::SEEK_CUR);
+    if after != before + skip {
+        if after >= 0 {
+            rmtlseek(in_des, before, libc::SEEK_SET);
+        }
+        input_tape.input_is_seekable = false;
+        return false;
+    }
+
+    input_tape.input_bytes += skip as usize;
+    input_tape.input_size = 0;
+    input_tape.in_buff = 0;
+    true
+}
+
+pub fn tape_toss_input(input_tape: &mut MutexGuard<TapeInput>, in_des: &mut File, num_bytes: u64) {
     let mut bytes_left = num_bytes;
 
+    // 校验 CRC 时必须读出数据
+    if !(get_only_verify_crc_flag() && get_crc_i_flag())
+        && tape_lseek_input(input_tape, in_des, num_bytes)
+    {
+        return;
+    }
+
     while bytes_left > 0 {
         if input_tape.input_size == 0 {
-            tape_fill_input_buffer(input_tape, in_des, num_bytes);
+            tape_fill_input_buffer(
+                input_tape,
+                in_des,
+                bytes_left.min(get_io_block_size() as u64) as i32,
+            );
         }
 
-        let space_left = if bytes_left < input_tape.input_size as i32 {
+        let space_left = if bytes_left < input_tape.input_size as u64 {
             bytes_left as usize
         } else {
             input_tape.input_size
@@ -561,7 +817,7 @@ pub fn tape_toss_input(input_tape: &mut MutexGuard<TapeInput>, in_des: &mut File
 
         input_tape.input_size -= space_left;
         input_tape.in_buff += space_left;
-        bytes_left -= space_left as i32;
+        bytes_left -= space_left as u64;
     }
 }
 
@@ -594,17 +850,203 @@ pub fn write_nuls_to_file(
         writer(tape_output, &mut zeros_512.to_vec(), out_file, extra_bytes);
     }
 }
+// 在内核中把 IN_DES 当前位置开始的 NUM_BYTES 字节复制到 OUT_DES 的当前位置，
+// 数据不经过用户态缓冲区。依次尝试 copy_file_range（同一文件系统上可能直接
+// 共享数据块）、splice（有一端是管道时）和 sendfile。返回实际复制的字节数，
+// 出错或提前遇到文件末尾时剩下的部分由调用者用缓冲区复制
+fn kernel_copy(in_des: &File, out_des: &File, num_bytes: usize) -> usize {
+    const MAX_CHUNK: usize = 1 << 30;
+    let in_fd = in_des.as_raw_fd();
+    let out_fd = out_des.as_raw_fd();
+    let mut copied = 0;
+    let mut method = 0;
+
+    while copied < num_bytes && method < 3 {
+        let chunk = (num_bytes - copied).min(MAX_CHUNK);
+        let n = unsafe {
+            match method {
+                0 => libc::copy_file_range(
+                    in_fd,
+                    std::ptr::null_mut(),
+                    out_fd,
+                    std::ptr::null_mut(),
+                    chunk,
+                    0,
+                ),
+                1 => libc::splice(
+                    in_fd,
+                    std::ptr::null_mut(),
+                    out_fd,
+                    std::ptr::null_mut(),
+                    chunk,
+                    libc::SPLICE_F_MOVE,
+                ),
+                _ => libc::sendfile(out_fd, in_fd, std::ptr::null_mut(), chunk),
+            }
+        };
+        if n > 0 {
+            copied += n as usize;
+        } else if n == 0 {
+            break;
+        } else if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            // 这种方式不支持这两个文件，换下一种
+            method += 1;
+        }
+    }
+    copied
+}
+
+// 输出是磁盘文件时能否在内核中直接复制数据。要计算校验和、交换字节或写稀疏
+// 文件时只能经过缓冲区
+fn disk_kernel_copy_ok() -> bool {
+    !get_crc_i_flag() && !get_sparse_flag() && !get_swapping_bytes() && !get_swapping_halfwords()
+}
+
+// 普通文件占用的块少于它的长度时说明其中有洞
+pub fn file_has_holes(file: &File) -> bool {
+    file.metadata()
+        .map(|st| st.is_file() && st.blocks() * 512 < st.len())
+        .unwrap_or(false)
+}
+
+// 返回 OFFSET 处或之后下一段数据的开始位置，之后没有数据时返回文件长度。
+// 文件系统不支持 SEEK_DATA 时把整个文件都当作数据。不改变文件的当前位置
+pub fn seek_data(file: &File, offset: u64) -> u64 {
+    let fd = file.as_raw_fd();
+    let current = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+    let res = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+    let data = if res >= 0 {
+        res as u64
+    } else if io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) {
+        file.metadata()
+            .map(|st| st.len())
+            .unwrap_or(offset)
+            .max(offset)
+    } else {
+        offset
+    };
+    unsafe { libc::lseek(fd, current, libc::SEEK_SET) };
+    data
+}
+
+// 返回 OFFSET 处或之后下一个洞的开始位置，文件末尾也算作洞。不改变文件的当前位置
+pub fn seek_hole(file: &File, offset: u64) -> u64 {
+    let fd = file.as_raw_fd();
+    let current = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+    let res = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_HOLE) };
+    let hole = if res >= 0 {
+        res as u64
+    } else if io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) {
+        offset
+    } else {
+        file.metadata()
+            .map(|st| st.len())
+            .unwrap_or(offset)
+            .max(offset)
+    };
+    unsafe { libc::lseek(fd, current, libc::SEEK_SET) };
+    hole
+}
+
+// 释放 FILE 中从 OFFSET 开始 LEN 字节占用的块，读出来都是零。文件系统不支持时
+// 什么也不做，由调用者跳过这一段
+pub fn punch_hole(file: &File, offset: u64, len: u64) {
+    if len > 0 {
+        unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        };
+    }
+}
+
+// 按照输入文件中数据和洞的分布复制 NUM_BYTES 字节：数据在内核中直接复制，洞在
+// 输出中也留成洞。返回处理了的字节数，复制出错或文件变短时剩下的部分由调用者
+// 用缓冲区复制
+fn copy_sparse_file(in_des: &mut File, out_des: &mut File, num_bytes: usize) -> usize {
+    let (Ok(in_start), Ok(out_start)) = (in_des.stream_position(), out_des.stream_position())
+    else {
+        return 0;
+    };
+    let end = in_start + num_bytes as u64;
+    let mut pos = in_start;
+
+    while pos < end {
+        let data = seek_data(in_des, pos).min(end);
+        if data > pos {
+            punch_hole(out_des, out_start + pos - in_start, data - pos);
+            pos = data;
+            continue;
+        }
+        let hole = seek_hole(in_des, pos).min(end);
+        if hole <= pos
+            || in_des.seek(SeekFrom::Start(pos)).is_err()
+            || out_des
+                .seek(SeekFrom::Start(out_start + pos - in_start))
+                .is_err()
+        {
+            break;
+        }
+        let copied = kernel_copy(in_des, out_des, (hole - pos) as usize) as u64;
+        pos += copied;
+        if pos < hole {
+            break;
+        }
+    }
+
+    // 文件以洞结尾时要设置文件长度
+    let out_pos = out_start + pos - in_start;
+    if pos == end && out_des.metadata().map_or(false, |st| st.len() < out_pos) {
+        let _ = out_des.set_len(out_pos);
+    }
+    let _ = in_des.seek(SeekFrom::Start(pos));
+    let _ = out_des.seek(SeekFrom::Start(out_pos));
+    (pos - in_start) as usize
+}
+
+// 向归档写入 NUM_BYTES 个零，用于输入文件中的洞
+fn tape_write_zeros(
+    tape_output: &mut MutexGuard<TapeOutput>,
+    out_file: &mut File,
+    num_bytes: usize,
+) {
+    let mut zeros = vec![0u8; num_bytes.min(64 * 1024)];
+    let mut num_bytes = num_bytes;
+    while num_bytes > 0 {
+        let size = num_bytes.min(zeros.len());
+        tape_buffered_write(tape_output, &mut zeros, out_file, size);
+        num_bytes -= size;
+    }
+}
+
 pub fn copy_files_tape_to_disk(
     input_tape: &mut MutexGuard<TapeInput>,
     output_tape: &mut MutexGuard<TapeOutput>,
     in_des: &mut File,
     out_file: &mut File,
-    num_bytes: i32,
+    num_bytes: u64,
 ) {
     let mut num_bytes = num_bytes as usize;
+    // 磁带和多卷归档要按块读取
+    let mut kernel_copy_ok =
+        disk_kernel_copy_ok() && !input_tape.input_is_special && !isrmt(in_des);
 
     while num_bytes > 0 {
         if input_tape.input_size == 0 {
+            if kernel_copy_ok {
+                kernel_copy_ok = false;
+                disk_empty_output_buffer(output_tape, out_file, false);
+                let copied = kernel_copy(in_des, out_file, num_bytes);
+                input_tape.input_bytes += copied;
+                output_tape.output_bytes += copied;
+                num_bytes -= copied;
+                if num_bytes == 0 {
+                    break;
+                }
+            }
             tape_fill_input_buffer(input_tape, in_des, get_io_block_size());
         }
 
@@ -724,20 +1166,74 @@ pub fn copy_files_disk_to_tape(
     input_tape: &mut MutexGuard<TapeInput>,
     in_des: &mut File,
     out_file: &mut File,
-    num_bytes: i32,
-    filename: &str,
+    num_bytes: u64,
+    filename: &OsStr,
 ) {
     let mut num_bytes = num_bytes as usize;
     let original_num_bytes = num_bytes;
+    let block_size = get_io_block_size() as usize;
+    // 写磁带和多卷归档时必须经过缓冲区，保证每次写入一个完整的块
+    let mut kernel_copy_ok =
+        !get_crc_i_flag() && !tape_output.output_is_special && !isrmt(out_file);
+    // 有洞的文件只读其中的数据，洞直接向归档写入零。DATA_END 是当前这段数据的结束位置
+    let start = in_des.stream_position().unwrap_or(0);
+    let mut data_end = if input_tape.input_size == 0 && file_has_holes(in_des) {
+        kernel_copy_ok = false;
+        Some(start)
+    } else {
+        None
+    };
 
     while num_bytes > 0 {
         if input_tape.input_size == 0 {
-            let read_size = if num_bytes < DISK_IO_BLOCK_SIZE {
+            let mut read_size = if num_bytes < DISK_IO_BLOCK_SIZE {
                 num_bytes
             } else {
                 DISK_IO_BLOCK_SIZE
             };
 
+            if let Some(end) = data_end {
+                let pos = start + (original_num_bytes - num_bytes) as u64;
+                if pos >= end {
+                    let hole = ((seek_data(in_des, pos) - pos) as usize).min(num_bytes);
+                    if hole > 0 && in_des.seek(SeekFrom::Current(hole as i64)).is_ok() {
+                        tape_write_zeros(tape_output, out_file, hole);
+                        num_bytes -= hole;
+                        continue;
+                    }
+                    let next_hole = seek_hole(in_des, pos);
+                    // 文件变短了，按普通文件读取，由下面报告错误
+                    data_end = if next_hole > pos {
+                        Some(next_hole)
+                    } else {
+                        None
+                    };
+                }
+                if let Some(end) = data_end {
+                    read_size = read_size.min((end - pos) as usize);
+                }
+            }
+
+            if kernel_copy_ok {
+                if tape_output.output_size == block_size {
+                    tape_empty_output_buffer(tape_output, out_file);
+                }
+                if tape_output.output_size == 0 && num_bytes >= block_size {
+                    // 只直接复制整数个块，写入归档的仍然都是完整的块
+                    kernel_copy_ok = false;
+                    let copied = kernel_copy(in_des, out_file, num_bytes - num_bytes % block_size);
+                    input_tape.input_bytes += copied;
+                    tape_output.output_bytes += copied;
+                    num_bytes -= copied;
+                    if num_bytes == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                // 先用缓冲区把当前块填满
+                read_size = read_size.min(block_size - tape_output.output_size);
+            }
+
             let rc: i32 = disk_fill_input_buffer(input_tape, in_des, read_size);
 
             if rc != 0 {
@@ -745,14 +1241,16 @@ pub fn copy_files_disk_to_tape(
                     let s = if num_bytes == 1 { "" } else { "s" };
                     let message = format!(
                         "File {} shrunk by {} byte{}, padding with zeros",
-                        filename, num_bytes, s
+                        quote_name(filename),
+                        num_bytes,
+                        s
                     );
                     error(0, 0, format_args!("{}", message));
                 } else {
                     let message = format!(
                         "Read error at byte {} in file {}, padding with zeros",
                         original_num_bytes - num_bytes,
-                        filename
+                        quote_name(filename)
                     );
                     error(0, 0, format_args!("{}", message));
                 }
@@ -792,13 +1290,25 @@ pub fn copy_files_disk_to_disk(
     input_tape: &mut MutexGuard<TapeInput>,
     in_des: &mut File,
     out_des: &mut File,
-    num_bytes: i32,
-    filename: &str,
+    num_bytes: u64,
+    filename: &OsStr,
 ) {
     let mut num_bytes = num_bytes as usize;
     let original_num_bytes = num_bytes;
     let mut rc: i32;
 
+    if disk_kernel_copy_ok() && input_tape.input_size == 0 {
+        disk_empty_output_buffer(output_tape, out_des, false);
+        let copied = if file_has_holes(in_des) {
+            copy_sparse_file(in_des, out_des, num_bytes)
+        } else {
+            kernel_copy(in_des, out_des, num_bytes)
+        };
+        input_tape.input_bytes += copied;
+        output_tape.output_bytes += copied;
+        num_bytes -= copied;
+    }
+
     while num_bytes > 0 {
         if input_tape.input_size == 0 {
             let read_size = if num_bytes < DISK_IO_BLOCK_SIZE {
@@ -813,7 +1323,7 @@ pub fn copy_files_disk_to_disk(
                 if rc > 0 {
                     let message = format!(
                         "File {} shrunk by {} byte{}, padding with zeros",
-                        filename,
+                        quote_name(filename),
                         num_bytes,
                         if num_bytes == 1 { "" } else { "s" }
                     );
@@ -822,7 +1332,7 @@ pub fn copy_files_disk_to_disk(
                     let message = format!(
                         "Read error at byte {} in file {}, padding with zeros",
                         original_num_bytes - num_bytes,
-                        filename
+                        quote_name(filename)
                     );
                     error(0, 0, format_args!("{}", message));
                 }
@@ -858,46 +1368,49 @@ pub fn copy_files_disk_to_disk(
     }
 }
 
-pub fn warn_if_file_changed(file_name: &str, old_file_size: u64, old_file_mtime: u64) {
+pub fn warn_if_file_changed(file_name: &OsStr, old_file_size: u64, old_file_mtime: i64) {
     let path = Path::new(file_name);
     match fs::metadata(path) {
         Ok(new_file_stat) => {
             let new_file_size = new_file_stat.len();
-            let new_file_mtime = new_file_stat
-                .modified()
-                .ok()
-                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
-                .map(|duration| duration.as_secs())
-                .unwrap_or(0); //Handle error by setting to 0.
+            // 1970 年以前的时间是负数
+            let new_file_mtime = new_file_stat.mtime();
 
             if new_file_size > old_file_size {
                 let diff = new_file_size - old_file_size;
-                println!("File {} grew, {} new bytes not copied", file_name, diff);
+                println!(
+                    "File {} grew, {} new bytes not copied",
+                    quote_name(file_name),
+                    diff
+                );
             } else if new_file_mtime != old_file_mtime {
-                println!("File {} was modified while being copied", file_name);
+                println!(
+                    "File {} was modified while being copied",
+                    quote_name(file_name)
+                );
             }
         }
         Err(e) => {
-            eprintln!("Error getting file status for {}: {}", file_name, e);
+            eprintln!(
+                "Error getting file status for {}: {}",
+                quote_name(file_name),
+                e
+            );
         }
     }
 }
-pub fn create_all_directories(name: &str) {
-    if let Some(dir) = dir_name(name) {
-        let chars: Vec<char> = dir.chars().collect();
-        if chars.len() < 2 {
-            return;
-        }
-        if chars[0] != '.' || chars[1] != '\0' {
-            let fmt = if (get_warn_option() as usize & CPIO_WARN_INTERDIR) != 0 {
-                Some("Creating intermediate directory `%s`")
-            } else {
-                None
-            };
-            make_path(&dir.clone(), -1, -1, fmt);
-        }
-    } else {
-        error(PAXEXIT_FAILURE, 0, format_args!("virtual memory exhausted"));
+pub fn create_all_directories(name: &OsStr) {
+    let dir = match Path::new(name).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => return,
+    };
+    if dir != Path::new(".") {
+        let fmt = if (get_warn_option() as usize & CPIO_WARN_INTERDIR) != 0 {
+            Some("Creating intermediate directory `%s`")
+        } else {
+            None
+        };
+        make_path(dir.as_os_str(), -1, -1, fmt);
     }
 }
 pub fn prepare_append(
@@ -906,14 +1419,11 @@ pub fn prepare_append(
     out_file_des: &mut File,
 ) {
     let start_of_header = get_last_header_start();
-    let useful_bytes_in_block = (start_of_header % get_io_block_size()) as usize;
+    let useful_bytes_in_block = (start_of_header % get_io_block_size() as u64) as usize;
 
-    let start_of_block = start_of_header - useful_bytes_in_block as i32;
+    let start_of_block = start_of_header - useful_bytes_in_block as u64;
 
-    if out_file_des
-        .seek(SeekFrom::Start(start_of_block as u64))
-        .is_err()
-    {
+    if out_file_des.seek(SeekFrom::Start(start_of_block)).is_err() {
         error(
             PAXEXIT_FAILURE,
             std::io::Error::last_os_error().raw_os_error().unwrap_or(0),
@@ -931,10 +1441,7 @@ pub fn prepare_append(
             );
         }
 
-        if out_file_des
-            .seek(SeekFrom::Start(start_of_block as u64))
-            .is_err()
-        {
+        if out_file_des.seek(SeekFrom::Start(start_of_block)).is_err() {
             error(
                 PAXEXIT_FAILURE,
                 std::io::Error::last_os_error().raw_os_error().unwrap_or(0),
@@ -965,13 +1472,13 @@ fn find_inode_val(node_num: u64, major_num: u64, minor_num: u64) -> Option<Inode
     HASH_TABLE.lock().unwrap().get(&sample).cloned()
 }
 
-pub fn find_inode_file(node_num: u64, major_num: u64, minor_num: u64) -> Option<String> {
+pub fn find_inode_file(node_num: u64, major_num: u64, minor_num: u64) -> Option<OsString> {
     find_inode_val(node_num, major_num, minor_num).and_then(|ival| ival.file_name)
 }
 
 pub fn add_inode(
     node_num: u64,
-    file_name: Option<String>,
+    file_name: Option<OsString>,
     major_num: u64,
     minor_num: u64,
 ) -> InodeVal {
@@ -995,16 +1502,29 @@ pub fn add_inode(
     if let Ok(mut hash_table) = HASH_TABLE.lock() {
         hash_table.insert(temp.clone(), temp.clone());
     }
+    journal_record(&JournalRecord::Inode {
+        ino: node_num,
+        maj: major_num,
+        min: minor_num,
+        name: temp.file_name.clone(),
+    });
     temp
 }
 fn get_inode_and_dev(hdr: &mut CpioFileStat, st: &std::fs::Metadata) {
     unsafe {
         if get_renumber_inodes_option() {
             if st.nlink() > 1 {
-                if let Some(ival) = find_inode_val(st.ino(), major(st.dev()), minor(st.dev())) {
+                if let Some(ival) =
+                    find_inode_val(st.ino(), major(st.dev()) as u64, minor(st.dev()) as u64)
+                {
                     hdr.c_ino = ival.trans_inode;
                 } else {
-                    let ival = add_inode(st.ino(), None, major(st.dev()), minor(st.dev()));
+                    let ival = add_inode(
+                        st.ino(),
+                        None,
+                        major(st.dev()) as u64,
+                        minor(st.dev()) as u64,
+                    );
                     hdr.c_ino = ival.trans_inode;
                 }
             } else {
@@ -1020,33 +1540,24 @@ fn get_inode_and_dev(hdr: &mut CpioFileStat, st: &std::fs::Metadata) {
             hdr.c_dev_min = 0;
         } else {
             hdr.c_dev_maj = major(st.dev()) as i32;
-            hdr.c_dev_min = minor(st.dev()) as u32;
+            hdr.c_dev_min = minor(st.dev());
         }
     }
 }
 
 pub fn open_archive(file: &str) -> io::Result<File> {
     let fd;
-    let copy_in: fn() -> io::Result<()> = process_copy_in; // Workaround for pcc bug.
-
-    let copy_func_guard = match COPY_FUNCTION.lock() {
-        Ok(guard) => guard,
-        Err(_) => {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to lock COPY_FUNCTION",
-            ));
-        }
-    };
 
-    if *copy_func_guard == Some(copy_in) {
+    if get_copy_mode() == Some(CopyMode::In) {
         fd = rmtopen(
             file,
             libc::O_RDONLY,
             MODE_RW,
             get_rsh_command_option().as_deref().unwrap_or(""),
         );
-    } else if !get_append_flag() {
+    } else if !get_append_flag()
+        && !get_resume_journal().is_some_and(|journal| journal_has_checkpoint(&journal))
+    {
         fd = rmtopen(
             file,
             libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
@@ -1229,12 +1740,24 @@ fn buf_all_zeros(buf: &[u8], size: usize) -> bool {
     buf.iter().take(size).all(|&x| x == 0)
 }
 
+thread_local! {
+    // copy-pass 的 --jobs 会在多个线程中写文件，尚未执行的跳过长度按线程保存
+    static DELAYED_SEEK_COUNT: Cell<i64> = const { Cell::new(0) };
+}
+
+// 在输出中跳过 COUNT 个零字节，并尽量释放这一段原来占用的块
+fn sparse_skip(fildes: &mut File, count: i64) -> bool {
+    if let Ok(pos) = fildes.stream_position() {
+        punch_hole(fildes, pos, count as u64);
+    }
+    fildes.seek(SeekFrom::Current(count)).is_ok()
+}
+
 fn sparse_write(fildes: &mut File, buf: &[u8], nbytes: usize, flush: bool) -> usize {
     let mut nwritten = 0;
     let mut start_ptr = 0;
-    static mut DELAYED_SEEK_COUNT: i64 = 0;
     let mut seek_count: i64 = 0;
-    let mut state = if unsafe { DELAYED_SEEK_COUNT } != 0 {
+    let mut state = if DELAYED_SEEK_COUNT.get() != 0 {
         State::InZeros
     } else {
         State::Begin
@@ -1268,11 +1791,11 @@ fn sparse_write(fildes: &mut File, buf: &[u8], nbytes: usize, flush: bool) -> us
             }
             state = State::InZeros;
         } else {
-            seek_count += unsafe { DELAYED_SEEK_COUNT };
-            if fildes.seek(SeekFrom::Current(seek_count)).is_err() {
+            seek_count += DELAYED_SEEK_COUNT.get();
+            if !sparse_skip(fildes, seek_count) {
                 return 0; // 发生错误，返回 0
             }
-            unsafe { DELAYED_SEEK_COUNT = 0 };
+            DELAYED_SEEK_COUNT.set(0);
             seek_count = 0;
             state = State::NotInZeros;
             start_ptr = current_pos;
@@ -1281,11 +1804,11 @@ fn sparse_write(fildes: &mut File, buf: &[u8], nbytes: usize, flush: bool) -> us
     }
 
     if let State::NotInZeros = state {
-        seek_count += unsafe { DELAYED_SEEK_COUNT };
-        if seek_count != 0 && fildes.seek(SeekFrom::Current(seek_count)).is_err() {
+        seek_count += DELAYED_SEEK_COUNT.get();
+        if seek_count != 0 && !sparse_skip(fildes, seek_count) {
             return 0; // 发生错误，返回 0
         }
-        unsafe { DELAYED_SEEK_COUNT = 0 };
+        DELAYED_SEEK_COUNT.set(0);
         seek_count = 0;
         if fildes.write_all(&buf[start_ptr..current_pos]).is_err() {
             return 0; // 发生错误，返回 0
@@ -1293,19 +1816,28 @@ fn sparse_write(fildes: &mut File, buf: &[u8], nbytes: usize, flush: bool) -> us
         nwritten += current_pos - start_ptr;
     }
 
-    unsafe { DELAYED_SEEK_COUNT += seek_count };
+    DELAYED_SEEK_COUNT.set(DELAYED_SEEK_COUNT.get() + seek_count);
 
-    if flush && unsafe { DELAYED_SEEK_COUNT } != 0 {
-        if fildes
-            .seek(SeekFrom::Current(unsafe { DELAYED_SEEK_COUNT } - 1))
-            .is_err()
-        {
-            return 0; // 发生错误，返回 0
-        }
-        if fildes.write_all(&[0]).is_err() {
-            return 0; // 发生错误，返回 0
+    if flush && DELAYED_SEEK_COUNT.get() != 0 {
+        // 文件以零结尾时直接设置文件长度，不能设置时再写入最后一个字节
+        let delayed = DELAYED_SEEK_COUNT.get();
+        let extended = fildes.stream_position().map_or(false, |pos| {
+            punch_hole(fildes, pos, delayed as u64);
+            fildes.set_len(pos + delayed as u64).is_ok()
+        });
+        if extended {
+            if fildes.seek(SeekFrom::Current(delayed)).is_err() {
+                return 0; // 发生错误，返回 0
+            }
+        } else {
+            if fildes.seek(SeekFrom::Current(delayed - 1)).is_err() {
+                return 0; // 发生错误，返回 0
+            }
+            if fildes.write_all(&[0]).is_err() {
+                return 0; // 发生错误，返回 0
+            }
         }
-        unsafe { DELAYED_SEEK_COUNT = 0 };
+        DELAYED_SEEK_COUNT.set(0);
     }
 
     nwritten + seek_count as usize
@@ -1352,23 +1884,33 @@ pub fn stat_to_cpio(st: &mut Metadata, hdr: &mut CpioFileStat) {
 
     if st.file_type().is_block_device() || st.file_type().is_char_device() {
         hdr.c_rdev_maj = major(st.rdev()) as i32;
-        hdr.c_rdev_min = minor(st.rdev()) as u32;
+        hdr.c_rdev_min = minor(st.rdev());
     } else {
         hdr.c_rdev_maj = 0;
         hdr.c_rdev_min = 0;
     }
 
-    hdr.c_mtime = st
-        .modified()
-        .map(|t| {
-            t.duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs() as i64
-        })
-        .unwrap_or(0);
+    // 1970 年以前的时间是负数，newcx 格式中按 64 位补码保存
+    hdr.c_mtime = st.mtime();
     hdr.c_filesize = st.len() as i64;
     hdr.c_chksum = 0;
     hdr.c_tar_linkname = None;
+    hdr.c_sparse = None;
+}
+
+// copy-out 时按 --mode、--mtime 改写成员的权限和修改时间。在谓词检查之后调用，
+// 谓词看到的是文件本身的属性
+pub fn apply_member_overrides(hdr: &mut CpioFileStat) {
+    let changes = get_mode_changes();
+    let file_type = hdr.c_mode & CP_IFMT;
+    if !changes.is_empty() && file_type != CP_IFLNK {
+        hdr.c_mode = file_type | mode_adjust(hdr.c_mode, file_type == CP_IFDIR, &changes);
+    }
+    if let Some(mtime) = get_mtime_override() {
+        if !get_clamp_mtime_flag() || hdr.c_mtime > mtime {
+            hdr.c_mtime = mtime;
+        }
+    }
 }
 
 fn fchown_or_chown(
@@ -1386,24 +1928,18 @@ fn fchown_or_chown(
             Err(std::io::Error::last_os_error())
         }
     } else {
-        match name.to_str() {
-            Some(name_str) => match std::ffi::CString::new(name_str) {
-                Ok(name_cstr) => {
-                    let result = unsafe { chown(name_cstr.as_ptr(), uid, gid) };
-                    if result == 0 {
-                        Ok(())
-                    } else {
-                        Err(std::io::Error::last_os_error())
-                    }
+        match std::ffi::CString::new(name.as_os_str().as_bytes()) {
+            Ok(name_cstr) => {
+                let result = unsafe { chown(name_cstr.as_ptr(), uid, gid) };
+                if result == 0 {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::last_os_error())
                 }
-                Err(_) => Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "Invalid path string",
-                )),
-            },
-            None => Err(std::io::Error::new(
+            }
+            Err(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
-                "Invalid path encoding",
+                "Invalid path string",
             )),
         }
     }
@@ -1419,24 +1955,18 @@ fn fchmod_or_chmod(file: Option<&File>, name: &Path, mode: mode_t) -> std::io::R
             Err(std::io::Error::last_os_error())
         }
     } else {
-        match name.to_str() {
-            Some(name_str) => match std::ffi::CString::new(name_str) {
-                Ok(name_cstr) => {
-                    let result = unsafe { libc::chmod(name_cstr.as_ptr(), mode) };
-                    if result == 0 {
-                        Ok(())
-                    } else {
-                        Err(std::io::Error::last_os_error())
-                    }
+        match std::ffi::CString::new(name.as_os_str().as_bytes()) {
+            Ok(name_cstr) => {
+                let result = unsafe { libc::chmod(name_cstr.as_ptr(), mode) };
+                if result == 0 {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::last_os_error())
                 }
-                Err(_) => Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "Invalid path string",
-                )),
-            },
-            None => Err(std::io::Error::new(
+            }
+            Err(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
-                "Invalid path encoding",
+                "Invalid path string",
             )),
         }
     }
@@ -1455,9 +1985,32 @@ pub fn set_perms(file: Option<&File>, header: &mut CpioFileStat) {
         }
     };
 
-    if !get_no_chown_flag() {
-        let uid = cpio_uid(header.c_uid);
-        let gid = cpio_gid(header.c_gid);
+    let mut mode = header.c_mode;
+    if get_rootless_flag() {
+        // 不改变属主，把属主和权限记录在扩展属性中，文件本身保持属主可读写
+        let uid = cpio_uid(map_uid(header.c_uid));
+        let gid = cpio_gid(map_gid(header.c_gid));
+        let value = format_override_stat(uid, gid, header);
+        if let Err(e) = set_override_stat(file, &safe_path, &value) {
+            error(
+                0,
+                e.raw_os_error().unwrap_or(0),
+                format_args!(
+                    "{}: cannot set {}",
+                    quote_name(&c_name),
+                    OVERRIDE_STAT_XATTR
+                ),
+            );
+            transaction_fail();
+        }
+        mode |= if header.c_mode & CP_IFMT == CP_IFDIR {
+            0o700
+        } else {
+            0o600
+        };
+    } else if !get_no_chown_flag() {
+        let uid = cpio_uid(map_uid(header.c_uid));
+        let gid = cpio_gid(map_gid(header.c_gid));
 
         match fchown_or_chown(file, &safe_path, uid, gid) {
             Ok(_) => (),
@@ -1469,15 +2022,17 @@ pub fn set_perms(file: Option<&File>, header: &mut CpioFileStat) {
                         // 这些错误对于符号链接来说是可以忽略的
                     }
                     _ => {
-                        chown_uid_error_details(&c_name, uid, gid);
+                        chown_uid_error_details(&quote_name(&c_name), uid, gid);
+                        transaction_fail();
                     }
                 }
             }
         }
     }
 
-    if (fchmod_or_chmod(file, &safe_path, header.c_mode)).is_err() {
-        chown_mode_error_details(&c_name, header.c_mode);
+    if (fchmod_or_chmod(file, &safe_path, mode)).is_err() {
+        chown_mode_error_details(&quote_name(&c_name), mode);
+        transaction_fail();
     }
 
     if get_retain_time_flag() {
@@ -1485,16 +2040,22 @@ pub fn set_perms(file: Option<&File>, header: &mut CpioFileStat) {
     }
 }
 
-pub fn set_file_times(file: Option<&File>, name: &str, atime: time_t, mtime: time_t, atflag: i32) {
+pub fn set_file_times(
+    file: Option<&File>,
+    name: &OsStr,
+    atime: time_t,
+    mtime: time_t,
+    atflag: i32,
+) {
     let mut ts: [timespec; 2] = unsafe { std::mem::zeroed() };
 
     ts[0].tv_sec = atime;
     ts[1].tv_sec = mtime;
 
-    match fdutimensat(file, AT_FDCWD, Some(name), &ts, atflag) {
+    match fdutimensat(file, AT_FDCWD, Some(Path::new(name)), &ts, atflag) {
         Ok(_) => (),
         Err(_e) => {
-            utime_error(name);
+            utime_error(&quote_name(name));
         }
     }
 }
@@ -1507,7 +2068,7 @@ pub fn set_file_times(file: Option<&File>, name: &str, atime: time_t, mtime: tim
 //     }
 // }
 
-pub fn cpio_set_c_name(file_hdr: &mut CpioFileStat, name: &str) {
+pub fn cpio_set_c_name(file_hdr: &mut CpioFileStat, name: impl AsRef<OsStr>) {
     file_hdr.set_c_name(name);
     //    file_hdr.c_namesize = name.len() + 1;
 
@@ -1517,7 +2078,7 @@ pub fn cpio_set_c_name(file_hdr: &mut CpioFileStat, name: &str) {
     // file_hdr.c_name = name.to_string();
 }
 pub fn cpio_safer_name_suffix(
-    name: &mut String,
+    name: &mut OsString,
     link_target: bool,
     absolute_names: bool,
     strip_leading_dots: bool,
@@ -1528,9 +2089,12 @@ pub fn cpio_safer_name_suffix(
 
     if strip_leading_dots && adjusted_p != "./" {
         // strip leading `./' from the filename.
-        while adjusted_p.starts_with("./") {
-            adjusted_p = adjusted_p[2..].trim_start_matches('/').to_string();
+        let mut bytes = adjusted_p.as_bytes();
+        while let Some(rest) = bytes.strip_prefix(b"./") {
+            let slashes = rest.iter().take_while(|&&b| b == b'/').count();
+            bytes = &rest[slashes..];
         }
+        adjusted_p = OsString::from_vec(bytes.to_vec());
     }
 
     if adjusted_p != *name {
@@ -1542,7 +2106,65 @@ pub fn cpio_safer_name_suffix(
     }
 }
 
-fn delay_cpio_set_stat(file_stat: &CpioFileStat, invert_permissions: mode_t) {
+// 去掉名字开头的 COUNT 个部分（--strip-components），开头和相邻的多个 '/'
+// 不算作部分。剩下的部分为空时返回 None
+pub fn strip_name_components(name: &OsStr, count: usize) -> Option<OsString> {
+    let skip_slashes = |rest: &[u8]| rest.iter().take_while(|&&b| b == b'/').count();
+    let mut rest = name.as_bytes();
+    for _ in 0..count {
+        rest = &rest[skip_slashes(rest)..];
+        let end = rest.iter().position(|&b| b == b'/')?;
+        rest = &rest[end..];
+    }
+    rest = &rest[skip_slashes(rest)..];
+    if rest.is_empty() {
+        return None;
+    }
+    Some(OsString::from_vec(rest.to_vec()))
+}
+
+// 按 --strip-components 和 --prefix 调整成员名，LINK_TARGET 表示调整的是硬链接
+// 的目标。去掉开头的部分后名字为空时给出警告并返回 None，这个成员被跳过
+pub fn adjust_member_path(name: &OsStr, link_target: bool) -> Option<OsString> {
+    let count = get_strip_components();
+    let prefix = get_name_prefix();
+    if count == 0 && prefix.is_none() {
+        return Some(name.to_os_string());
+    }
+    let mut path = name.to_os_string();
+    if count > 0 {
+        let Some(stripped) = strip_name_components(name, count) else {
+            warn_msg(format_args!(
+                "{}: {} is empty after --strip-components={}, skipped",
+                quote_name(name),
+                if link_target {
+                    "hard link target"
+                } else {
+                    "member name"
+                },
+                count
+            ));
+            return None;
+        };
+        path = stripped;
+    }
+    if let Some(prefix) = prefix {
+        let mut joined = prefix.into_vec();
+        while joined.last() == Some(&b'/') {
+            joined.pop();
+        }
+        joined.push(b'/');
+        joined.extend_from_slice(path.as_bytes());
+        path = OsString::from_vec(joined);
+    }
+    Some(path)
+}
+
+pub fn delay_cpio_set_stat(file_stat: &CpioFileStat, invert_permissions: mode_t) {
+    journal_record(&JournalRecord::Dir {
+        invert: invert_permissions,
+        stat: file_stat.clone(),
+    });
     if let Ok(mut head) = DELAYED_SET_STAT_HEAD.lock() {
         let new_node = Arc::new(Mutex::new(DelayedSetStat {
             stat: file_stat.clone(),
@@ -1554,7 +2176,7 @@ fn delay_cpio_set_stat(file_stat: &CpioFileStat, invert_permissions: mode_t) {
     }
 }
 
-fn delay_set_stat(file_name: &str, st: &mut Metadata, invert_permissions: u32) {
+fn delay_set_stat(file_name: &OsStr, st: &mut Metadata, invert_permissions: u32) {
     let mut fs = CpioFileStat::new();
 
     stat_to_cpio(st, &mut fs);
@@ -1600,7 +2222,7 @@ fn repair_inter_delayed_set_stat(dir_stat_info: &mut Metadata) -> i32 {
                 }
             }
             Err(_) => {
-                stat_error(&borrowed_node.stat.get_c_name());
+                stat_error(&quote_name(&borrowed_node.stat.get_c_name()));
                 return -1;
             }
         }
@@ -1611,7 +2233,7 @@ fn repair_inter_delayed_set_stat(dir_stat_info: &mut Metadata) -> i32 {
     1
 }
 
-fn repair_delayed_set_stat(file_hdr: &mut CpioFileStat) -> i32 {
+pub fn repair_delayed_set_stat(file_hdr: &mut CpioFileStat) -> i32 {
     let head = match DELAYED_SET_STAT_HEAD.lock() {
         Ok(guard) => guard,
         Err(_) => return -1,
@@ -1624,6 +2246,10 @@ fn repair_delayed_set_stat(file_hdr: &mut CpioFileStat) -> i32 {
             Err(_) => return -1,
         };
         if file_hdr.get_c_name() == borrowed_node.stat.get_c_name() {
+            journal_record(&JournalRecord::Repair {
+                mode: file_hdr.c_mode,
+                name: file_hdr.get_c_name(),
+            });
             borrowed_node.invert_permissions = 0;
             borrowed_node.stat.c_mode = file_hdr.c_mode; // Copy c_mode
                                                          // ... Copy other fields except c_name ...
@@ -1654,7 +2280,7 @@ pub fn apply_delayed_set_stat() {
         *head = borrowed_node.next.clone();
     }
 }
-fn make_path(argpath: &str, owner: i32, group: i32, verbose_fmt_string: Option<&str>) -> i32 {
+fn make_path(argpath: &OsStr, owner: i32, group: i32, verbose_fmt_string: Option<&str>) -> i32 {
     // 验证和清理路径
     let safe_path = match validate_and_sanitize_path(argpath) {
         Ok(path) => path,
@@ -1664,19 +2290,24 @@ fn make_path(argpath: &str, owner: i32, group: i32, verbose_fmt_string: Option<&
                 0,
                 format_args!(
                     "cannot make directory `{}`: invalid or unsafe path",
-                    argpath
+                    quote_name(argpath)
                 ),
             );
             return 1;
         }
     };
 
+    let missing = missing_directories(&safe_path);
+
     // 如果目录已存在也会返回 Ok
     match fs::create_dir_all(&safe_path) {
         Ok(_) => {
+            for dir in missing.iter() {
+                transaction_record_dir(dir);
+            }
             // 如果需要显示创建信息
             if let Some(fmt) = verbose_fmt_string {
-                error(0, 0, format_args!("{}{}", fmt, argpath));
+                error(0, 0, format_args!("{}{}", fmt, quote_name(argpath)));
             }
 
             // 设置所有者和权限
@@ -1692,7 +2323,7 @@ fn make_path(argpath: &str, owner: i32, group: i32, verbose_fmt_string: Option<&
             error(
                 0,
                 e.raw_os_error().unwrap_or(0),
-                format_args!("cannot make directory `{}`", argpath),
+                format_args!("cannot make directory `{}`", quote_name(argpath)),
             );
             1
         }
@@ -1710,6 +2341,7 @@ fn cpio_mkdir(file_hdr: &mut CpioFileStat, setstat_delayed: &mut bool) -> std::i
         let _new_mode = mode | S_IWUSR;
         match fs::create_dir(&safe_path) {
             Ok(_) => {
+                transaction_record_dir(&c_name);
                 delay_cpio_set_stat(file_hdr, 0);
                 *setstat_delayed = true;
                 Ok(())
@@ -1719,6 +2351,7 @@ fn cpio_mkdir(file_hdr: &mut CpioFileStat, setstat_delayed: &mut bool) -> std::i
     } else {
         match fs::create_dir(&safe_path) {
             Ok(_) => {
+                transaction_record_dir(&c_name);
                 *setstat_delayed = false;
                 Ok(())
             }
@@ -1731,11 +2364,11 @@ pub fn cpio_create_dir(file_hdr: &mut CpioFileStat, existing_dir: bool) -> i32 {
     if get_to_stdout_option() {
         return 0;
     }
-    let mut c_name = file_hdr.get_c_name();
-
-    strip_trailing_slashes(&mut c_name);
-
-    file_hdr.set_c_name(&c_name);
+    // 去掉末尾的斜杠，但保留根目录 "/"
+    let c_name = file_hdr.get_c_name();
+    let bytes = c_name.as_bytes();
+    let len = bytes.iter().rposition(|&b| b != b'/').map_or(1, |i| i + 1);
+    file_hdr.set_c_name(OsStr::from_bytes(&bytes[..len.min(bytes.len())]));
 
     if file_hdr.c_name[0] == b'.' && file_hdr.c_name[1] == b'\0' {
         return 0;
@@ -1756,7 +2389,8 @@ pub fn cpio_create_dir(file_hdr: &mut CpioFileStat, existing_dir: bool) -> i32 {
 
     if res.is_err() {
         if std::io::Error::last_os_error().raw_os_error() != Some(EEXIST) {
-            mkdir_error(&c_name);
+            mkdir_error(&quote_name(&c_name));
+            transaction_fail();
             return -1;
         }
 
@@ -1766,20 +2400,29 @@ pub fn cpio_create_dir(file_hdr: &mut CpioFileStat, existing_dir: bool) -> i32 {
                     error(
                         0,
                         0,
-                        format_args!("{:?} is not a directory", quotearg_colon(&c_name)),
+                        format_args!(
+                            "{:?} is not a directory",
+                            quotearg_colon(&quote_name(&c_name))
+                        ),
                     );
+                    transaction_fail();
                     return -1;
                 }
             }
             Err(_) => {
-                stat_error(&c_name);
+                stat_error(&quote_name(&c_name));
                 return -1;
             }
         }
     }
 
     if !setstat_delayed && repair_delayed_set_stat(file_hdr) != 0 {
-        set_perms(None, file_hdr);
+        // 事务模式下目录属性在提交后才设置
+        if get_transactional_flag() {
+            delay_cpio_set_stat(file_hdr, 0);
+        } else {
+            set_perms(None, file_hdr);
+        }
     }
 
     0
@@ -1797,7 +2440,7 @@ pub fn change_dir() {
                     None
                 };
 
-                if make_path(dir, -1, -1, warn_msg) != 0 {
+                if make_path(OsStr::new(dir), -1, -1, warn_msg) != 0 {
                     // 创建失败，退出（这里返回错误）
                     return;
                 }
@@ -1818,3 +2461,230 @@ pub fn arf_stores_inode_p(arf: ArchiveFormat) -> bool {
         _ => true,
     }
 }
+
+// 转义日志、索引等文本文件中的字段，使其不含制表符和换行。
+// 不是合法 UTF-8 的字节写成 \xHH，保证文件名可以原样读回
+pub fn escape_field(s: &OsStr) -> String {
+    let mut out = String::with_capacity(s.len());
+    for chunk in s.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                _ => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    out
+}
+
+pub fn unescape_field(s: &str) -> Option<OsString> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next()? {
+            b'\\' => out.push(b'\\'),
+            b't' => out.push(b'\t'),
+            b'n' => out.push(b'\n'),
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+    Some(OsString::from_vec(out))
+}
+
+// 给出警告后继续。error() 写到标准输出，而 copy-out 时标准输出是归档，
+// 所以警告一律写到标准错误
+pub fn warn_msg(args: fmt::Arguments) {
+    eprintln!("{}: {}", get_program_name().unwrap_or_default(), args);
+}
+
+// 在消息中显示文件名：合法 UTF-8 中的可打印字符原样输出，控制字符和
+// 不是合法 UTF-8 的字节写成 \ooo
+pub fn quote_name(name: &OsStr) -> String {
+    let mut out = String::with_capacity(name.len());
+    for chunk in name.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() {
+                let mut buf = [0u8; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("\\{:03o}", b));
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\{:03o}", b));
+        }
+    }
+    out
+}
+
+// 解析时间参数：已经存在的文件取它的修改时间，否则按日期解析。
+// 日期可以是 @秒数、RFC 3339 格式，或者本地时间的 YYYY-MM-DD[ HH:MM[:SS]]
+// （日期与时间之间也可以用 'T' 分隔）
+pub fn parse_time_reference(text: &str) -> Option<i64> {
+    if let Ok(st) = fs::metadata(text) {
+        return Some(st.mtime());
+    }
+    parse_date(text)
+}
+
+pub fn parse_date(text: &str) -> Option<i64> {
+    use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+    let text = text.trim();
+    if let Some(seconds) = text.strip_prefix('@') {
+        return seconds.parse().ok();
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.timestamp());
+    }
+    let local = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })?;
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Mutex;
+
+    fn name(s: &str) -> Option<OsString> {
+        Some(OsString::from(s))
+    }
+
+    #[test]
+    fn test_strip_name_components() {
+        let strip = |s: &str, count| strip_name_components(OsStr::new(s), count);
+        assert_eq!(strip("a/b/c", 1), name("b/c"));
+        assert_eq!(strip("a/b/c", 2), name("c"));
+        assert_eq!(strip("a/b/c", 3), None);
+        assert_eq!(strip("a/b/c", 10), None);
+        assert_eq!(strip("a/b/", 2), None);
+        assert_eq!(strip("/a/b", 1), name("b"));
+        assert_eq!(strip("//a//b", 1), name("b"));
+        assert_eq!(strip("./a/b", 1), name("a/b"));
+        assert_eq!(strip("./a/b", 2), name("b"));
+        assert_eq!(strip(".", 1), None);
+    }
+
+    #[test]
+    fn test_adjust_member_path() {
+        let _ = APPARGS.set(Mutex::new(AppArgs::new()));
+        let adjust = |s: &str, link_target| adjust_member_path(OsStr::new(s), link_target);
+
+        set_strip_components(0);
+        set_name_prefix(None);
+        assert_eq!(adjust("/a/b", false), name("/a/b"));
+
+        set_strip_components(1);
+        assert_eq!(adjust("a/b/c", false), name("b/c"));
+        assert_eq!(adjust("/a/b", false), name("b"));
+        assert_eq!(adjust("./a/b", false), name("a/b"));
+        assert_eq!(adjust("a", false), None);
+        // 硬链接的目标去掉开头的部分后为空
+        assert_eq!(adjust("a", true), None);
+        assert_eq!(adjust("a/b", true), name("b"));
+
+        set_name_prefix(Some(OsString::from("pre//")));
+        assert_eq!(adjust("a/b/c", false), name("pre/b/c"));
+        assert_eq!(adjust("a", false), None);
+
+        set_strip_components(0);
+        assert_eq!(adjust("a/b", false), name("pre/a/b"));
+
+        set_name_prefix(None);
+    }
+
+    fn temp_file(name: &str) -> (std::path::PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("utcpio-{}-{}", name, std::process::id()));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    // 用 copy_files_tape_to_disk 把 INPUT 中的 LEN 个字节复制到新文件，返回文件的内容
+    fn copy_to_disk(input: &mut File, len: usize, name: &str) -> Vec<u8> {
+        let _ = APPARGS.set(Mutex::new(AppArgs::new()));
+        let (path, mut out) = temp_file(name);
+        let input_tape = Mutex::new(TapeInput::new(512));
+        let output_tape = Mutex::new(TapeOutput::new(DISK_IO_BLOCK_SIZE));
+        let mut input_tape = input_tape.lock().unwrap();
+        let mut output_tape = output_tape.lock().unwrap();
+        copy_files_tape_to_disk(
+            &mut input_tape,
+            &mut output_tape,
+            input,
+            &mut out,
+            len as u64,
+        );
+        disk_empty_output_buffer(&mut output_tape, &mut out, true);
+        assert_eq!(input_tape.input_bytes, len);
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_kernel_copy_fallback() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+        // 普通文件之间在内核中复制
+        let (path, mut input) = temp_file("kcopy-in");
+        input.write_all(&data).unwrap();
+        input.seek(SeekFrom::Start(0)).unwrap();
+        assert_eq!(copy_to_disk(&mut input, data.len(), "kcopy-file"), data);
+        input.seek(SeekFrom::Start(0)).unwrap();
+        let (out_path, out) = temp_file("kcopy-direct");
+        assert_eq!(kernel_copy(&input, &out, data.len()), data.len());
+        fs::remove_file(&out_path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // 套接字不支持 copy_file_range、splice 和 sendfile，改为经过缓冲区复制
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        let sender = {
+            let data = data.clone();
+            std::thread::spawn(move || writer.write_all(&data).unwrap())
+        };
+        let mut input = File::from(OwnedFd::from(reader));
+        let (out_path, out) = temp_file("kcopy-socket");
+        assert_eq!(kernel_copy(&input, &out, data.len()), 0);
+        fs::remove_file(&out_path).unwrap();
+        assert_eq!(copy_to_disk(&mut input, data.len(), "kcopy-fallback"), data);
+        sender.join().unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+// 集成测试共用的辅助函数，每个测试文件用 `mod common;` 引入，
+// 不是每个文件都用到全部函数
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+// 在临时目录中建立测试 NAME 的空工作目录
+pub fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("utcpio-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// 在 DIR 中用参数 ARGS 运行 utcpio，STDIN 是它的标准输入
+pub fn utcpio(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_utcpio"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute command");
+    let mut input = child.stdin.take().unwrap();
+    let data = stdin.to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = input.write_all(&data);
+    });
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    output
+}
+
+// 每行一个文件名的列表
+pub fn name_list(names: &[&str]) -> Vec<u8> {
+    (names.join("\n") + "\n").into_bytes()
+}
+
+// 在 DIR 中按 FORMAT 格式归档 NAMES，返回归档的内容
+pub fn archive(dir: &Path, format: &str, names: &[&str]) -> Vec<u8> {
+    let output = utcpio(dir, &["-o", "-H", format], &name_list(names));
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+// DIR 下的普通文件和符号链接，按名字排序的 (文件名, 链接数, 内容)，
+// 符号链接的内容是它的目标
+pub fn tree(dir: &Path) -> Vec<(String, u64, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        for entry in fs::read_dir(&path).unwrap() {
+            let path = entry.unwrap().path();
+            let stat = fs::symlink_metadata(&path).unwrap();
+            let name = path
+                .strip_prefix(dir)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            if stat.is_dir() {
+                stack.push(path);
+            } else if stat.file_type().is_symlink() {
+                let target = fs::read_link(&path).unwrap();
+                files.push((name, stat.nlink(), target.as_os_str().as_bytes().to_vec()));
+            } else {
+                files.push((name, stat.nlink(), fs::read(&path).unwrap()));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+// DIR 下的文件名，按名字排序
+pub fn tree_names(dir: &Path) -> Vec<String> {
+    tree(dir).into_iter().map(|(name, _, _)| name).collect()
+}
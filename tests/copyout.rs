@@ -0,0 +1,98 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use crate::common::*;
+
+    // 源目录：几个小文件、一个超过预读上限的大文件和一对硬链接
+    fn source(dir: &Path) -> (PathBuf, Vec<&'static str>) {
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("d")).unwrap();
+        for i in 1..=20 {
+            let data: Vec<u8> = (0..i * 1000u32).map(|j| (j * i % 251) as u8).collect();
+            fs::write(src.join(format!("d/f{:02}", i)), data).unwrap();
+        }
+        let big: Vec<u8> = (0..(3u32 << 20)).map(|j| (j % 253) as u8).collect();
+        fs::write(src.join("d/big"), big).unwrap();
+        fs::hard_link(src.join("d/f01"), src.join("d/l01")).unwrap();
+        let names = vec![
+            "d", "d/f01", "d/f02", "d/f03", "d/big", "d/l01", "d/f04", "d/f05", "d/f06", "d/f07",
+            "d/f08", "d/f09", "d/f10", "d/f11", "d/f12", "d/f13", "d/f14", "d/f15", "d/f16",
+            "d/f17", "d/f18", "d/f19", "d/f20",
+        ];
+        (src, names)
+    }
+
+    // 使用 --jobs 生成的归档与顺序生成的逐字节相同
+    #[test]
+    fn test_jobs_same_archive() {
+        let dir = work_dir("copyout-jobs");
+        let (src, names) = source(&dir);
+        let mut input = name_list(&names);
+        input.extend_from_slice(b"missing\n");
+
+        for format in ["newc", "crc"] {
+            let output = utcpio(&src, &["-o", "-H", format], &input);
+            let expected = output.stdout;
+            assert!(!expected.is_empty());
+            for jobs in ["--jobs=1", "--jobs=4"] {
+                let output = utcpio(&src, &["-o", "-H", format, jobs], &input);
+                assert_eq!(output.status.code(), Some(2), "{} {}", format, jobs);
+                assert!(output.stdout == expected, "{} {}", format, jobs);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // --compress-program 把归档交给另一个进程，收到的归档与不压缩时相同
+    #[test]
+    fn test_compress_program() {
+        let dir = work_dir("copyout-compress");
+        let (src, names) = source(&dir);
+        let expected = archive(&src, "newc", &names);
+
+        let args = ["-o", "-H", "newc", "--jobs=4", "--compress-program=cat"];
+        let output = utcpio(&src, &args, &name_list(&names));
+        assert!(output.status.success());
+        assert!(output.stdout == expected);
+
+        // 压缩程序写到 -F 指定的文件，用 gzip 解压后相同
+        let file = dir.join("a.cpio.gz");
+        let file_str = file.to_str().unwrap();
+        let args = [
+            "-o",
+            "-H",
+            "newc",
+            "-F",
+            file_str,
+            "--compress-program=gzip",
+        ];
+        if Command::new("gzip").arg("--version").output().is_ok() {
+            let output = utcpio(&src, &args, &name_list(&names));
+            assert!(output.status.success());
+            let output = Command::new("gzip").args(["-dc", file_str]).output();
+            assert!(output.unwrap().stdout == expected);
+        }
+
+        // 压缩程序失败时以失败状态退出
+        let output = utcpio(
+            &src,
+            &["-o", "-H", "newc", "--compress-program=false"],
+            &name_list(&names),
+        );
+        assert_eq!(output.status.code(), Some(2));
+
+        // 压缩后的归档不能续写
+        let output = utcpio(
+            &src,
+            &["-o", "-A", "-F", file_str, "--compress-program=gzip"],
+            &name_list(&names),
+        );
+        assert!(!output.status.success());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
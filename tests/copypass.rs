@@ -0,0 +1,129 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::{FileExt, MetadataExt};
+    use std::path::{Path, PathBuf};
+    use std::process::Output;
+
+    use crate::common::*;
+
+    // 在 DIR 中运行 copy-pass，标准输入是 NAMES
+    fn copy_pass(dir: &Path, args: &[&str], names: &[&str]) -> Output {
+        let args = [&["-p"], args].concat();
+        utcpio(dir, &args, &name_list(names))
+    }
+
+    // --link-dest 下没有改变的文件直接硬链接，改变了的文件照常复制
+    #[test]
+    fn test_link_dest_links_unchanged_files() {
+        let dir = work_dir("linkdest");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("same"), "same\n").unwrap();
+        fs::write(src.join("changed"), "old\n").unwrap();
+
+        let prev = dir.join("prev");
+        let output = copy_pass(&src, &["-dm", prev.to_str().unwrap()], &["same", "changed"]);
+        assert!(output.status.success());
+        fs::write(src.join("changed"), "new contents\n").unwrap();
+        fs::write(src.join("added"), "added\n").unwrap();
+
+        let next = dir.join("next");
+        let link_dest = format!("--link-dest={}", prev.display());
+        let output = copy_pass(
+            &src,
+            &["-dm", &link_dest, next.to_str().unwrap()],
+            &["same", "changed", "added"],
+        );
+        assert!(output.status.success());
+
+        let ino = |path: PathBuf| fs::metadata(path).unwrap().ino();
+        assert_eq!(ino(next.join("same")), ino(prev.join("same")));
+        assert_ne!(ino(next.join("changed")), ino(prev.join("changed")));
+        assert_eq!(fs::read(next.join("changed")).unwrap(), b"new contents\n");
+        assert_eq!(fs::read(prev.join("changed")).unwrap(), b"old\n");
+        assert_eq!(fs::read(next.join("added")).unwrap(), b"added\n");
+        assert_eq!(fs::metadata(next.join("added")).unwrap().nlink(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 有洞的文件复制后洞的位置不变
+    #[test]
+    fn test_holes_preserved() {
+        let dir = work_dir("holes");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        let file = fs::File::create(src.join("sparse")).unwrap();
+        file.set_len(8 << 20).unwrap();
+        let data = vec![0x5au8; 8192];
+        file.write_all_at(&data, 0).unwrap();
+        file.write_all_at(&data, 4 << 20).unwrap();
+        drop(file);
+        let in_stat = fs::metadata(src.join("sparse")).unwrap();
+        if in_stat.blocks() * 512 >= in_stat.len() {
+            // 文件系统不支持洞
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+
+        let dest = dir.join("dest");
+        for args in [&["-d"][..], &["-d", "--jobs=2"][..]] {
+            let _ = fs::remove_dir_all(&dest);
+            let mut args = args.to_vec();
+            args.push(dest.to_str().unwrap());
+            let output = copy_pass(&src, &args, &["sparse"]);
+            assert!(output.status.success());
+
+            let copy = dest.join("sparse");
+            assert_eq!(
+                fs::read(&copy).unwrap(),
+                fs::read(src.join("sparse")).unwrap()
+            );
+            let out_stat = fs::metadata(&copy).unwrap();
+            assert_eq!(out_stat.len(), in_stat.len());
+            assert!(out_stat.blocks() <= in_stat.blocks() + 8, "{:?}", args);
+
+            let file = fs::File::open(&copy).unwrap();
+            let fd = file.as_raw_fd();
+            let seek = |offset: i64, whence| unsafe { libc::lseek(fd, offset, whence) };
+            assert_eq!(seek(0, libc::SEEK_HOLE), 8192);
+            assert_eq!(seek(8192, libc::SEEK_DATA), 4 << 20);
+            assert_eq!(seek(4 << 20, libc::SEEK_HOLE), (4 << 20) + 8192);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // --jobs 复制的结果与顺序复制相同
+    #[test]
+    fn test_jobs_same_as_sequential() {
+        let dir = work_dir("jobs");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("d")).unwrap();
+        let mut names = vec!["d".to_string()];
+        for i in 0..50 {
+            fs::write(src.join(format!("d/f{}", i)), format!("{}\n", i)).unwrap();
+            names.push(format!("d/f{}", i));
+        }
+        let big: Vec<u8> = (0..2_000_000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(src.join("big"), &big).unwrap();
+        fs::hard_link(src.join("d/f1"), src.join("link")).unwrap();
+        names.extend(["big".to_string(), "link".to_string(), "missing".to_string()]);
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        let seq = dir.join("seq");
+        let par = dir.join("par");
+        let output = copy_pass(&src, &["-d", seq.to_str().unwrap()], &names);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("missing"));
+        let output = copy_pass(&src, &["-d", "--jobs=4", par.to_str().unwrap()], &names);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("missing"));
+
+        let copied = tree(&par);
+        assert_eq!(copied.len(), 52);
+        assert_eq!(copied, tree(&seq));
+        assert_eq!(fs::metadata(par.join("link")).unwrap().nlink(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
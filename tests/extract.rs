@@ -0,0 +1,96 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::common::*;
+
+    // 归档在成员数据中间截断时，已有的文件保持不变，也不留下临时文件
+    #[test]
+    fn test_truncated_archive_keeps_old_file() {
+        let dir = work_dir("trunc");
+        let src = dir.join("src");
+        let dest = dir.join("dest");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        let new: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(src.join("f"), &new).unwrap();
+        let data = archive(&src, "newc", &["f"]);
+        fs::write(dest.join("f"), "old\n").unwrap();
+
+        let output = utcpio(&dest, &["-i", "-u"], &data[..data.len() / 2]);
+        assert!(!output.status.success());
+        assert_eq!(fs::read(dest.join("f")).unwrap(), b"old\n");
+        let names: Vec<_> = fs::read_dir(&dest)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["f"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // --transactional 下有成员校验和不对时，已有的文件都不改变，
+    // 不留下临时文件，退出状态为 2
+    #[test]
+    fn test_transactional_checksum_error_changes_nothing() {
+        let dir = work_dir("trans-crc");
+        let src = dir.join("src");
+        let dest = dir.join("dest");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(src.join("a"), "new a\n").unwrap();
+        fs::write(src.join("b"), "new b\n").unwrap();
+        let mut data = archive(&src, "crc", &["a", "b"]);
+        let pos = data.windows(5).position(|w| w == b"new b").unwrap();
+        data[pos] = b'N';
+        fs::write(dest.join("a"), "old a\n").unwrap();
+        fs::write(dest.join("b"), "old b\n").unwrap();
+
+        let output = utcpio(&dest, &["-i", "-u", "--transactional"], &data);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(
+            tree(&dest),
+            [
+                ("a".to_string(), 1, b"old a\n".to_vec()),
+                ("b".to_string(), 1, b"old b\n".to_vec()),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 从可以定位的归档文件和从管道中选择性提取的结果相同
+    #[test]
+    fn test_seekable_and_pipe_extract_same_members() {
+        let dir = work_dir("seek");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("d")).unwrap();
+        let big: Vec<u8> = (0..300_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(src.join("big1"), &big).unwrap();
+        fs::write(src.join("d/keep"), "keep\n").unwrap();
+        fs::write(src.join("big2"), &big[..100_001]).unwrap();
+        fs::write(src.join("d/keep2"), "keep2\n").unwrap();
+        fs::write(src.join("odd"), "odd").unwrap();
+        let data = archive(
+            &src,
+            "newc",
+            &["big1", "d", "d/keep", "big2", "d/keep2", "odd"],
+        );
+        let file = dir.join("a.cpio");
+        fs::write(&file, &data).unwrap();
+
+        let seek = dir.join("seek");
+        let pipe = dir.join("pipe");
+        fs::create_dir(&seek).unwrap();
+        fs::create_dir(&pipe).unwrap();
+        let file = file.to_str().unwrap();
+        let output = utcpio(&seek, &["-i", "-d", "-F", file, "d/*", "odd"], b"");
+        assert!(output.status.success());
+        let output = utcpio(&pipe, &["-i", "-d", "d/*", "odd"], &data);
+        assert!(output.status.success());
+
+        assert_eq!(tree_names(&seek), ["d/keep", "d/keep2", "odd"]);
+        assert_eq!(tree(&seek), tree(&pipe));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::common::*;
+
+    // 使用索引和顺序读取时选中的成员应该相同
+    #[test]
+    fn test_index_selects_same_members() {
+        let dir = work_dir("index");
+        let src = dir.join("src/rs");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("f1"), "one\n").unwrap();
+        fs::write(src.join("f2"), "two\n").unwrap();
+
+        // 归档中是绝对路径
+        let archive = dir.join("a.cpio");
+        let index = dir.join("a.idx");
+        let build_index = format!("--build-index={}", index.display());
+        let f1 = src.join("f1").display().to_string();
+        let f2 = src.join("f2").display().to_string();
+        let output = utcpio(
+            &dir,
+            &[
+                "-o",
+                "-H",
+                "newc",
+                "-F",
+                archive.to_str().unwrap(),
+                &build_index,
+            ],
+            &name_list(&[&f1, &f2]),
+        );
+        assert!(output.status.success());
+
+        let pattern = src.join("f2").to_string_lossy()[1..].to_string();
+        let use_index = format!("--index={}", index.display());
+        let mut results = Vec::new();
+        for (out, with_index) in [("seq", false), ("idx", true)] {
+            let out = dir.join(out);
+            fs::create_dir(&out).unwrap();
+            let mut args = vec!["-i", "-d", "--no-absolute-filenames", "-F"];
+            args.extend([archive.to_str().unwrap(), &pattern]);
+            if with_index {
+                args.push(&use_index);
+            }
+            let output = utcpio(&out, &args, b"");
+            assert!(output.status.success());
+            results.push(tree_names(&out));
+        }
+
+        assert_eq!(results[0], vec![pattern]);
+        assert_eq!(results[0], results[1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 索引不存在或与归档不符时给出警告，照常按顺序读取整个归档
+    #[test]
+    fn test_missing_or_stale_index_reads_whole_archive() {
+        let dir = work_dir("index-stale");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("f1"), "one\n").unwrap();
+        fs::write(src.join("f2"), "two\n").unwrap();
+
+        let archive = dir.join("a.cpio");
+        let archive = archive.to_str().unwrap();
+        let index = dir.join("a.idx");
+        let build_index = format!("--build-index={}", index.display());
+        let output = utcpio(
+            &src,
+            &["-o", "-H", "newc", "-F", archive, &build_index],
+            &name_list(&["f1", "f2"]),
+        );
+        assert!(output.status.success());
+
+        // 重新生成归档，旧的索引中的偏移不再正确
+        fs::write(src.join("f1"), "one, rewritten\n").unwrap();
+        fs::write(src.join("f2"), "two, rewritten\n").unwrap();
+        let output = utcpio(
+            &src,
+            &["-o", "-H", "newc", "-F", archive],
+            &name_list(&["f1", "f2"]),
+        );
+        assert!(output.status.success());
+
+        let missing = format!("--index={}", dir.join("missing.idx").display());
+        let stale = format!("--index={}", index.display());
+        for (out, use_index) in [("missing", &missing), ("stale", &stale)] {
+            let out = dir.join(out);
+            fs::create_dir(&out).unwrap();
+            let output = utcpio(&out, &["-i", "-F", archive, "f2", use_index], b"");
+            assert!(output.status.success(), "{}", use_index);
+            assert!(
+                String::from_utf8_lossy(&output.stdout).contains("reading the whole archive"),
+                "{}",
+                use_index
+            );
+            assert_eq!(
+                tree(&out),
+                [("f2".to_string(), 1, b"two, rewritten\n".to_vec())]
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
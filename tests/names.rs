@@ -0,0 +1,72 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+
+    use crate::common::*;
+
+    // 不是合法 UTF-8 的文件名（GBK 编码的“中文”）在归档、列出和提取时保持原样
+    #[test]
+    fn test_non_utf8_names_round_trip() {
+        let dir = work_dir("names");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        let name = b"g\xd6\xd0\xce\xc4";
+        fs::write(src.join(OsStr::from_bytes(name)), "data\n").unwrap();
+
+        for format in ["newc", "ustar"] {
+            let mut list = name.to_vec();
+            list.push(b'\n');
+            let output = utcpio(&src, &["-o", "-H", format], &list);
+            assert!(output.status.success(), "{}", format);
+            let archive = output.stdout;
+
+            let output = utcpio(&src, &["-t"], &archive);
+            assert!(output.status.success(), "{}", format);
+            assert_eq!(output.stdout, list, "{}", format);
+
+            let dest = dir.join(format);
+            fs::create_dir(&dest).unwrap();
+            let output = utcpio(&dest, &["-i"], &archive);
+            assert!(output.status.success(), "{}", format);
+            let names: Vec<_> = fs::read_dir(&dest)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            assert_eq!(names, [OsStr::from_bytes(name)], "{}", format);
+            assert_eq!(
+                fs::read(dest.join(OsStr::from_bytes(name))).unwrap(),
+                b"data\n"
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 不能转换字符集的名字被跳过，其他成员照常处理，退出状态为失败
+    #[test]
+    fn test_unconvertible_name_fails() {
+        let dir = work_dir("charset");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("plain"), "plain\n").unwrap();
+        fs::write(src.join("café"), "café\n").unwrap();
+        let names = name_list(&["plain", "café"]);
+
+        let output = utcpio(&src, &["-o", "-H", "newc", "--to-charset=ASCII"], &names);
+        assert_eq!(output.status.code(), Some(2));
+        let output = utcpio(&src, &["-t"], &output.stdout);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"plain\n");
+
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+        let data = archive(&src, "newc", &["plain", "café"]);
+        let output = utcpio(&dest, &["-i", "--to-charset=ASCII"], &data);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(tree_names(&dest), ["plain"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+    use std::thread::sleep;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::common::*;
+
+    // 在 DIR 中运行 utcpio，送入 INPUT 后不关闭标准输入，等到日志 JOURNAL 中
+    // 记录了 NAME 处理完后杀掉进程，模拟中途中断
+    fn interrupted(dir: &Path, args: &[&str], input: &[u8], journal: &Path, name: &str) {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_utcpio"))
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to execute command");
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(input).unwrap();
+
+        let done = format!("\t{}", name);
+        for _ in 0..1000 {
+            let text = fs::read_to_string(journal).unwrap_or_default();
+            if text
+                .lines()
+                .any(|line| line.starts_with("done\t") && line.ends_with(&done))
+            {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(fs::metadata(journal).is_ok());
+    }
+
+    // 源目录：只读的目录 d（提取时属性要延迟设置）、几个文件和一对硬链接
+    fn source(dir: &Path) -> (PathBuf, Vec<&'static str>) {
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("d")).unwrap();
+        for i in 1..=5 {
+            let data: Vec<u8> = (0..3000u32).map(|j| (j * i % 251) as u8).collect();
+            fs::write(src.join(format!("d/f{}", i)), data).unwrap();
+        }
+        fs::hard_link(src.join("d/f1"), src.join("d/l1")).unwrap();
+        fs::File::open(src.join("d"))
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(978307200))
+            .unwrap();
+        fs::set_permissions(src.join("d"), fs::Permissions::from_mode(0o555)).unwrap();
+        let names = vec!["d", "d/f1", "d/f2", "d/f3", "d/l1", "d/f4", "d/f5"];
+        (src, names)
+    }
+
+    fn cleanup(dir: &Path, dirs: &[PathBuf]) {
+        for d in dirs {
+            let _ = fs::set_permissions(d, fs::Permissions::from_mode(0o755));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // 中断后用 --resume 继续写出的归档与一次写完的相同，
+    // 中断前延迟的硬链接数据也照常写出
+    #[test]
+    fn test_resume_copy_out() {
+        let dir = work_dir("resume-out");
+        let (src, names) = source(&dir);
+        let full = archive(&src, "newc", &names);
+
+        let file = dir.join("a.cpio");
+        let journal = dir.join("a.journal");
+        let file_str = file.to_str().unwrap();
+        let resume = format!("--resume={}", journal.display());
+        let args = ["-o", "-H", "newc", "-F", file_str, &resume];
+        interrupted(&src, &args, &name_list(&names[..3]), &journal, "d/f2");
+        assert_ne!(fs::read(&file).unwrap(), full);
+
+        let output = utcpio(&src, &args, &name_list(&names));
+        assert!(output.status.success());
+        assert_eq!(fs::read(&file).unwrap(), full);
+        assert!(fs::metadata(&journal).is_err());
+        cleanup(&dir, &[src.join("d")]);
+    }
+
+    // 中断后用 --resume 继续提取，中断前延迟的硬链接和目录属性都会恢复
+    #[test]
+    fn test_resume_copy_in() {
+        let dir = work_dir("resume-in");
+        let (src, names) = source(&dir);
+        let data = archive(&src, "newc", &names);
+        // 在 d/l1 的头部之前中断，这时 d/f1 的硬链接还在等待数据
+        let cut = data.windows(5).position(|w| w == b"d/l1\0").unwrap() - 110;
+
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+        let journal = dir.join("in.journal");
+        let resume = format!("--resume={}", journal.display());
+        let args = ["-i", "-d", "-m", &resume];
+        interrupted(&dest, &args, &data[..cut], &journal, "d/f1");
+        assert!(fs::metadata(dest.join("d/l1")).is_err());
+
+        let output = utcpio(&dest, &args, &data);
+        assert!(output.status.success());
+        assert_eq!(tree(&dest), tree(&src));
+        assert_eq!(fs::metadata(dest.join("d/f1")).unwrap().nlink(), 2);
+        let (st, src_st) = (
+            fs::metadata(dest.join("d")).unwrap(),
+            fs::metadata(src.join("d")).unwrap(),
+        );
+        assert_eq!(st.mode(), src_st.mode());
+        assert_eq!(st.mtime(), src_st.mtime());
+        assert!(fs::metadata(&journal).is_err());
+        cleanup(&dir, &[src.join("d"), dest.join("d")]);
+    }
+}
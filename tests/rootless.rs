@@ -0,0 +1,97 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{symlink, MetadataExt};
+    use std::path::Path;
+
+    use crate::common::*;
+
+    // 设置 --rootless 使用的扩展属性，文件系统不支持时返回 false
+    fn set_override_stat(path: &Path, value: &str) -> bool {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = CString::new("user.containers.override_stat").unwrap();
+        let res = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+        res == 0
+    }
+
+    // 归档、用 --rootless 提取、再归档得到的归档与原来的相同
+    #[test]
+    fn test_rootless_round_trip() {
+        let dir = work_dir("rootless");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("d")).unwrap();
+        fs::write(src.join("d/f"), "data\n").unwrap();
+        fs::write(src.join("d/tty"), "").unwrap();
+        symlink("f", src.join("d/sl")).unwrap();
+        if !set_override_stat(&src.join("d/tty"), "0:5:0620:char-136-3") {
+            // 文件系统不支持 user.* 扩展属性
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        assert!(set_override_stat(&src.join("d"), "0:0:0755:dir"));
+
+        let names = ["d", "d/f", "d/tty", "d/sl"];
+        let args = ["-o", "-H", "newc", "--rootless", "--device-independent"];
+        let output = utcpio(&src, &args, &name_list(&names));
+        assert!(output.status.success());
+        let original = output.stdout;
+        let output = utcpio(&src, &["-tv"], &original);
+        let listing = String::from_utf8_lossy(&output.stdout);
+        assert!(listing.contains("136,   3"), "{}", listing);
+
+        let tree = dir.join("tree");
+        fs::create_dir(&tree).unwrap();
+        let output = utcpio(&tree, &["-idm", "--rootless"], &original);
+        assert!(output.status.success());
+        assert!(!String::from_utf8_lossy(&output.stderr).contains("not recorded"));
+        let tty = fs::symlink_metadata(tree.join("d/tty")).unwrap();
+        assert!(tty.is_file());
+        assert_eq!(tty.uid(), unsafe { libc::geteuid() });
+
+        let output = utcpio(&tree, &args, &name_list(&names));
+        assert!(output.status.success());
+        assert_eq!(output.stdout, original);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 提取时不改变符号链接的属主，属主不是当前用户时给出一次警告
+    #[test]
+    fn test_rootless_symlink_owner() {
+        let dir = work_dir("rootless-symlink");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        symlink("target", src.join("sl1")).unwrap();
+        symlink("target", src.join("sl2")).unwrap();
+        let output = utcpio(
+            &src,
+            &["-o", "-H", "newc", "-R", "4242:4242"],
+            &name_list(&["sl1", "sl2"]),
+        );
+        assert!(output.status.success());
+
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+        let output = utcpio(&dest, &["-i", "--rootless"], &output.stdout);
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(stderr.matches("not recorded").count(), 1, "{}", stderr);
+        for name in ["sl1", "sl2"] {
+            let st = fs::symlink_metadata(dest.join(name)).unwrap();
+            assert!(st.file_type().is_symlink());
+            assert_eq!(st.uid(), unsafe { libc::geteuid() });
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::{FileExt, MetadataExt};
+    use std::path::Path;
+    use std::process::Command;
+
+    use crate::common::*;
+
+    // 在 DIR 中建立有洞的文件 NAME，返回它的内容
+    fn sparse_file(dir: &Path, name: &str) -> Vec<u8> {
+        let file = fs::File::create(dir.join(name)).unwrap();
+        file.set_len(4 << 20).unwrap();
+        file.write_all_at(b"hello", 300_000).unwrap();
+        file.write_all_at(&[0x5a; 8192], 2 << 20).unwrap();
+        file.write_all_at(b"end", (4 << 20) - 3).unwrap();
+        fs::read(dir.join(name)).unwrap()
+    }
+
+    // 在 DIR 中运行 tar，没有 tar 时返回 false
+    fn tar(dir: &Path, args: &[&str]) -> bool {
+        match Command::new("tar").args(args).current_dir(dir).output() {
+            Ok(output) => {
+                assert!(
+                    output.status.success(),
+                    "{}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // --sparse 写出的 ustar 归档提取后内容不变，洞仍然是洞
+    #[test]
+    fn test_sparse_ustar_round_trip() {
+        let dir = work_dir("tar-sparse");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        let data = sparse_file(&src, "sparse");
+
+        let output = utcpio(&src, &["-o", "-H", "ustar", "--sparse"], b"sparse\n");
+        assert!(output.status.success());
+        let archive = output.stdout;
+        assert!(archive.len() < 64 * 1024);
+
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+        let output = utcpio(&dest, &["-i"], &archive);
+        assert!(output.status.success());
+        assert_eq!(fs::read(dest.join("sparse")).unwrap(), data);
+        let in_stat = fs::metadata(src.join("sparse")).unwrap();
+        if in_stat.blocks() * 512 < in_stat.len() {
+            let out_stat = fs::metadata(dest.join("sparse")).unwrap();
+            assert!(out_stat.blocks() <= in_stat.blocks() + 8);
+        }
+
+        // GNU tar 也能读出同样的内容
+        let gnu = dir.join("gnu");
+        fs::create_dir(&gnu).unwrap();
+        fs::write(dir.join("a.tar"), &archive).unwrap();
+        if tar(&gnu, &["-xf", "../a.tar"]) {
+            assert_eq!(fs::read(gnu.join("sparse")).unwrap(), data);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 读出 GNU tar 写的各种格式的稀疏文件
+    #[test]
+    fn test_read_gnu_tar_sparse() {
+        let dir = work_dir("tar-gnu");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        let data = sparse_file(&src, "sparse");
+
+        let formats: [&[&str]; 4] = [
+            &["--format=gnu"],
+            &["--format=posix", "--sparse-version=0.0"],
+            &["--format=posix", "--sparse-version=0.1"],
+            &["--format=posix", "--sparse-version=1.0"],
+        ];
+        for format in formats {
+            let file = dir.join("a.tar");
+            let mut args = vec!["--sparse", "-cf", file.to_str().unwrap()];
+            args.extend(format);
+            args.push("sparse");
+            if !tar(&src, &args) {
+                break;
+            }
+
+            let dest = dir.join("dest");
+            let _ = fs::remove_dir_all(&dest);
+            fs::create_dir(&dest).unwrap();
+            let output = utcpio(&dest, &["-i", "-H", "ustar"], &fs::read(&file).unwrap());
+            assert!(output.status.success(), "{:?}", format);
+            assert_eq!(tree_names(&dest), ["sparse"], "{:?}", format);
+            assert_eq!(fs::read(dest.join("sparse")).unwrap(), data, "{:?}", format);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}